@@ -0,0 +1,370 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use super::chain::{bearer_token, Authenticator};
+use super::cognito_auth::AuthError;
+use super::principal::Principal;
+use crate::config::AuthMethod;
+use crate::db::{OperationResult, TenantRepository};
+use crate::models::api_key::{ApiKey, ApiKeyDynamoDbRepository};
+use crate::tenant::TenantId;
+
+pub const API_KEY_PREFIX: &str = "ak";
+
+/// How stale `last_used_at` may get before a request writes it again, so a busy key does not
+/// cost a write per request.
+const LAST_USED_RESOLUTION_SECONDS: u64 = 60;
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+/// An API key as presented by callers: `ak.<tenant>.<id>.<secret>`, with the tenant base64url
+/// encoded. The tenant and id locate the stored key, so no lookup by hash is needed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyToken {
+    pub tenant_id: TenantId,
+    pub id: String,
+    pub secret: String,
+}
+
+impl ApiKeyToken {
+    pub fn generate(tenant_id: &TenantId) -> Self {
+        Self {
+            tenant_id: tenant_id.clone(),
+            id: Uuid::new_v4().simple().to_string(),
+            secret: generate_secret(),
+        }
+    }
+
+    /// A new secret for the same key, as handed out when the key is rotated.
+    pub fn rotated(tenant_id: &TenantId, id: &str) -> Self {
+        Self {
+            tenant_id: tenant_id.clone(),
+            id: id.to_string(),
+            secret: generate_secret(),
+        }
+    }
+
+    /// `None` for anything that is not shaped like an API key, e.g. a JWT or the legacy secret.
+    pub fn parse(token: &str) -> Option<Self> {
+        let mut parts = token.split('.');
+
+        match (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) {
+            (Some(API_KEY_PREFIX), Some(tenant), Some(id), Some(secret), None)
+                if !id.is_empty() && !secret.is_empty() =>
+            {
+                let tenant = String::from_utf8(URL_SAFE_NO_PAD.decode(tenant).ok()?).ok()?;
+
+                Some(Self {
+                    tenant_id: TenantId::new(tenant)?,
+                    id: id.to_string(),
+                    secret: secret.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn hash(&self) -> String {
+        hash_secret(&self.secret)
+    }
+}
+
+impl fmt::Display for ApiKeyToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            API_KEY_PREFIX,
+            URL_SAFE_NO_PAD.encode(self.tenant_id.as_str()),
+            self.id,
+            self.secret
+        )
+    }
+}
+
+fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    URL_SAFE_NO_PAD.encode(secret)
+}
+
+/// Secrets are 256 random bits, so a plain SHA-256 is enough; no password hashing is needed.
+pub fn hash_secret(secret: &str) -> String {
+    STANDARD.encode(Sha256::digest(secret.as_bytes()))
+}
+
+pub fn verify_secret(secret: &str, key_hash: &str) -> bool {
+    hash_secret(secret)
+        .as_bytes()
+        .ct_eq(key_hash.as_bytes())
+        .into()
+}
+
+/// Recorded as the principal of requests made with a key; the key record names its owner.
+pub fn api_key_subject(id: &str) -> String {
+    format!("api-key:{}", id)
+}
+
+/// Authenticates managed API keys against the keys table. Other bearer tokens are left to the
+/// rest of the chain, which may still accept the legacy shared secret.
+#[derive(Clone)]
+pub struct ApiKeyAuth {
+    keys: Arc<dyn TenantRepository<dyn ApiKeyDynamoDbRepository>>,
+}
+
+impl ApiKeyAuth {
+    pub fn new(keys: Arc<dyn TenantRepository<dyn ApiKeyDynamoDbRepository>>) -> Self {
+        Self { keys }
+    }
+}
+
+#[async_trait]
+impl Authenticator for ApiKeyAuth {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>, AuthError> {
+        let token = match bearer_token(headers).and_then(ApiKeyToken::parse) {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        let keys = self.keys.for_tenant(&token.tenant_id);
+
+        let key: ApiKey = match keys.get_item(token.id.clone()).await {
+            OperationResult::Success(Some(key)) => key,
+            OperationResult::Success(None) | OperationResult::ItemNotFound => {
+                return Err(AuthError::InvalidToken)
+            }
+            OperationResult::InternalError(err) => return Err(AuthError::VerificationFailed(err)),
+            _ => return Err(AuthError::InvalidToken),
+        };
+
+        if !verify_secret(&token.secret, &key.key_hash) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        let now = now();
+        if key.is_expired(now) {
+            return Err(AuthError::TokenExpired);
+        }
+
+        if key
+            .last_used_at
+            .is_none_or(|used_at| used_at + LAST_USED_RESOLUTION_SECONDS <= now)
+        {
+            if let OperationResult::InternalError(err) = keys.record_use(key.id.clone(), now).await
+            {
+                tracing::warn!("Failed to record use of API key {}: {}", key.id, err);
+            }
+        }
+
+        Ok(Some(Principal {
+            username: Some(key.owner),
+            scopes: key.scopes,
+            tenant_id: Some(token.tenant_id),
+            ..Principal::new(api_key_subject(&key.id), AuthMethod::ApiKey)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ScanFilter, ScanReport};
+    use mockall::mock;
+    use mockall::predicate::eq;
+
+    mock! {
+        pub ApiKeyRepository {}
+
+        #[async_trait]
+        impl crate::db::DynamoDbOperations<ApiKey> for ApiKeyRepository {
+            async fn get_item(&self, id: String) -> OperationResult<ApiKey>;
+            async fn create(&self, item: ApiKey) -> OperationResult<ApiKey>;
+            async fn update(&self, item: ApiKey) -> OperationResult<ApiKey>;
+            async fn delete(&self, id: String) -> OperationResult<ApiKey>;
+            async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<ApiKey>;
+            async fn scan(&self) -> OperationResult<Vec<ApiKey>>;
+            async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<ApiKey>>;
+            async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<ApiKey>>;
+            async fn get_deleted_items(&self) -> OperationResult<Vec<ApiKey>>;
+        }
+
+        #[async_trait]
+        impl ApiKeyDynamoDbRepository for ApiKeyRepository {
+            async fn replace_hash(&self, id: String, key_hash: String) -> OperationResult<ApiKey>;
+            async fn record_use(&self, id: String, used_at: u64) -> OperationResult<ApiKey>;
+        }
+    }
+
+    fn tenant() -> TenantId {
+        TenantId::new("acme").unwrap()
+    }
+
+    fn stored(token: &ApiKeyToken) -> ApiKey {
+        ApiKey {
+            id: token.id.clone(),
+            name: "ci".to_string(),
+            owner: "user123".to_string(),
+            key_hash: token.hash(),
+            scopes: vec!["items:read".to_string()],
+            created_at: now(),
+            expires_at: None,
+            last_used_at: None,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    fn auth(keys: MockApiKeyRepository) -> ApiKeyAuth {
+        let keys: Arc<dyn ApiKeyDynamoDbRepository> = Arc::new(keys);
+        ApiKeyAuth::new(Arc::new(move |tenant_id: &TenantId| {
+            assert_eq!(tenant_id.as_str(), "acme");
+            keys.clone()
+        }))
+    }
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", authorization.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_token_round_trip() {
+        let token = ApiKeyToken::generate(&TenantId::new("acme.eu").unwrap());
+
+        assert_eq!(ApiKeyToken::parse(&token.to_string()), Some(token));
+        assert_eq!(ApiKeyToken::parse("a.b.c"), None);
+        assert_eq!(ApiKeyToken::parse("Secret0190192091"), None);
+    }
+
+    #[test]
+    fn test_secret_verification() {
+        let token = ApiKeyToken::generate(&tenant());
+
+        assert!(verify_secret(&token.secret, &token.hash()));
+        assert!(!verify_secret("guess", &token.hash()));
+        assert!(!verify_secret(
+            &token.secret,
+            &ApiKeyToken::generate(&tenant()).hash()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_valid_key_authenticates_with_its_scopes() {
+        let token = ApiKeyToken::generate(&tenant());
+        let key = stored(&token);
+        let mut keys = MockApiKeyRepository::new();
+        keys.expect_get_item()
+            .with(eq(token.id.clone()))
+            .returning(move |_| OperationResult::Success(Some(key.clone())));
+        keys.expect_record_use()
+            .times(1)
+            .returning(|_, _| OperationResult::Success(None));
+
+        let principal = auth(keys)
+            .authenticate(&headers(&format!("Bearer {}", token)))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(principal.subject, api_key_subject(&token.id));
+        assert_eq!(principal.username.as_deref(), Some("user123"));
+        assert_eq!(principal.method, AuthMethod::ApiKey);
+        assert_eq!(principal.tenant_id, Some(tenant()));
+        assert_eq!(principal.scopes, vec!["items:read"]);
+    }
+
+    #[tokio::test]
+    async fn test_recent_use_is_not_rewritten() {
+        let token = ApiKeyToken::generate(&tenant());
+        let key = ApiKey {
+            last_used_at: Some(now()),
+            ..stored(&token)
+        };
+        let mut keys = MockApiKeyRepository::new();
+        keys.expect_get_item()
+            .returning(move |_| OperationResult::Success(Some(key.clone())));
+        keys.expect_record_use().never();
+
+        assert!(auth(keys)
+            .authenticate(&headers(&format!("Bearer {}", token)))
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_secret_revoked_and_expired_keys_are_rejected() {
+        let token = ApiKeyToken::generate(&tenant());
+        let wrong = ApiKeyToken {
+            secret: "guess".to_string(),
+            ..token.clone()
+        };
+        let expired = ApiKeyToken::generate(&tenant());
+        let expired_key = ApiKey {
+            expires_at: Some(now() - 1),
+            ..stored(&expired)
+        };
+        let key = stored(&token);
+        let expired_id = expired.id.clone();
+        let mut keys = MockApiKeyRepository::new();
+        keys.expect_get_item().returning(move |id| match id {
+            id if id == expired_id => OperationResult::Success(Some(expired_key.clone())),
+            id if id == key.id => OperationResult::Success(Some(key.clone())),
+            _ => OperationResult::ItemNotFound,
+        });
+        keys.expect_record_use().never();
+        let auth = auth(keys);
+
+        assert!(matches!(
+            auth.authenticate(&headers(&format!("Bearer {}", wrong)))
+                .await,
+            Err(AuthError::InvalidToken)
+        ));
+        assert!(matches!(
+            auth.authenticate(&headers(&format!("Bearer {}", expired)))
+                .await,
+            Err(AuthError::TokenExpired)
+        ));
+        assert!(matches!(
+            auth.authenticate(&headers(&format!(
+                "Bearer {}",
+                ApiKeyToken::generate(&tenant())
+            )))
+            .await,
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_other_tokens_are_left_to_the_chain() {
+        let mut keys = MockApiKeyRepository::new();
+        keys.expect_get_item().never();
+
+        assert!(auth(keys)
+            .authenticate(&headers("Bearer Secret0190192091"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
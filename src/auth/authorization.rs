@@ -0,0 +1,93 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+
+use super::principal::AuthUser;
+use super::roles::ADMIN_ROLE;
+use crate::db::OperationResult;
+use crate::state::Repositories;
+use crate::tenant::TenantId;
+
+pub const ITEMS_READ: &str = "items:read";
+pub const ITEMS_WRITE: &str = "items:write";
+
+fn forbidden(message: &str) -> Response {
+    (StatusCode::FORBIDDEN, Json(json!({ "error": message }))).into_response()
+}
+
+/// Scope a route requires of the caller's token (or of the shared secret's configured scopes).
+#[derive(Clone, Copy)]
+pub struct RequiredScope(pub &'static str);
+
+pub async fn require_scope(
+    State(RequiredScope(scope)): State<RequiredScope>,
+    AuthUser(principal): AuthUser,
+    request: Request,
+    next: Next,
+) -> Response {
+    if principal.has_scope(scope) {
+        next.run(request).await
+    } else {
+        forbidden(&format!("Missing scope {}", scope))
+    }
+}
+
+/// Lets the request through when the caller's token maps to the admin role, or when its `User`
+/// record, looked up in its tenant by the principal's subject, is an active admin.
+pub async fn require_admin(
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
+    AuthUser(principal): AuthUser,
+    request: Request,
+    next: Next,
+) -> Response {
+    if principal.has_role(ADMIN_ROLE) {
+        return next.run(request).await;
+    }
+
+    let users = repositories.users.for_tenant(&tenant);
+
+    match users.get_item(principal.subject).await {
+        OperationResult::Success(Some(user)) if user.admin && user.deleted_at.is_none() => {
+            next.run(request).await
+        }
+        OperationResult::Success(_) | OperationResult::ItemNotFound => {
+            forbidden("Admin role required")
+        }
+        err => err.into_response(),
+    }
+}
+
+/// Lets the request through when the caller's email is verified: by an `email_verified` claim
+/// of its token, as Cognito ID tokens carry, or else by its `User` record in its tenant.
+pub async fn require_verified_email(
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
+    AuthUser(principal): AuthUser,
+    request: Request,
+    next: Next,
+) -> Response {
+    let claimed = principal
+        .claims
+        .as_ref()
+        .and_then(|claims| claims.additional.get("email_verified"))
+        .is_some_and(|verified| *verified == json!(true) || *verified == json!("true"));
+    if claimed {
+        return next.run(request).await;
+    }
+
+    let users = repositories.users.for_tenant(&tenant);
+
+    match users.get_item(principal.subject).await {
+        OperationResult::Success(Some(user)) if user.email_verified => next.run(request).await,
+        OperationResult::Success(_) | OperationResult::ItemNotFound => {
+            forbidden("Verified email required")
+        }
+        err => err.into_response(),
+    }
+}
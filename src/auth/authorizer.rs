@@ -0,0 +1,394 @@
+use axum::extract::Request;
+use axum::http::Extensions;
+use axum::middleware::Next;
+use axum::response::Response;
+use lambda_http::aws_lambda_events::apigw::{
+    ApiGatewayCustomAuthorizerPolicy, ApiGatewayV2CustomAuthorizerIamPolicyResponse,
+    ApiGatewayV2CustomAuthorizerSimpleResponse, ApiGatewayV2CustomAuthorizerV2Request,
+};
+use lambda_http::aws_lambda_events::iam::{IamPolicyEffect, IamPolicyStatement};
+use lambda_http::request::RequestContext;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::api_key::ApiKeyAuth;
+use super::chain::{insert_principal, AuthChain, Authenticator};
+use super::cognito_auth::Auth;
+use super::principal::Principal;
+use super::secret_auth::SecretAuth;
+use crate::config::{AuthMethod, AuthorizerConfig, AuthorizerResponseFormat};
+use crate::db::{dynamodb_client, DynamoDbRepository, TenantRepository};
+use crate::models::api_key::{ApiKey, ApiKeyDynamoDbRepository};
+use crate::state::load_sdk_config;
+use crate::tenant::TenantId;
+
+/// Recorded as `principalId` of denied requests in IAM policy responses.
+const ANONYMOUS: &str = "anonymous";
+
+/// Flattens a principal into an authorizer context, which API Gateway hands to the
+/// integration as `requestContext.authorizer.lambda`. Lists are space separated.
+pub fn principal_context(principal: &Principal) -> HashMap<String, Value> {
+    let mut context = HashMap::from([
+        ("subject".to_string(), json!(principal.subject)),
+        ("method".to_string(), json!(principal.method.as_str())),
+        ("scopes".to_string(), json!(principal.scopes.join(" "))),
+        ("groups".to_string(), json!(principal.groups.join(" "))),
+        ("roles".to_string(), json!(principal.roles.join(" "))),
+    ]);
+    if let Some(tenant_id) = &principal.tenant_id {
+        context.insert("tenant_id".to_string(), json!(tenant_id.as_str()));
+    }
+    if let Some(username) = &principal.username {
+        context.insert("username".to_string(), json!(username));
+    }
+
+    context
+}
+
+/// The principal [`principal_context`] describes, if the context holds one.
+pub fn context_principal(context: &HashMap<String, Value>) -> Option<Principal> {
+    let string = |key: &str| context.get(key).and_then(Value::as_str);
+    let list = |key: &str| {
+        string(key)
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect()
+    };
+    let method = AuthMethod::parse(string("method")?)?;
+
+    Some(Principal {
+        username: string("username").map(str::to_string),
+        scopes: list("scopes"),
+        groups: list("groups"),
+        roles: list("roles"),
+        tenant_id: string("tenant_id").and_then(TenantId::new),
+        ..Principal::new(string("subject")?, method)
+    })
+}
+
+/// The principal a Lambda authorizer established for this request, as lambda_http passes on
+/// the HTTP API request context.
+pub fn authorizer_principal(extensions: &Extensions) -> Option<Principal> {
+    match extensions.get::<RequestContext>()? {
+        RequestContext::ApiGatewayV2(context) => {
+            context_principal(&context.authorizer.as_ref()?.fields)
+        }
+        _ => None,
+    }
+}
+
+/// Takes the caller from the authorizer context, for deployments where the HTTP API runs the
+/// `authorizer` binary in front of this function; requests without one go on to the
+/// `authenticate` chain. The context is set by API Gateway alone, so this is only sound when
+/// the function cannot be invoked other than through that API.
+pub async fn authenticate_authorizer(mut request: Request, next: Next) -> Response {
+    if let Some(principal) = authorizer_principal(request.extensions()) {
+        insert_principal(&mut request, principal);
+    }
+
+    next.run(request).await
+}
+
+/// Handles HTTP API Lambda authorizer events (payload format 2.0) with the same verification
+/// as the API's own middleware.
+#[derive(Clone)]
+pub struct Authorizer {
+    chain: AuthChain,
+    format: AuthorizerResponseFormat,
+}
+
+impl Authorizer {
+    pub fn new(chain: AuthChain, format: AuthorizerResponseFormat) -> Self {
+        Self { chain, format }
+    }
+
+    pub async fn from_config(config: &AuthorizerConfig) -> Self {
+        let api_keys: Option<Arc<dyn TenantRepository<dyn ApiKeyDynamoDbRepository>>> =
+            match &config.dynamodb_api_key_table_name {
+                Some(table_name) => {
+                    let sdk_config = load_sdk_config().await;
+                    Some(Arc::new(DynamoDbRepository::<ApiKey>::from_client(
+                        dynamodb_client(&sdk_config, config.dynamodb_endpoint.as_deref()),
+                        table_name.clone(),
+                    )))
+                }
+                None => None,
+            };
+
+        let chain = config
+            .auth_methods
+            .iter()
+            .fold(AuthChain::new(), |chain, method| {
+                let authenticator: Arc<dyn Authenticator> = match method {
+                    AuthMethod::Cognito => Arc::new(
+                        Auth::new(
+                            config.cognito_region.as_deref().unwrap(),
+                            config.cognito_user_pool_id.as_deref().unwrap(),
+                            config.cognito_client_id.as_deref().unwrap(),
+                        )
+                        .expect("Failed to initialize Cognito key set")
+                        .with_role_mappings(config.role_mappings.clone()),
+                    ),
+                    AuthMethod::ApiKey => Arc::new(ApiKeyAuth::new(
+                        api_keys.clone().expect("API_KEY_TABLE_NAME must be set"),
+                    )),
+                    AuthMethod::Secret => {
+                        let tenant_id = TenantId::new(config.secret_tenant_id.clone().unwrap())
+                            .expect("SECRET_TENANT_ID must not be empty or contain '#'");
+                        Arc::new(
+                            SecretAuth::new(config.secret.clone().unwrap(), tenant_id)
+                                .with_scopes(config.secret_scopes.clone())
+                                .with_roles(config.secret_roles.clone()),
+                        )
+                    }
+                    // `AuthorizerConfig` admits no other method.
+                    _ => return chain,
+                };
+                chain.with(authenticator)
+            });
+
+        Self::new(chain, config.response_format)
+    }
+
+    /// Invalid and missing credentials alike yield `None`.
+    pub async fn authorize(
+        &self,
+        request: &ApiGatewayV2CustomAuthorizerV2Request,
+    ) -> Option<Principal> {
+        match self.chain.authenticate(&request.headers).await {
+            Ok(principal) => principal,
+            Err(err) => {
+                tracing::info!(
+                    target: "security",
+                    event = "auth_failure",
+                    route = request.route_arn.as_deref().unwrap_or_default(),
+                    "Authorizer rejected credentials: {:?}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// The response in the configured format, ready to be serialized back to API Gateway.
+    pub async fn handle(&self, request: ApiGatewayV2CustomAuthorizerV2Request) -> Value {
+        let principal = self.authorize(&request).await;
+
+        match self.format {
+            AuthorizerResponseFormat::Simple => json!(simple_response(principal.as_ref())),
+            AuthorizerResponseFormat::Iam => json!(iam_policy_response(
+                principal.as_ref(),
+                request.route_arn.as_deref().unwrap_or_default()
+            )),
+        }
+    }
+}
+
+pub fn simple_response(
+    principal: Option<&Principal>,
+) -> ApiGatewayV2CustomAuthorizerSimpleResponse<HashMap<String, Value>> {
+    ApiGatewayV2CustomAuthorizerSimpleResponse {
+        is_authorized: principal.is_some(),
+        context: principal.map(principal_context).unwrap_or_default(),
+    }
+}
+
+/// Allows or denies every route of the stage rather than just `route_arn`, since API Gateway
+/// may cache the policy for the caller's other requests.
+pub fn iam_policy_response(
+    principal: Option<&Principal>,
+    route_arn: &str,
+) -> ApiGatewayV2CustomAuthorizerIamPolicyResponse<HashMap<String, Value>> {
+    let effect = match principal {
+        Some(_) => IamPolicyEffect::Allow,
+        None => IamPolicyEffect::Deny,
+    };
+
+    ApiGatewayV2CustomAuthorizerIamPolicyResponse {
+        principal_id: Some(
+            principal
+                .map(|principal| principal.subject.clone())
+                .unwrap_or_else(|| ANONYMOUS.to_string()),
+        ),
+        policy_document: ApiGatewayCustomAuthorizerPolicy {
+            version: Some("2012-10-17".to_string()),
+            statement: vec![IamPolicyStatement {
+                action: vec!["execute-api:Invoke".to_string()],
+                effect,
+                resource: vec![stage_arn(route_arn)],
+                condition: None,
+            }],
+        },
+        context: principal.map(principal_context).unwrap_or_default(),
+    }
+}
+
+/// `arn:aws:execute-api:<region>:<account>:<api>/<stage>/*` from the ARN of one route.
+fn stage_arn(route_arn: &str) -> String {
+    let mut parts = route_arn.splitn(3, '/');
+
+    match (parts.next(), parts.next()) {
+        (Some(api), Some(stage)) => format!("{}/{}/*", api, stage),
+        _ => route_arn.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::chain::authenticate;
+    use crate::auth::principal::AuthUser;
+    use axum::body::{to_bytes, Body};
+    use axum::middleware::{from_fn, from_fn_with_state};
+    use axum::routing::get;
+    use axum::Router;
+    use reqwest::StatusCode;
+    use tower::ServiceExt;
+
+    const SECRET_REQUEST: &str = include_str!("../../fixtures/authorizer/v2-request-secret.json");
+    const ANONYMOUS_REQUEST: &str =
+        include_str!("../../fixtures/authorizer/v2-request-anonymous.json");
+    const AUTHORIZED_EVENT: &str =
+        include_str!("../../fixtures/authorizer/http-request-authorized.json");
+
+    fn authorizer(format: AuthorizerResponseFormat) -> Authorizer {
+        let secret = SecretAuth::new("s3cret".to_string(), TenantId::new("acme").unwrap())
+            .with_scopes(vec!["items:read".to_string(), "items:write".to_string()]);
+
+        Authorizer::new(AuthChain::new().with(Arc::new(secret)), format)
+    }
+
+    fn request(fixture: &str) -> ApiGatewayV2CustomAuthorizerV2Request {
+        serde_json::from_str(fixture).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_simple_response_carries_principal_context() {
+        let response = authorizer(AuthorizerResponseFormat::Simple)
+            .handle(request(SECRET_REQUEST))
+            .await;
+
+        assert_eq!(
+            response,
+            json!({
+                "isAuthorized": true,
+                "context": {
+                    "subject": "secret",
+                    "method": "SECRET",
+                    "tenant_id": "acme",
+                    "scopes": "items:read items:write",
+                    "groups": "",
+                    "roles": "",
+                },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_and_invalid_credentials_are_denied() {
+        let authorizer = authorizer(AuthorizerResponseFormat::Simple);
+        let mut invalid = request(SECRET_REQUEST);
+        invalid
+            .headers
+            .insert("authorization", "Bearer guess".parse().unwrap());
+
+        for request in [request(ANONYMOUS_REQUEST), invalid] {
+            assert_eq!(
+                authorizer.handle(request).await,
+                json!({ "isAuthorized": false, "context": {} })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_iam_policy_covers_the_stage() {
+        let authorizer = authorizer(AuthorizerResponseFormat::Iam);
+
+        let allowed = authorizer.handle(request(SECRET_REQUEST)).await;
+        assert_eq!(allowed["principalId"], "secret");
+        assert_eq!(
+            allowed["policyDocument"],
+            json!({
+                "Version": "2012-10-17",
+                "Statement": [{
+                    "Action": ["execute-api:Invoke"],
+                    "Effect": "Allow",
+                    "Resource": ["arn:aws:execute-api:us-east-1:123456789012:abcdef123/$default/*"],
+                }],
+            })
+        );
+        assert_eq!(allowed["context"]["tenant_id"], "acme");
+
+        let denied = authorizer.handle(request(ANONYMOUS_REQUEST)).await;
+        assert_eq!(denied["principalId"], ANONYMOUS);
+        assert_eq!(denied["policyDocument"]["Statement"][0]["Effect"], "Deny");
+    }
+
+    #[test]
+    fn test_context_round_trip() {
+        let principal = Principal {
+            username: Some("jane".to_string()),
+            scopes: vec!["items:read".to_string()],
+            groups: vec!["admins".to_string(), "staff".to_string()],
+            roles: vec!["admin".to_string()],
+            tenant_id: TenantId::new("acme"),
+            ..Principal::new("user-1", AuthMethod::Cognito)
+        };
+
+        let decoded = context_principal(&principal_context(&principal)).unwrap();
+
+        assert_eq!(decoded.subject, "user-1");
+        assert_eq!(decoded.method, AuthMethod::Cognito);
+        assert_eq!(decoded.username.as_deref(), Some("jane"));
+        assert_eq!(decoded.scopes, principal.scopes);
+        assert_eq!(decoded.groups, principal.groups);
+        assert_eq!(decoded.roles, principal.roles);
+        assert_eq!(decoded.tenant_id, principal.tenant_id);
+        assert!(context_principal(&HashMap::new()).is_none());
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/foo",
+                get(|AuthUser(principal): AuthUser| async move {
+                    format!(
+                        "{}:{}:{}",
+                        principal.subject,
+                        principal.method.as_str(),
+                        principal.tenant_id.unwrap().as_str()
+                    )
+                }),
+            )
+            .route_layer(from_fn_with_state(AuthChain::new(), authenticate))
+            .route_layer(from_fn(authenticate_authorizer))
+    }
+
+    #[tokio::test]
+    async fn test_app_trusts_authorizer_context() {
+        let (parts, _) = lambda_http::request::from_str(AUTHORIZED_EVENT)
+            .unwrap()
+            .into_parts();
+
+        let response = app()
+            .oneshot(Request::from_parts(parts, Body::empty()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"user-1:COGNITO:acme");
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_authorizer_context_go_to_the_chain() {
+        let response = app()
+            .oneshot(Request::builder().uri("/foo").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,383 @@
+use async_trait::async_trait;
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use super::cognito_auth::AuthError;
+use super::lockout::{client_ip, ip_key, too_many_attempts, Lockout};
+use super::principal::{unauthenticated, AuthUser, Principal};
+use crate::config::AuthMethod;
+
+/// One way of authenticating a request. `Ok(None)` means the request carries no credentials
+/// this authenticator understands, so the chain moves on; an error means it does but they are
+/// invalid, which ends the chain.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>, AuthError>;
+}
+
+/// Authenticators tried in configured order; the first one to recognise the request decides.
+#[derive(Clone, Default)]
+pub struct AuthChain {
+    authenticators: Vec<Arc<dyn Authenticator>>,
+    lockout: Option<Lockout>,
+}
+
+impl AuthChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticators.push(authenticator);
+        self
+    }
+
+    /// Counts failed attempts per client IP and refuses clients locked out with 429.
+    pub fn with_lockout(mut self, lockout: Lockout) -> Self {
+        self.lockout = Some(lockout);
+        self
+    }
+
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>, AuthError> {
+        for authenticator in &self.authenticators {
+            if let Some(principal) = authenticator.authenticate(headers).await? {
+                return Ok(Some(principal));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Runs the chain for a request from `client_ip`, applying the lockout to that IP.
+    /// Credentials that are rejected or that no authenticator recognises count as a failure;
+    /// requests without credentials do not.
+    async fn authenticate_client(
+        &self,
+        headers: &HeaderMap,
+        client_ip: Option<String>,
+    ) -> Result<Option<Principal>, Response> {
+        let ip = client_ip
+            .filter(|_| self.lockout.is_some())
+            .map(|ip| ip_key(&ip));
+
+        if let (Some(lockout), Some(key)) = (&self.lockout, &ip) {
+            if let Some(retry_after) = lockout.retry_after(std::slice::from_ref(key)).await {
+                return Err(too_many_attempts(retry_after));
+            }
+        }
+
+        let result = self.authenticate(headers).await;
+        let failed = match &result {
+            Ok(Some(_)) => false,
+            Ok(None) => headers.contains_key(AUTHORIZATION),
+            Err(_) => true,
+        };
+
+        if let (true, Some(lockout), Some(key)) = (failed, &self.lockout, &ip) {
+            tracing::info!(
+                target: "security",
+                event = "auth_failure",
+                key = %key,
+                "Failed authentication attempt"
+            );
+            lockout.record_failure(key).await;
+        }
+
+        result.map_err(IntoResponse::into_response)
+    }
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Reads a claim from a JWT without verifying it, e.g. so JWT authenticators only claim tokens
+/// of their own issuer and several of them can share a chain. Not a trust decision.
+pub fn unverified_claim(token: &str, claim: &str) -> Option<Value> {
+    let mut segments = token.split('.');
+    let payload = match (
+        segments.next(),
+        segments.next(),
+        segments.next(),
+        segments.next(),
+    ) {
+        (Some(_), Some(payload), Some(_), None) => payload,
+        _ => return None,
+    };
+    let payload: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+
+    payload.get(claim).cloned()
+}
+
+pub fn unverified_issuer(token: &str) -> Option<String> {
+    unverified_claim(token, "iss")?.as_str().map(str::to_string)
+}
+
+pub(crate) fn insert_principal(request: &mut Request, principal: Principal) {
+    if let Some(tenant_id) = principal.tenant_id.clone() {
+        request.extensions_mut().insert(tenant_id);
+    }
+    if let Some(claims) = principal.claims.clone() {
+        request.extensions_mut().insert(claims);
+    }
+    request.extensions_mut().insert(principal);
+}
+
+/// Runs the chain and exposes the caller to handlers as `AuthUser`, plus `TenantId` and
+/// `Claims` when it has them. Requests no authenticator recognises are rejected. A caller
+/// already authenticated by an outer layer, such as `authenticate_signed`, is kept.
+pub async fn authenticate(
+    State(chain): State<AuthChain>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.extensions().get::<Principal>().is_some() {
+        return next.run(request).await;
+    }
+    let client_ip = client_ip(request.extensions());
+
+    match chain
+        .authenticate_client(request.headers(), client_ip)
+        .await
+    {
+        Ok(Some(principal)) => {
+            insert_principal(&mut request, principal);
+            next.run(request).await
+        }
+        Ok(None) => unauthenticated(),
+        Err(response) => response,
+    }
+}
+
+/// Like `authenticate`, but lets anonymous requests through for routes that take a
+/// `MaybeAuthUser`. Invalid credentials are still rejected.
+pub async fn authenticate_optional(
+    State(chain): State<AuthChain>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.extensions().get::<Principal>().is_some() {
+        return next.run(request).await;
+    }
+    let client_ip = client_ip(request.extensions());
+
+    match chain
+        .authenticate_client(request.headers(), client_ip)
+        .await
+    {
+        Ok(Some(principal)) => {
+            insert_principal(&mut request, principal);
+            next.run(request).await
+        }
+        Ok(None) => next.run(request).await,
+        Err(response) => response,
+    }
+}
+
+/// Methods a route accepts, for routes that must not be reachable with every configured
+/// method:
+///
+/// ```ignore
+/// get(handler).route_layer(from_fn_with_state(
+///     AcceptedMethods::only(&[AuthMethod::Cognito]),
+///     require_auth_method,
+/// ))
+/// ```
+#[derive(Clone, Copy)]
+pub struct AcceptedMethods(&'static [AuthMethod]);
+
+impl AcceptedMethods {
+    pub const fn only(methods: &'static [AuthMethod]) -> Self {
+        Self(methods)
+    }
+}
+
+pub async fn require_auth_method(
+    State(accepted): State<AcceptedMethods>,
+    AuthUser(principal): AuthUser,
+    request: Request,
+    next: Next,
+) -> Response {
+    if accepted.0.contains(&principal.method) {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "Authentication method not accepted for this route" })),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use axum::Router;
+    use lambda_http::aws_lambda_events::apigw::{
+        ApiGatewayV2httpRequestContext, ApiGatewayV2httpRequestContextHttpDescription,
+    };
+    use lambda_http::request::RequestContext;
+    use tower::ServiceExt;
+
+    use crate::auth::lockout::LockoutPolicy;
+    use crate::rate_limit::InMemoryRateLimitStore;
+
+    /// Recognises one bearer token, rejects tokens starting with `bad`, ignores the rest.
+    struct StaticAuthenticator {
+        token: &'static str,
+        method: AuthMethod,
+    }
+
+    #[async_trait]
+    impl Authenticator for StaticAuthenticator {
+        async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>, AuthError> {
+            match bearer_token(headers) {
+                Some(token) if token == self.token => {
+                    Ok(Some(Principal::new(self.token, self.method)))
+                }
+                Some(token) if token.starts_with("bad") => Err(AuthError::InvalidSignature),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    fn chain() -> AuthChain {
+        AuthChain::new()
+            .with(Arc::new(StaticAuthenticator {
+                token: "secret",
+                method: AuthMethod::Secret,
+            }))
+            .with(Arc::new(StaticAuthenticator {
+                token: "jwt",
+                method: AuthMethod::Cognito,
+            }))
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|AuthUser(principal): AuthUser| async move { principal.subject }),
+            )
+            .route(
+                "/browser-only",
+                get(|AuthUser(principal): AuthUser| async move { principal.subject }).route_layer(
+                    from_fn_with_state(
+                        AcceptedMethods::only(&[AuthMethod::Cognito]),
+                        require_auth_method,
+                    ),
+                ),
+            )
+            .route_layer(from_fn_with_state(chain(), authenticate))
+    }
+
+    async fn call(uri: &str, token: Option<&str>) -> (StatusCode, String) {
+        let mut request = Request::builder().uri(uri);
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+
+        let response = app()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_each_configured_method_is_accepted() {
+        assert_eq!(
+            call("/", Some("secret")).await,
+            (StatusCode::OK, "secret".to_string())
+        );
+        assert_eq!(
+            call("/", Some("jwt")).await,
+            (StatusCode::OK, "jwt".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unrecognised_or_missing_credentials_are_unauthorized() {
+        assert_eq!(call("/", Some("other")).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(call("/", None).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_credentials_end_the_chain() {
+        assert_eq!(call("/", Some("bad-jwt")).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_failures_lock_out_the_client_ip() {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|AuthUser(principal): AuthUser| async move { principal.subject }),
+            )
+            .route_layer(from_fn_with_state(
+                chain().with_lockout(Lockout::new(
+                    Arc::new(InMemoryRateLimitStore::new()),
+                    LockoutPolicy {
+                        threshold: 2,
+                        ..LockoutPolicy::default()
+                    },
+                )),
+                authenticate,
+            ));
+        let call = |ip: &str, token: &str| {
+            let context = ApiGatewayV2httpRequestContext {
+                http: ApiGatewayV2httpRequestContextHttpDescription {
+                    source_ip: Some(ip.to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let request = Request::builder()
+                .uri("/")
+                .header("Authorization", format!("Bearer {}", token))
+                .extension(RequestContext::ApiGatewayV2(context))
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(call("203.0.113.7", "bad").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(call("203.0.113.7", "other").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            call("203.0.113.7", "secret").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(call("203.0.113.8", "secret").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_route_rejects_methods_it_does_not_accept() {
+        assert_eq!(call("/browser-only", Some("jwt")).await.0, StatusCode::OK);
+        assert_eq!(
+            call("/browser-only", Some("secret")).await.0,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
@@ -1,33 +1,64 @@
 use async_trait::async_trait;
-use axum::{response::IntoResponse, Json};
+use axum::{http::HeaderMap, response::IntoResponse, Json};
 use jsonwebtokens_cognito::{Error as JwtError, KeySet};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
 
 #[cfg(test)]
 use mockall::automock;
 use serde_json::json;
 
+use super::chain::{bearer_token, unverified_claim, unverified_issuer, Authenticator};
+use super::principal::Principal;
+use super::roles::RoleMappings;
+
+/// Claims of a Cognito access or ID token. Only the core fields are required; anything not
+/// modelled here is kept in `additional` under its raw claim name.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Claims {
-    pub sub: String,        // Subject identifier (unique user ID)
-    pub exp: usize,         // Expiration time (Unix timestamp)
-    pub client_id: String,  // ID of the client application
-    pub scope: String,      // Permissions granted to the token
-    pub token_use: String,  // Type of token (e.g., "access")
-    pub username: String,   // Username (often same as sub)
-    pub auth_time: usize,   // Time of authentication (Unix timestamp)
-    pub iss: String,        // Issuer (Cognito user pool URL)
-    pub iat: usize,         // Issued at time (Unix timestamp)
-    pub jti: String,        // JWT ID (unique identifier for this token)
-    pub origin_jti: String, // Original JWT ID
-    pub event_id: String,   // Unique identifier for the authentication event
+    pub sub: String,       // Subject identifier (unique user ID)
+    pub exp: usize,        // Expiration time (Unix timestamp)
+    pub iss: String,       // Issuer (Cognito user pool URL)
+    pub iat: usize,        // Issued at time (Unix timestamp)
+    pub token_use: String, // Type of token ("access" or "id")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>, // ID of the client application (access tokens)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>, // ID of the client application (ID tokens)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>, // Permissions granted to the token (access tokens)
+    #[serde(
+        default,
+        alias = "cognito:username",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub username: Option<String>, // Username (often same as sub)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>, // Email address (ID tokens)
+    #[serde(
+        rename = "cognito:groups",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub groups: Vec<String>, // User pool groups the user belongs to
+    #[serde(
+        rename = "custom:tenant_id",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tenant_id: Option<String>, // Tenant the user belongs to
+    #[serde(flatten)]
+    pub additional: HashMap<String, Value>, // Every other claim, e.g. custom attributes
 }
 
 #[derive(Clone, Debug)]
 pub struct Auth {
     keyset: KeySet,
     client_id: String,
+    issuer: String,
+    role_mappings: RoleMappings,
 }
 
 #[derive(Debug)]
@@ -83,20 +114,34 @@ impl Auth {
             Ok(keyset) => Ok(Self {
                 keyset,
                 client_id: client_id.to_string(),
+                issuer: format!(
+                    "https://cognito-idp.{}.amazonaws.com/{}",
+                    region, user_pool_id
+                ),
+                role_mappings: RoleMappings::default(),
             }),
             Err(err) => Err(err),
         }
     }
+
+    pub fn with_role_mappings(mut self, role_mappings: RoleMappings) -> Self {
+        self.role_mappings = role_mappings;
+        self
+    }
 }
 
 #[async_trait]
 impl AuthOperations for Auth {
     async fn verify_token(&self, token: &str) -> Result<Claims, AuthError> {
-        match self
-            .keyset
-            .new_access_token_verifier(&[&self.client_id])
-            .build()
-        {
+        // The verifier checks `token_use` itself, so peeking at it only picks which one to run.
+        let verifier = match unverified_claim(token, "token_use") {
+            Some(Value::String(token_use)) if token_use == "id" => {
+                self.keyset.new_id_token_verifier(&[&self.client_id])
+            }
+            _ => self.keyset.new_access_token_verifier(&[&self.client_id]),
+        };
+
+        match verifier.build() {
             Ok(verifier) => match self.keyset.verify(token, &verifier).await {
                 Ok(claims) => match serde_json::from_value(claims) {
                     Ok(claims) => Ok(claims),
@@ -113,26 +158,65 @@ impl AuthOperations for Auth {
     }
 }
 
+/// Verifies bearer JWTs from `issuer`; anything else is left to the rest of the chain.
+pub async fn authenticate_bearer_jwt<A>(
+    auth: &A,
+    issuer: &str,
+    role_mappings: &RoleMappings,
+    headers: &HeaderMap,
+) -> Result<Option<Principal>, AuthError>
+where
+    A: AuthOperations + Sync,
+{
+    let token = match bearer_token(headers) {
+        Some(token) if unverified_issuer(token).as_deref() == Some(issuer) => token,
+        _ => return Ok(None),
+    };
+
+    let claims = auth.verify_token(token).await?;
+    let raw_claims =
+        serde_json::to_value(&claims).map_err(|err| AuthError::ConversionError(err.to_string()))?;
+
+    Ok(Some(Principal {
+        roles: role_mappings.roles_for(&raw_claims),
+        ..claims.into()
+    }))
+}
+
+#[async_trait]
+impl Authenticator for Auth {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>, AuthError> {
+        authenticate_bearer_jwt(self, &self.issuer, &self.role_mappings, headers).await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::AuthMethod;
+    use crate::tenant::TenantId;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
     use mockall::predicate::*;
 
     fn create_mock_claims() -> Claims {
-        Claims {
-            sub: "user123".to_string(),
-            exp: 1625097600,
-            client_id: "test_client".to_string(),
-            scope: "openid profile".to_string(),
-            token_use: "access".to_string(),
-            username: "testuser".to_string(),
-            auth_time: 1625011200,
-            iss: "https://cognito-idp.us-east-1.amazonaws.com/us-east-1_example".to_string(),
-            iat: 1625011200,
-            jti: "example-jti".to_string(),
-            origin_jti: "example-origin-jti".to_string(),
-            event_id: "example-event-id".to_string(),
-        }
+        serde_json::from_value(json!({
+            "sub": "user123",
+            "exp": 1625097600,
+            "client_id": "test_client",
+            "scope": "openid profile",
+            "token_use": "access",
+            "username": "testuser",
+            "auth_time": 1625011200,
+            "iss": "https://cognito-idp.us-east-1.amazonaws.com/us-east-1_example",
+            "iat": 1625011200,
+            "jti": "example-jti",
+            "origin_jti": "example-origin-jti",
+            "event_id": "example-event-id",
+            "cognito:groups": ["admins"],
+            "custom:tenant_id": "example-tenant",
+        }))
+        .unwrap()
     }
 
     #[tokio::test]
@@ -147,8 +231,8 @@ mod tests {
         let result = mock.verify_token("valid_token").await.unwrap();
 
         assert_eq!(result.sub, "user123");
-        assert_eq!(result.username, "testuser");
-        assert_eq!(result.client_id, "test_client");
+        assert_eq!(result.username.as_deref(), Some("testuser"));
+        assert_eq!(result.client_id.as_deref(), Some("test_client"));
     }
 
     #[tokio::test]
@@ -192,4 +276,141 @@ mod tests {
 
         assert!(matches!(result, Err(AuthError::ConversionError(_))));
     }
+
+    const ISSUER: &str = "https://cognito-idp.us-east-1.amazonaws.com/us-east-1_example";
+
+    /// Unsigned token carrying only an issuer; verification itself is mocked.
+    fn bearer(issuer: &str) -> (String, HeaderMap) {
+        let token = format!(
+            "e30.{}.signature",
+            URL_SAFE_NO_PAD.encode(json!({ "iss": issuer }).to_string())
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+
+        (token, headers)
+    }
+
+    #[tokio::test]
+    async fn test_jwt_claims_become_principal() {
+        let mut mock = MockAuthOperations::new();
+        let (token, headers) = bearer(ISSUER);
+
+        mock.expect_verify_token()
+            .with(eq(token))
+            .times(1)
+            .returning(move |_| Ok(create_mock_claims()));
+
+        let principal = authenticate_bearer_jwt(&mock, ISSUER, &RoleMappings::default(), &headers)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(principal.subject, "user123");
+        assert_eq!(principal.method, AuthMethod::Cognito);
+        assert_eq!(principal.tenant_id, TenantId::new("example-tenant"));
+        assert_eq!(principal.username.as_deref(), Some("testuser"));
+        assert_eq!(principal.scopes, vec!["openid", "profile"]);
+        assert_eq!(principal.groups, vec!["admins"]);
+        assert!(principal.roles.is_empty());
+        assert!(principal.claims.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_groups_and_custom_claims_map_to_roles() {
+        let mut mock = MockAuthOperations::new();
+        let (_, headers) = bearer(ISSUER);
+        mock.expect_verify_token().returning(|_| {
+            let mut claims = create_mock_claims();
+            claims
+                .additional
+                .insert("custom:plan".to_string(), json!("pro"));
+            Ok(claims)
+        });
+        let mappings =
+            RoleMappings::parse("cognito:groups=admins->admin,custom:plan=pro->editor").unwrap();
+
+        let principal = authenticate_bearer_jwt(&mock, ISSUER, &mappings, &headers)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(principal.roles, vec!["admin", "editor"]);
+    }
+
+    #[test]
+    fn test_id_token_claims_parse() {
+        let claims: Claims = serde_json::from_value(json!({
+            "sub": "user123",
+            "aud": "test_client",
+            "token_use": "id",
+            "cognito:username": "testuser",
+            "email": "user@example.com",
+            "email_verified": true,
+            "exp": 1625097600,
+            "iat": 1625011200,
+            "iss": ISSUER,
+            "custom:department": "finance",
+        }))
+        .unwrap();
+
+        assert_eq!(claims.username.as_deref(), Some("testuser"));
+        assert_eq!(claims.aud.as_deref(), Some("test_client"));
+        assert_eq!(claims.email.as_deref(), Some("user@example.com"));
+        assert_eq!(claims.client_id, None);
+        assert_eq!(claims.additional["custom:department"], json!("finance"));
+
+        let principal = Principal::from(claims);
+        assert_eq!(principal.username.as_deref(), Some("testuser"));
+        assert!(principal.scopes.is_empty());
+    }
+
+    #[test]
+    fn test_access_token_without_optional_claims_parses() {
+        let claims: Claims = serde_json::from_value(json!({
+            "sub": "user123",
+            "client_id": "test_client",
+            "token_use": "access",
+            "exp": 1625097600,
+            "iat": 1625011200,
+            "iss": ISSUER,
+        }))
+        .unwrap();
+
+        assert_eq!(claims.scope, None);
+        assert!(claims.groups.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_other_bearer_tokens_are_left_to_the_chain() {
+        let mut mock = MockAuthOperations::new();
+        mock.expect_verify_token().never();
+
+        let mut secret = HeaderMap::new();
+        secret.insert("Authorization", "Bearer s3cret".parse().unwrap());
+        let (_, other_issuer) = bearer("https://issuer.example.com/");
+
+        for headers in [secret, other_issuer] {
+            let result =
+                authenticate_bearer_jwt(&mock, ISSUER, &RoleMappings::default(), &headers).await;
+
+            assert!(matches!(result, Ok(None)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_jwt_ends_the_chain() {
+        let mut mock = MockAuthOperations::new();
+        mock.expect_verify_token()
+            .returning(|_| Err(AuthError::InvalidSignature));
+
+        let (_, headers) = bearer(ISSUER);
+        let result =
+            authenticate_bearer_jwt(&mock, ISSUER, &RoleMappings::default(), &headers).await;
+
+        assert!(matches!(result, Err(AuthError::InvalidSignature)));
+    }
 }
@@ -0,0 +1,247 @@
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header::RETRY_AFTER, Extensions, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use lambda_http::request::RequestContext;
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::api_key::hash_secret;
+use crate::rate_limit::LockoutStore;
+use crate::tenant::TenantId;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+/// After `threshold` failures less than `window` apart, each further failure locks the client
+/// or account out for `base`, doubling with every failure up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    pub threshold: u64,
+    pub window: Duration,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            window: Duration::from_secs(900),
+            base: Duration::from_secs(60),
+            max: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// How long the `failures`-th consecutive failure locks out for, if at all.
+    pub fn lock_duration(&self, failures: u64) -> Option<Duration> {
+        let excess = failures.checked_sub(self.threshold)?;
+        let factor = 2u32.checked_pow(excess.min(31) as u32).unwrap_or(u32::MAX);
+
+        Some(self.base.saturating_mul(factor).min(self.max))
+    }
+}
+
+/// Tracks failed authentication per client IP and per account. Store errors are logged and
+/// the request let through, so an outage of the table does not lock everyone out.
+#[derive(Clone)]
+pub struct Lockout {
+    store: Arc<dyn LockoutStore>,
+    policy: LockoutPolicy,
+}
+
+impl Lockout {
+    pub fn new(store: Arc<dyn LockoutStore>, policy: LockoutPolicy) -> Self {
+        Self { store, policy }
+    }
+
+    /// How long until the longest running lockout among `keys` ends, if any is locked out.
+    pub async fn retry_after(&self, keys: &[String]) -> Option<Duration> {
+        let now = now();
+        let mut retry_after = None;
+
+        for key in keys {
+            match self.store.locked_until(key).await {
+                Ok(Some(until)) if until > now => {
+                    let remaining = Duration::from_secs(until - now);
+                    tracing::warn!(
+                        target: "security",
+                        event = "auth_locked_out",
+                        key = %key,
+                        retry_after_seconds = remaining.as_secs(),
+                        "Rejected attempt from a locked out client or account"
+                    );
+                    retry_after = retry_after.max(Some(remaining));
+                }
+                Ok(_) => {}
+                Err(err) => tracing::error!("Failed to read lockout of {}: {}", key, err),
+            }
+        }
+
+        retry_after
+    }
+
+    pub async fn record_failure(&self, key: &str) {
+        let now = now();
+        let failures = match self
+            .store
+            .record_failure(key, now + self.policy.window.as_secs())
+            .await
+        {
+            Ok(failures) => failures,
+            Err(err) => {
+                tracing::error!("Failed to record failed attempt of {}: {}", key, err);
+                return;
+            }
+        };
+
+        if let Some(duration) = self.policy.lock_duration(failures) {
+            let until = now + duration.as_secs();
+            // The count outlives the lockout, so the next failure after it escalates.
+            let expires_at = until + self.policy.window.as_secs();
+
+            if let Err(err) = self.store.lock(key, until, expires_at).await {
+                tracing::error!("Failed to lock out {}: {}", key, err);
+                return;
+            }
+            tracing::warn!(
+                target: "security",
+                event = "auth_lockout",
+                key = %key,
+                failures,
+                lock_seconds = duration.as_secs(),
+                "Locked out after repeated authentication failures"
+            );
+        }
+    }
+
+    pub async fn clear(&self, key: &str) {
+        if let Err(err) = self.store.clear(key).await {
+            tracing::error!("Failed to clear lockout of {}: {}", key, err);
+        }
+    }
+}
+
+pub fn ip_key(ip: &str) -> String {
+    format!("lockout#ip#{}", ip)
+}
+
+/// Keyed by a hash of the identifier, so emails are not stored in the clear.
+pub fn account_key(tenant_id: &TenantId, account: &str) -> String {
+    format!(
+        "lockout#account#{}",
+        hash_secret(&format!("{}#{}", tenant_id.as_str(), account))
+    )
+}
+
+pub fn too_many_attempts(retry_after: Duration) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after.as_secs().max(1).to_string())],
+        Json(json!({ "error": "Too many failed attempts, try again later" })),
+    )
+        .into_response()
+}
+
+/// The caller's IP as API Gateway saw it. Headers such as `X-Forwarded-For` are not trusted,
+/// since clients can set them.
+pub fn client_ip(extensions: &Extensions) -> Option<String> {
+    match extensions.get::<RequestContext>()? {
+        RequestContext::ApiGatewayV2(context) => context.http.source_ip.clone(),
+        RequestContext::ApiGatewayV1(context) => context.identity.source_ip.clone(),
+        _ => None,
+    }
+}
+
+/// [`client_ip`] as an extractor; `None` outside API Gateway, e.g. in local runs.
+pub struct ClientIp(pub Option<String>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(client_ip(&parts.extensions)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::InMemoryRateLimitStore;
+
+    fn lockout() -> Lockout {
+        Lockout::new(
+            Arc::new(InMemoryRateLimitStore::new()),
+            LockoutPolicy {
+                threshold: 3,
+                ..LockoutPolicy::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_lock_duration_doubles_up_to_max() {
+        let policy = LockoutPolicy::default();
+
+        assert_eq!(policy.lock_duration(4), None);
+        assert_eq!(policy.lock_duration(5), Some(Duration::from_secs(60)));
+        assert_eq!(policy.lock_duration(6), Some(Duration::from_secs(120)));
+        assert_eq!(policy.lock_duration(8), Some(Duration::from_secs(480)));
+        assert_eq!(policy.lock_duration(40), Some(Duration::from_secs(3600)));
+    }
+
+    #[tokio::test]
+    async fn test_locks_out_after_threshold() {
+        let lockout = lockout();
+        let key = ip_key("203.0.113.7");
+
+        lockout.record_failure(&key).await;
+        lockout.record_failure(&key).await;
+        assert_eq!(lockout.retry_after(std::slice::from_ref(&key)).await, None);
+
+        lockout.record_failure(&key).await;
+        let retry_after = lockout
+            .retry_after(std::slice::from_ref(&key))
+            .await
+            .unwrap();
+        assert!(retry_after > Duration::from_secs(55) && retry_after <= Duration::from_secs(60));
+        assert_eq!(lockout.retry_after(&[ip_key("203.0.113.8")]).await, None);
+
+        lockout.clear(&key).await;
+        assert_eq!(lockout.retry_after(&[key]).await, None);
+    }
+
+    #[test]
+    fn test_account_key_hides_identifier() {
+        let tenant = TenantId::new("acme").unwrap();
+        let key = account_key(&tenant, "jane@example.com");
+
+        assert!(!key.contains("jane"));
+        assert_ne!(
+            key,
+            account_key(&TenantId::new("other").unwrap(), "jane@example.com")
+        );
+    }
+
+    #[test]
+    fn test_retry_after_header() {
+        let response = too_many_attempts(Duration::from_secs(90));
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "90");
+    }
+}
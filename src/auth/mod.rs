@@ -1,3 +1,15 @@
+pub mod api_key;
+pub mod authorization;
+pub mod authorizer;
+pub mod chain;
 pub mod cognito_auth;
-pub mod cognito_middleware;
-pub mod secret_auth_middleware;
+pub mod lockout;
+pub mod oidc;
+pub mod password;
+pub mod principal;
+pub mod roles;
+pub mod secret_auth;
+pub mod sessions;
+pub mod signing;
+pub mod tokens;
+pub mod verification;
@@ -0,0 +1,164 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use once_cell::sync::Lazy;
+
+/// Compared against when a login names no known user, so unknown and known emails take
+/// equally long to reject.
+static DUMMY_HASH: Lazy<String> =
+    Lazy::new(|| hash_password("dummy password").expect("Failed to hash dummy password"));
+
+/// Argon2id with the crate's default parameters, as a PHC string that records them, so
+/// hashes stay verifiable if the defaults change.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    Argon2::default()
+        .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+        .map(|hash| hash.to_string())
+        .map_err(|err| err.to_string())
+}
+
+/// `None` stands for a user without a password (e.g. one that signs in through Cognito),
+/// which is checked against a dummy hash and always fails.
+pub fn verify_password(password: &str, password_hash: Option<&str>) -> bool {
+    let known = password_hash.is_some();
+    let hash = match PasswordHash::new(password_hash.unwrap_or(&DUMMY_HASH)) {
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+        && known
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharacterClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl CharacterClass {
+    fn parse(class: &str) -> Option<Self> {
+        match class {
+            "LOWERCASE" => Some(Self::Lowercase),
+            "UPPERCASE" => Some(Self::Uppercase),
+            "DIGIT" => Some(Self::Digit),
+            "SYMBOL" => Some(Self::Symbol),
+            _ => None,
+        }
+    }
+
+    fn matches(self, c: char) -> bool {
+        match self {
+            Self::Lowercase => c.is_lowercase(),
+            Self::Uppercase => c.is_uppercase(),
+            Self::Digit => c.is_ascii_digit(),
+            Self::Symbol => !c.is_alphanumeric() && !c.is_whitespace(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Lowercase => "a lowercase letter",
+            Self::Uppercase => "an uppercase letter",
+            Self::Digit => "a digit",
+            Self::Symbol => "a symbol",
+        }
+    }
+}
+
+/// Rules new passwords must meet. Lengths count characters, not bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub required_classes: Vec<CharacterClass>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            max_length: 128,
+            required_classes: Vec::new(),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Parses a comma separated list such as `LOWERCASE,UPPERCASE,DIGIT,SYMBOL`.
+    pub fn parse_classes(classes: &str) -> Result<Vec<CharacterClass>, String> {
+        classes
+            .split(',')
+            .map(str::trim)
+            .filter(|class| !class.is_empty())
+            .map(|class| {
+                CharacterClass::parse(class).ok_or_else(|| format!("Unknown class {}", class))
+            })
+            .collect()
+    }
+
+    /// Every rule the password breaks, as messages for the caller; empty if it is acceptable.
+    pub fn violations(&self, password: &str) -> Vec<String> {
+        let length = password.chars().count();
+        let mut violations = Vec::new();
+
+        if length < self.min_length {
+            violations.push(format!(
+                "Must be at least {} characters long",
+                self.min_length
+            ));
+        }
+        if length > self.max_length {
+            violations.push(format!(
+                "Must be at most {} characters long",
+                self.max_length
+            ));
+        }
+        for class in &self.required_classes {
+            if !password.chars().any(|c| class.matches(c)) {
+                violations.push(format!("Must contain {}", class.name()));
+            }
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_round_trip() {
+        let hash = hash_password("correct horse battery staple").unwrap();
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse battery staple", Some(&hash)));
+        assert!(!verify_password("wrong password", Some(&hash)));
+        assert!(!verify_password("dummy password", None));
+        assert!(!verify_password("anything", Some("not a hash")));
+    }
+
+    #[test]
+    fn test_policy_reports_every_violation() {
+        let policy = PasswordPolicy {
+            required_classes: PasswordPolicy::parse_classes("UPPERCASE,DIGIT").unwrap(),
+            ..PasswordPolicy::default()
+        };
+
+        assert_eq!(
+            policy.violations("short"),
+            vec![
+                "Must be at least 12 characters long",
+                "Must contain an uppercase letter",
+                "Must contain a digit",
+            ]
+        );
+        assert!(policy.violations("Long enough passw0rd").is_empty());
+        assert!(PasswordPolicy::parse_classes("EMOJI").is_err());
+    }
+}
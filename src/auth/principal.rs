@@ -0,0 +1,187 @@
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use reqwest::StatusCode;
+use serde_json::json;
+
+use super::cognito_auth::Claims;
+use crate::config::AuthMethod;
+use crate::tenant::TenantId;
+
+/// The authenticated caller, normalized across authenticators. `subject` is recorded on the
+/// records a request changes (e.g. `deleted_by`).
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub subject: String,
+    pub username: Option<String>,
+    pub scopes: Vec<String>,
+    pub groups: Vec<String>,
+    pub roles: Vec<String>,
+    pub method: AuthMethod,
+    pub tenant_id: Option<TenantId>,
+    pub claims: Option<Claims>,
+}
+
+impl Principal {
+    pub fn new(subject: impl Into<String>, method: AuthMethod) -> Self {
+        Self {
+            subject: subject.into(),
+            username: None,
+            scopes: Vec::new(),
+            groups: Vec::new(),
+            roles: Vec::new(),
+            method,
+            tenant_id: None,
+            claims: None,
+        }
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| granted == scope)
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|granted| granted == role)
+    }
+}
+
+impl From<Claims> for Principal {
+    fn from(claims: Claims) -> Self {
+        Self {
+            username: claims.username.clone(),
+            scopes: claims
+                .scope
+                .as_deref()
+                .map(split_scopes)
+                .unwrap_or_default(),
+            groups: claims.groups.clone(),
+            tenant_id: claims.tenant_id.clone().and_then(TenantId::new),
+            claims: Some(claims.clone()),
+            ..Self::new(claims.sub, AuthMethod::Cognito)
+        }
+    }
+}
+
+/// Splits an OAuth `scope` claim, which is a space separated list.
+pub fn split_scopes(scope: &str) -> Vec<String> {
+    scope.split_whitespace().map(str::to_string).collect()
+}
+
+pub fn unauthenticated() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({ "error": "You are not authenticated" })),
+    )
+        .into_response()
+}
+
+/// The caller of a route that requires authentication; rejects with 401 otherwise.
+#[derive(Debug, Clone)]
+pub struct AuthUser(pub Principal);
+
+/// The caller of a route that also serves anonymous requests.
+#[derive(Debug, Clone)]
+pub struct MaybeAuthUser(pub Option<Principal>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Principal>()
+            .cloned()
+            .map(AuthUser)
+            .ok_or_else(unauthenticated)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for MaybeAuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(MaybeAuthUser(parts.extensions.get::<Principal>().cloned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use axum::routing::get;
+    use axum::{Extension, Router};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/required",
+                get(|AuthUser(principal): AuthUser| async move { principal.subject }),
+            )
+            .route(
+                "/optional",
+                get(|MaybeAuthUser(principal): MaybeAuthUser| async move {
+                    principal.map_or("anonymous".to_string(), |principal| principal.subject)
+                }),
+            )
+    }
+
+    async fn call(app: Router, uri: &str) -> (StatusCode, String) {
+        let response = app
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_auth_user_rejects_anonymous_requests_with_json_401() {
+        assert_eq!(
+            call(app(), "/required").await,
+            (
+                StatusCode::UNAUTHORIZED,
+                "{\"error\":\"You are not authenticated\"}".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_maybe_auth_user_serves_anonymous_and_authenticated_requests() {
+        let authenticated = app().layer(Extension(Principal::new("user-1", AuthMethod::Oidc)));
+
+        assert_eq!(
+            call(app(), "/optional").await,
+            (StatusCode::OK, "anonymous".to_string())
+        );
+        assert_eq!(
+            call(authenticated.clone(), "/optional").await,
+            (StatusCode::OK, "user-1".to_string())
+        );
+        assert_eq!(
+            call(authenticated, "/required").await,
+            (StatusCode::OK, "user-1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_scope_claim_is_split_on_whitespace() {
+        assert_eq!(
+            split_scopes("openid  items:write\titems:read"),
+            vec!["openid", "items:write", "items:read"]
+        );
+    }
+}
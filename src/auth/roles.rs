@@ -0,0 +1,126 @@
+use serde_json::Value;
+
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Grants `role` to callers whose `claim` equals `value`, or contains it when the claim is a
+/// list such as `cognito:groups`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoleMapping {
+    pub claim: String,
+    pub value: String,
+    pub role: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoleMappings(Vec<RoleMapping>);
+
+impl RoleMappings {
+    pub fn new(mappings: Vec<RoleMapping>) -> Self {
+        Self(mappings)
+    }
+
+    /// Parses comma separated `claim=value->role` entries, e.g.
+    /// `cognito:groups=admins->admin,custom:plan=pro->editor`.
+    pub fn parse(mappings: &str) -> Result<Self, String> {
+        mappings
+            .split(',')
+            .map(str::trim)
+            .filter(|mapping| !mapping.is_empty())
+            .map(|mapping| {
+                let (condition, role) = mapping
+                    .rsplit_once("->")
+                    .ok_or_else(|| format!("Missing role in {}", mapping))?;
+                let (claim, value) = condition
+                    .split_once('=')
+                    .ok_or_else(|| format!("Missing claim value in {}", mapping))?;
+
+                match (claim.trim(), value.trim(), role.trim()) {
+                    ("", _, _) | (_, "", _) | (_, _, "") => Err(format!("Invalid {}", mapping)),
+                    (claim, value, role) => Ok(RoleMapping {
+                        claim: claim.to_string(),
+                        value: value.to_string(),
+                        role: role.to_string(),
+                    }),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// Roles granted by a token's claims, given as a JSON object of raw claim names.
+    pub fn roles_for(&self, claims: &Value) -> Vec<String> {
+        let mut roles: Vec<String> = Vec::new();
+
+        for mapping in &self.0 {
+            let matches = match claims.get(&mapping.claim) {
+                Some(Value::String(value)) => *value == mapping.value,
+                Some(Value::Array(values)) => values
+                    .iter()
+                    .any(|value| value.as_str() == Some(&mapping.value)),
+                _ => false,
+            };
+
+            if matches && !roles.contains(&mapping.role) {
+                roles.push(mapping.role.clone());
+            }
+        }
+
+        roles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_mappings() {
+        let mappings =
+            RoleMappings::parse("cognito:groups=admins->admin, custom:plan = pro -> editor")
+                .unwrap();
+
+        assert_eq!(
+            mappings,
+            RoleMappings::new(vec![
+                RoleMapping {
+                    claim: "cognito:groups".to_string(),
+                    value: "admins".to_string(),
+                    role: "admin".to_string(),
+                },
+                RoleMapping {
+                    claim: "custom:plan".to_string(),
+                    value: "pro".to_string(),
+                    role: "editor".to_string(),
+                },
+            ])
+        );
+        assert_eq!(RoleMappings::parse("").unwrap(), RoleMappings::default());
+    }
+
+    #[test]
+    fn test_parse_rejects_incomplete_mappings() {
+        assert!(RoleMappings::parse("cognito:groups=admins").is_err());
+        assert!(RoleMappings::parse("cognito:groups->admin").is_err());
+        assert!(RoleMappings::parse("cognito:groups=->admin").is_err());
+    }
+
+    #[test]
+    fn test_roles_from_list_and_string_claims() {
+        let mappings = RoleMappings::parse(
+            "cognito:groups=admins->admin,custom:plan=pro->editor,cognito:groups=staff->admin",
+        )
+        .unwrap();
+
+        assert_eq!(
+            mappings.roles_for(&json!({
+                "cognito:groups": ["staff", "admins"],
+                "custom:plan": "pro",
+            })),
+            vec!["admin", "editor"]
+        );
+        assert!(mappings
+            .roles_for(&json!({"cognito:groups": "users", "custom:plan": ["basic"]}))
+            .is_empty());
+    }
+}
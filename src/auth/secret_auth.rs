@@ -0,0 +1,102 @@
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use subtle::ConstantTimeEq;
+
+use super::chain::{bearer_token, Authenticator};
+use super::cognito_auth::AuthError;
+use super::principal::Principal;
+use crate::config::AuthMethod;
+use crate::tenant::TenantId;
+
+/// Recorded as the principal of requests made with the shared secret, which carries no
+/// identity of its own.
+pub const SECRET_ACTOR: &str = "secret";
+
+#[derive(Clone)]
+pub struct SecretAuth {
+    pub secret: String,
+    pub tenant_id: TenantId,
+    pub scopes: Vec<String>,
+    pub roles: Vec<String>,
+}
+
+impl SecretAuth {
+    pub fn new(secret: String, tenant_id: TenantId) -> Self {
+        Self {
+            secret,
+            tenant_id,
+            scopes: Vec::new(),
+            roles: Vec::new(),
+        }
+    }
+
+    /// Scopes granted to every secret caller, which has no token to carry them.
+    pub fn with_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    /// Roles granted to every secret caller, e.g. `admin` to issue the first API keys.
+    pub fn with_roles(mut self, roles: Vec<String>) -> Self {
+        self.roles = roles;
+        self
+    }
+}
+
+/// The legacy single secret, kept so callers can move to managed API keys during rollout. Any
+/// other bearer token is left to the rest of the chain, since it may be a JWT or an API key.
+#[async_trait]
+impl Authenticator for SecretAuth {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>, AuthError> {
+        match bearer_token(headers) {
+            Some(token) if bool::from(token.as_bytes().ct_eq(self.secret.as_bytes())) => {
+                Ok(Some(Principal {
+                    scopes: self.scopes.clone(),
+                    roles: self.roles.clone(),
+                    tenant_id: Some(self.tenant_id.clone()),
+                    ..Principal::new(SECRET_ACTOR, AuthMethod::Secret)
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", authorization.parse().unwrap());
+        headers
+    }
+
+    fn auth() -> SecretAuth {
+        SecretAuth::new("s3cret".to_string(), TenantId::new("acme").unwrap())
+            .with_scopes(vec!["items:read".to_string()])
+    }
+
+    #[tokio::test]
+    async fn test_matching_secret_authenticates_configured_tenant() {
+        let principal = auth()
+            .authenticate(&headers("Bearer s3cret"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(principal.subject, SECRET_ACTOR);
+        assert_eq!(principal.method, AuthMethod::Secret);
+        assert_eq!(principal.tenant_id, TenantId::new("acme"));
+        assert_eq!(principal.scopes, vec!["items:read"]);
+    }
+
+    #[tokio::test]
+    async fn test_other_tokens_are_left_to_the_chain() {
+        assert!(auth()
+            .authenticate(&headers("Bearer a.b.c"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,254 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use super::api_key::hash_secret;
+use super::cognito_auth::AuthError;
+use crate::db::{OperationResult, TenantRepository};
+use crate::models::session::SessionDynamoDbRepository;
+use crate::tenant::TenantId;
+
+pub const REFRESH_TOKEN_PREFIX: &str = "rt";
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+/// A refresh token as handed to clients: `rt.<session id>.<secret>`. Only the hash of the
+/// secret is stored, on the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshToken {
+    pub session_id: String,
+    pub secret: String,
+}
+
+impl RefreshToken {
+    /// The first token of a new session.
+    pub fn new_session() -> Self {
+        Self::for_session(&Uuid::new_v4().to_string())
+    }
+
+    /// A fresh token for an existing session, as issued on rotation.
+    pub fn for_session(session_id: &str) -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+
+        Self {
+            session_id: session_id.to_string(),
+            secret: URL_SAFE_NO_PAD.encode(secret),
+        }
+    }
+
+    pub fn parse(token: &str) -> Option<Self> {
+        let mut parts = token.split('.');
+
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(REFRESH_TOKEN_PREFIX), Some(session_id), Some(secret), None)
+                if !session_id.is_empty() && !secret.is_empty() =>
+            {
+                Some(Self {
+                    session_id: session_id.to_string(),
+                    secret: secret.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn hash(&self) -> String {
+        hash_secret(&self.secret)
+    }
+}
+
+impl fmt::Display for RefreshToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}",
+            REFRESH_TOKEN_PREFIX, self.session_id, self.secret
+        )
+    }
+}
+
+/// Cached answers, keyed by tenant and session id, with when they were looked up.
+type ActiveCache = HashMap<(String, String), (bool, Instant)>;
+
+/// Whether the session behind an access token is still active, checked on every request.
+/// Answers are cached for `ttl`, so a revoked session stays usable on other instances for at
+/// most that long; the instance that revokes it forgets its answer immediately.
+#[derive(Clone)]
+pub struct SessionCheck {
+    sessions: Arc<dyn TenantRepository<dyn SessionDynamoDbRepository>>,
+    cache: Arc<RwLock<ActiveCache>>,
+    ttl: Duration,
+}
+
+impl SessionCheck {
+    pub fn new(
+        sessions: Arc<dyn TenantRepository<dyn SessionDynamoDbRepository>>,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            sessions,
+            cache: Arc::new(RwLock::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    pub async fn is_active(
+        &self,
+        tenant_id: &TenantId,
+        session_id: &str,
+    ) -> Result<bool, AuthError> {
+        let key = (tenant_id.as_str().to_string(), session_id.to_string());
+
+        if let Some((active, checked_at)) = self.cache.read().unwrap().get(&key) {
+            if checked_at.elapsed() < self.ttl {
+                return Ok(*active);
+            }
+        }
+
+        let active = match self
+            .sessions
+            .for_tenant(tenant_id)
+            .get_item(session_id.to_string())
+            .await
+        {
+            OperationResult::Success(Some(session)) => !session.is_expired(now()),
+            OperationResult::Success(None) | OperationResult::ItemNotFound => false,
+            OperationResult::InternalError(err) => return Err(AuthError::VerificationFailed(err)),
+            _ => false,
+        };
+
+        let mut cache = self.cache.write().unwrap();
+        cache.retain(|_, (_, checked_at)| checked_at.elapsed() < self.ttl);
+        cache.insert(key, (active, Instant::now()));
+
+        Ok(active)
+    }
+
+    pub fn forget(&self, tenant_id: &TenantId, session_id: &str) {
+        self.cache
+            .write()
+            .unwrap()
+            .remove(&(tenant_id.as_str().to_string(), session_id.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ScanFilter, ScanReport};
+    use crate::models::session::Session;
+    use mockall::mock;
+
+    mock! {
+        pub SessionRepository {}
+
+        #[async_trait::async_trait]
+        impl crate::db::DynamoDbOperations<Session> for SessionRepository {
+            async fn get_item(&self, id: String) -> OperationResult<Session>;
+            async fn create(&self, item: Session) -> OperationResult<Session>;
+            async fn update(&self, item: Session) -> OperationResult<Session>;
+            async fn delete(&self, id: String) -> OperationResult<Session>;
+            async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<Session>;
+            async fn scan(&self) -> OperationResult<Vec<Session>>;
+            async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<Session>>;
+            async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<Session>>;
+            async fn get_deleted_items(&self) -> OperationResult<Vec<Session>>;
+        }
+
+        #[async_trait::async_trait]
+        impl SessionDynamoDbRepository for SessionRepository {
+            async fn rotate_refresh_hash(
+                &self,
+                id: String,
+                current_hash: String,
+                new_hash: String,
+                retired_hashes: Vec<String>,
+            ) -> OperationResult<Session>;
+            async fn revoke_user_sessions(
+                &self,
+                user_id: String,
+                revoked_by: String,
+            ) -> OperationResult<Vec<String>>;
+        }
+    }
+
+    fn tenant() -> TenantId {
+        TenantId::new("acme").unwrap()
+    }
+
+    fn session(id: &str, ttl: u64) -> Session {
+        Session {
+            id: id.to_string(),
+            user_id: "user-1".to_string(),
+            refresh_hash: RefreshToken::for_session(id).hash(),
+            retired_hashes: Vec::new(),
+            created_at: 1700000000,
+            ttl,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    fn check(sessions: MockSessionRepository) -> SessionCheck {
+        let sessions: Arc<dyn SessionDynamoDbRepository> = Arc::new(sessions);
+        SessionCheck::new(
+            Arc::new(move |_: &TenantId| sessions.clone()),
+            Duration::from_secs(30),
+        )
+    }
+
+    #[test]
+    fn test_token_round_trip() {
+        let token = RefreshToken::new_session();
+        let rotated = RefreshToken::for_session(&token.session_id);
+
+        assert_eq!(RefreshToken::parse(&token.to_string()), Some(token.clone()));
+        assert_eq!(rotated.session_id, token.session_id);
+        assert_ne!(rotated.hash(), token.hash());
+        assert_eq!(RefreshToken::parse("rt.s1"), None);
+        assert_eq!(RefreshToken::parse("ak.s1.secret"), None);
+        assert_eq!(RefreshToken::parse("rt.s1.secret.extra"), None);
+    }
+
+    #[tokio::test]
+    async fn test_answers_are_cached_until_forgotten() {
+        let mut sessions = MockSessionRepository::new();
+        sessions
+            .expect_get_item()
+            .times(2)
+            .returning(|id| OperationResult::Success(Some(session(&id, u64::MAX))));
+        let check = check(sessions);
+
+        assert!(check.is_active(&tenant(), "s1").await.unwrap());
+        assert!(check.is_active(&tenant(), "s1").await.unwrap());
+        check.forget(&tenant(), "s1");
+        assert!(check.is_active(&tenant(), "s1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_revoked_and_expired_sessions_are_inactive() {
+        let mut sessions = MockSessionRepository::new();
+        sessions
+            .expect_get_item()
+            .returning(|id| match id.as_str() {
+                "expired" => OperationResult::Success(Some(session(&id, 1))),
+                _ => OperationResult::ItemNotFound,
+            });
+        let check = check(sessions);
+
+        assert!(!check.is_active(&tenant(), "expired").await.unwrap());
+        assert!(!check.is_active(&tenant(), "revoked").await.unwrap());
+    }
+}
@@ -0,0 +1,568 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::{header::AUTHORIZATION, HeaderMap, HeaderValue, Method, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use reqwest::StatusCode;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::api_key::now;
+use super::chain::insert_principal;
+use super::cognito_auth::AuthError;
+use super::lockout::{client_ip, ip_key, too_many_attempts, Lockout};
+use super::principal::Principal;
+use crate::config::{AuthMethod, HmacConfig};
+use crate::rate_limit::NonceStore;
+use crate::tenant::TenantId;
+
+pub const SIGNATURE_SCHEME: &str = "HMAC-SHA256";
+pub const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+pub const NONCE_HEADER: &str = "x-signature-nonce";
+
+/// API Gateway's own payload limit; signed bodies are buffered to be hashed.
+const MAX_BODY_BYTES: usize = 6 * 1024 * 1024;
+const MAX_NONCE_LENGTH: usize = 128;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a signature covers, one field per line: the scheme, method, path, query parameters
+/// sorted so clients need not preserve their order, the timestamp, the nonce and the base64
+/// SHA-256 of the body.
+pub fn string_to_sign(
+    method: &Method,
+    uri: &Uri,
+    timestamp: u64,
+    nonce: &str,
+    body: &[u8],
+) -> String {
+    let mut query: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .collect();
+    query.sort_unstable();
+
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}\n{}",
+        SIGNATURE_SCHEME,
+        method.as_str(),
+        uri.path(),
+        query.join("&"),
+        timestamp,
+        nonce,
+        STANDARD.encode(Sha256::digest(body))
+    )
+}
+
+fn mac(secret: &str, string_to_sign: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+    mac.update(string_to_sign.as_bytes());
+    mac
+}
+
+/// Signs requests the way [`HmacAuth`] verifies them, for tests and client SDKs. The headers
+/// it returns are added to the request as they are:
+///
+/// ```ignore
+/// Authorization: HMAC-SHA256 Credential=<client id>, Signature=<base64 signature>
+/// X-Signature-Timestamp: <unix seconds>
+/// X-Signature-Nonce: <unique per request>
+/// ```
+#[derive(Clone)]
+pub struct RequestSigner {
+    pub client_id: String,
+    pub secret: String,
+}
+
+impl RequestSigner {
+    pub fn new(client_id: impl Into<String>, secret: impl Into<String>) -> Self {
+        Self {
+            client_id: client_id.into(),
+            secret: secret.into(),
+        }
+    }
+
+    /// Signs with the current time and a random nonce.
+    pub fn sign(&self, method: &Method, uri: &Uri, body: &[u8]) -> HeaderMap {
+        let mut nonce = [0u8; 16];
+        OsRng.fill_bytes(&mut nonce);
+
+        self.sign_at(method, uri, body, now(), &URL_SAFE_NO_PAD.encode(nonce))
+    }
+
+    pub fn sign_at(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        body: &[u8],
+        timestamp: u64,
+        nonce: &str,
+    ) -> HeaderMap {
+        let signature = mac(
+            &self.secret,
+            &string_to_sign(method, uri, timestamp, nonce, body),
+        )
+        .finalize()
+        .into_bytes();
+        let authorization = format!(
+            "{} Credential={}, Signature={}",
+            SIGNATURE_SCHEME,
+            self.client_id,
+            STANDARD.encode(signature)
+        );
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            (AUTHORIZATION.as_str(), authorization),
+            (TIMESTAMP_HEADER, timestamp.to_string()),
+            (NONCE_HEADER, nonce.to_string()),
+        ] {
+            headers.insert(
+                name,
+                HeaderValue::from_str(&value).expect("Signature headers are ASCII"),
+            );
+        }
+        headers
+    }
+}
+
+struct SigningClient {
+    tenant_id: TenantId,
+    secret: String,
+}
+
+/// Verifies signed requests from the clients in [`HmacConfig`]. A request is accepted once:
+/// its timestamp must be within `max_skew_seconds` of the server clock and its nonce unused
+/// in that window.
+#[derive(Clone)]
+pub struct HmacAuth {
+    clients: Arc<HashMap<String, SigningClient>>,
+    scopes: Vec<String>,
+    max_skew_seconds: u64,
+    nonces: Arc<dyn NonceStore>,
+    lockout: Option<Lockout>,
+}
+
+impl HmacAuth {
+    pub fn new(config: HmacConfig, nonces: Arc<dyn NonceStore>) -> Self {
+        let clients = config
+            .clients
+            .into_iter()
+            .map(|client| {
+                (
+                    client.id,
+                    SigningClient {
+                        tenant_id: client.tenant_id,
+                        secret: client.secret,
+                    },
+                )
+            })
+            .collect();
+
+        Self {
+            clients: Arc::new(clients),
+            scopes: config.scopes,
+            max_skew_seconds: config.max_skew_seconds,
+            nonces,
+            lockout: None,
+        }
+    }
+
+    /// Counts signatures that do not verify against the client IP's lockout, and refuses
+    /// signed requests from locked out IPs with 429.
+    pub fn with_lockout(mut self, lockout: Lockout) -> Self {
+        self.lockout = Some(lockout);
+        self
+    }
+
+    /// `Ok(None)` for requests that are not signed with [`SIGNATURE_SCHEME`].
+    pub async fn verify(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Option<Principal>, AuthError> {
+        let credentials = match signed_credentials(headers) {
+            Some(credentials) => credentials,
+            None => return Ok(None),
+        };
+        let (client_id, signature) =
+            parse_credentials(credentials).ok_or(AuthError::MalformedToken)?;
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let timestamp: u64 = header(TIMESTAMP_HEADER)
+            .and_then(|timestamp| timestamp.parse().ok())
+            .ok_or(AuthError::MalformedToken)?;
+        let nonce = header(NONCE_HEADER)
+            .filter(|nonce| !nonce.is_empty() && nonce.len() <= MAX_NONCE_LENGTH)
+            .ok_or(AuthError::MalformedToken)?;
+
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(AuthError::InvalidSignature)?;
+        let signature = STANDARD
+            .decode(signature)
+            .map_err(|_| AuthError::InvalidSignature)?;
+        mac(
+            &client.secret,
+            &string_to_sign(method, uri, timestamp, nonce, body),
+        )
+        .verify_slice(&signature)
+        .map_err(|_| AuthError::InvalidSignature)?;
+
+        if now().abs_diff(timestamp) > self.max_skew_seconds {
+            return Err(AuthError::InvalidToken);
+        }
+
+        // Claimed only once the signature holds, so nobody else can burn a client's nonces.
+        let key = format!("nonce#{}#{}", client_id, nonce);
+        let fresh = self
+            .nonces
+            .claim(&key, timestamp + self.max_skew_seconds + 1)
+            .await
+            .map_err(AuthError::VerificationFailed)?;
+        if !fresh {
+            tracing::warn!(
+                target: "security",
+                event = "signature_replay",
+                client = %client_id,
+                "Rejected a replayed signed request"
+            );
+            return Err(AuthError::InvalidToken);
+        }
+
+        Ok(Some(Principal {
+            scopes: self.scopes.clone(),
+            tenant_id: Some(client.tenant_id.clone()),
+            ..Principal::new(client_id, AuthMethod::Hmac)
+        }))
+    }
+}
+
+fn signed_credentials(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|value| value.strip_prefix(SIGNATURE_SCHEME))
+        .and_then(|value| value.strip_prefix(' '))
+}
+
+/// `Credential=<client id>, Signature=<signature>`, in either order.
+fn parse_credentials(credentials: &str) -> Option<(&str, &str)> {
+    let (mut client_id, mut signature) = (None, None);
+
+    for field in credentials.split(',') {
+        match field.trim().split_once('=') {
+            Some(("Credential", value)) if !value.is_empty() => client_id = Some(value),
+            // Base64 padding also uses '=', so only the first one separates the name.
+            Some(("Signature", value)) if !value.is_empty() => signature = Some(value),
+            _ => return None,
+        }
+    }
+
+    Some((client_id?, signature?))
+}
+
+/// Authenticates requests signed with [`SIGNATURE_SCHEME`] and leaves every other request to
+/// the `authenticate` chain behind it. A layer of its own rather than a chain member, since
+/// the signature covers the body.
+pub async fn authenticate_signed(
+    State(auth): State<HmacAuth>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if signed_credentials(request.headers()).is_none() {
+        return next.run(request).await;
+    }
+
+    let ip = client_ip(request.extensions())
+        .filter(|_| auth.lockout.is_some())
+        .map(|ip| ip_key(&ip));
+    if let (Some(lockout), Some(key)) = (&auth.lockout, &ip) {
+        if let Some(retry_after) = lockout.retry_after(std::slice::from_ref(key)).await {
+            return too_many_attempts(retry_after);
+        }
+    }
+
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(_) => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(json!({ "error": "Request body too large" })),
+            )
+                .into_response()
+        }
+    };
+
+    match auth
+        .verify(&parts.method, &parts.uri, &parts.headers, &body)
+        .await
+    {
+        Ok(principal) => {
+            let mut request = Request::from_parts(parts, Body::from(body));
+            if let Some(principal) = principal {
+                insert_principal(&mut request, principal);
+            }
+            next.run(request).await
+        }
+        Err(err) => {
+            if let (AuthError::InvalidSignature, Some(lockout), Some(key)) =
+                (&err, &auth.lockout, &ip)
+            {
+                tracing::info!(
+                    target: "security",
+                    event = "auth_failure",
+                    key = %key,
+                    "Failed signature verification"
+                );
+                lockout.record_failure(key).await;
+            }
+            err.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::chain::{authenticate, AuthChain};
+    use crate::auth::lockout::LockoutPolicy;
+    use crate::auth::principal::AuthUser;
+    use crate::config::HmacClient;
+    use crate::rate_limit::InMemoryRateLimitStore;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::post;
+    use axum::Router;
+    use lambda_http::aws_lambda_events::apigw::{
+        ApiGatewayV2httpRequestContext, ApiGatewayV2httpRequestContextHttpDescription,
+    };
+    use lambda_http::request::RequestContext;
+    use tower::ServiceExt;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn auth() -> HmacAuth {
+        HmacAuth::new(
+            HmacConfig {
+                clients: vec![HmacClient {
+                    id: "partner".to_string(),
+                    tenant_id: TenantId::new("acme").unwrap(),
+                    secret: SECRET.to_string(),
+                }],
+                scopes: vec!["items:read".to_string()],
+                max_skew_seconds: 300,
+            },
+            Arc::new(InMemoryRateLimitStore::new()),
+        )
+    }
+
+    fn uri() -> Uri {
+        "/foo?b=2&a=1".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_signed_request_authenticates_client() {
+        let headers = RequestSigner::new("partner", SECRET).sign(&Method::POST, &uri(), b"{}");
+
+        let principal = auth()
+            .verify(&Method::POST, &uri(), &headers, b"{}")
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(principal.subject, "partner");
+        assert_eq!(principal.method, AuthMethod::Hmac);
+        assert_eq!(principal.tenant_id, TenantId::new("acme"));
+        assert_eq!(principal.scopes, vec!["items:read"]);
+    }
+
+    #[tokio::test]
+    async fn test_query_order_does_not_matter() {
+        let headers = RequestSigner::new("partner", SECRET).sign(&Method::GET, &uri(), b"");
+        let reordered: Uri = "/foo?a=1&b=2".parse().unwrap();
+
+        assert!(auth()
+            .verify(&Method::GET, &reordered, &headers, b"")
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tampered_requests_are_rejected() {
+        let auth = auth();
+        let headers = RequestSigner::new("partner", SECRET).sign(&Method::POST, &uri(), b"{}");
+        let other_path: Uri = "/foo/1?b=2&a=1".parse().unwrap();
+        let other_query: Uri = "/foo?b=3&a=1".parse().unwrap();
+
+        for (method, uri, body) in [
+            (Method::POST, uri(), &b"{\"admin\":true}"[..]),
+            (Method::DELETE, uri(), &b"{}"[..]),
+            (Method::POST, other_path, &b"{}"[..]),
+            (Method::POST, other_query, &b"{}"[..]),
+        ] {
+            assert!(matches!(
+                auth.verify(&method, &uri, &headers, body).await,
+                Err(AuthError::InvalidSignature)
+            ));
+        }
+
+        let forged =
+            RequestSigner::new("partner", "f".repeat(32)).sign(&Method::POST, &uri(), b"{}");
+        let unknown = RequestSigner::new("stranger", SECRET).sign(&Method::POST, &uri(), b"{}");
+        for headers in [forged, unknown] {
+            assert!(matches!(
+                auth.verify(&Method::POST, &uri(), &headers, b"{}").await,
+                Err(AuthError::InvalidSignature)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stale_and_replayed_requests_are_rejected() {
+        let auth = auth();
+        let signer = RequestSigner::new("partner", SECRET);
+        let stale = signer.sign_at(&Method::GET, &uri(), b"", now() - 301, "n1");
+        let fresh = signer.sign_at(&Method::GET, &uri(), b"", now(), "n2");
+
+        assert!(matches!(
+            auth.verify(&Method::GET, &uri(), &stale, b"").await,
+            Err(AuthError::InvalidToken)
+        ));
+        assert!(auth
+            .verify(&Method::GET, &uri(), &fresh, b"")
+            .await
+            .unwrap()
+            .is_some());
+        assert!(matches!(
+            auth.verify(&Method::GET, &uri(), &fresh, b"").await,
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_other_schemes_are_left_to_the_chain() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer s3cret".parse().unwrap());
+
+        assert!(auth()
+            .verify(&Method::GET, &uri(), &headers, b"")
+            .await
+            .unwrap()
+            .is_none());
+
+        headers.insert(
+            AUTHORIZATION,
+            "HMAC-SHA256 Credential=partner".parse().unwrap(),
+        );
+        assert!(matches!(
+            auth().verify(&Method::GET, &uri(), &headers, b"").await,
+            Err(AuthError::MalformedToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_middleware_passes_the_body_on() {
+        let app = Router::new()
+            .route(
+                "/foo",
+                post(|AuthUser(principal): AuthUser, body: String| async move {
+                    format!("{}:{}", principal.subject, body)
+                }),
+            )
+            .route_layer(from_fn_with_state(AuthChain::new(), authenticate))
+            .route_layer(from_fn_with_state(auth(), authenticate_signed));
+        let call = |body: &'static str, signed_body: &'static [u8]| {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri(uri())
+                .body(Body::from(body))
+                .unwrap();
+            request
+                .headers_mut()
+                .extend(RequestSigner::new("partner", SECRET).sign(
+                    &Method::POST,
+                    &uri(),
+                    signed_body,
+                ));
+            app.clone().oneshot(request)
+        };
+
+        let response = call("hello", b"hello").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"partner:hello");
+
+        let response = call("hello", b"goodbye").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_bad_signatures_lock_out_the_client_ip() {
+        let lockout = Lockout::new(
+            Arc::new(InMemoryRateLimitStore::new()),
+            LockoutPolicy {
+                threshold: 2,
+                ..LockoutPolicy::default()
+            },
+        );
+        let app = Router::new()
+            .route(
+                "/foo",
+                post(|AuthUser(principal): AuthUser| async move { principal.subject }),
+            )
+            .route_layer(from_fn_with_state(AuthChain::new(), authenticate))
+            .route_layer(from_fn_with_state(
+                auth().with_lockout(lockout),
+                authenticate_signed,
+            ));
+        let call = |ip: &str, secret: String| {
+            let context = ApiGatewayV2httpRequestContext {
+                http: ApiGatewayV2httpRequestContextHttpDescription {
+                    source_ip: Some(ip.to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri(uri())
+                .extension(RequestContext::ApiGatewayV2(context))
+                .body(Body::from("{}"))
+                .unwrap();
+            request
+                .headers_mut()
+                .extend(RequestSigner::new("partner", secret).sign(&Method::POST, &uri(), b"{}"));
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        for _ in 0..2 {
+            assert_eq!(
+                call("203.0.113.7", "f".repeat(32)).await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+        assert_eq!(
+            call("203.0.113.7", SECRET.to_string()).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            call("203.0.113.8", SECRET.to_string()).await,
+            StatusCode::OK
+        );
+    }
+}
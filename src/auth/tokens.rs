@@ -0,0 +1,293 @@
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header};
+use jsonwebtoken::{errors::ErrorKind, Validation};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use super::chain::{bearer_token, unverified_issuer, Authenticator};
+use super::cognito_auth::AuthError;
+use super::principal::{split_scopes, Principal};
+use super::sessions::SessionCheck;
+use crate::config::{AuthMethod, JwtConfig};
+use crate::models::user::User;
+use crate::tenant::TenantId;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+/// Claims of the access tokens this service issues to password users. `sid` names the login
+/// session, which can be revoked before the token expires.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AccessClaims {
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub username: String,
+    pub scope: String,
+    pub tenant_id: String,
+    pub sid: String,
+    pub jti: String,
+}
+
+/// Signs and verifies the service's own JWTs (HS256). The first configured key signs; the
+/// others only verify, so a new key can be introduced before the old one is dropped.
+#[derive(Clone)]
+pub struct TokenIssuer {
+    config: Arc<JwtConfig>,
+    sessions: Option<SessionCheck>,
+}
+
+impl TokenIssuer {
+    pub fn new(config: JwtConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            sessions: None,
+        }
+    }
+
+    /// Rejects tokens whose session has been revoked, e.g. by logout.
+    pub fn with_sessions(mut self, sessions: SessionCheck) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    pub fn sessions(&self) -> Option<&SessionCheck> {
+        self.sessions.as_ref()
+    }
+
+    pub fn refresh_token_ttl(&self) -> u64 {
+        self.config.refresh_token_ttl_seconds
+    }
+
+    pub fn access_token_ttl(&self) -> u64 {
+        self.config.access_token_ttl_seconds
+    }
+
+    pub fn issue(
+        &self,
+        user: &User,
+        tenant_id: &TenantId,
+        session_id: &str,
+    ) -> Result<String, AuthError> {
+        let (kid, secret) = &self.config.signing_keys[0];
+        let iat = now();
+        let claims = AccessClaims {
+            sub: user.id.clone(),
+            iss: self.config.issuer.clone(),
+            aud: self.config.audience.clone(),
+            iat,
+            exp: iat + self.config.access_token_ttl_seconds,
+            username: user.username.clone(),
+            scope: self.config.scopes.join(" "),
+            tenant_id: tenant_id.as_str().to_string(),
+            sid: session_id.to_string(),
+            jti: Uuid::new_v4().to_string(),
+        };
+        let header = Header {
+            kid: Some(kid.clone()),
+            ..Header::new(Algorithm::HS256)
+        };
+
+        encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .map_err(|err| AuthError::ConversionError(err.to_string()))
+    }
+
+    pub fn verify(&self, token: &str) -> Result<AccessClaims, AuthError> {
+        let kid = decode_header(token)
+            .map_err(|_| AuthError::MalformedToken)?
+            .kid
+            .ok_or(AuthError::InvalidToken)?;
+        let (_, secret) = self
+            .config
+            .signing_keys
+            .iter()
+            .find(|(key_id, _)| *key_id == kid)
+            .ok_or(AuthError::InvalidToken)?;
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        decode::<AccessClaims>(
+            token,
+            &DecodingKey::from_secret(secret.as_bytes()),
+            &validation,
+        )
+        .map(|data| data.claims)
+        .map_err(|err| match err.kind() {
+            ErrorKind::ExpiredSignature => AuthError::TokenExpired,
+            ErrorKind::InvalidSignature => AuthError::InvalidSignature,
+            _ => AuthError::InvalidToken,
+        })
+    }
+}
+
+/// Only claims tokens naming this service as issuer; other JWTs are left to the chain.
+#[async_trait]
+impl Authenticator for TokenIssuer {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>, AuthError> {
+        let token = match bearer_token(headers) {
+            Some(token) if unverified_issuer(token).as_deref() == Some(&self.config.issuer) => {
+                token
+            }
+            _ => return Ok(None),
+        };
+        let claims = self.verify(token)?;
+        let tenant_id = TenantId::new(claims.tenant_id).ok_or(AuthError::InvalidToken)?;
+
+        if let Some(sessions) = &self.sessions {
+            if !sessions.is_active(&tenant_id, &claims.sid).await? {
+                return Err(AuthError::InvalidToken);
+            }
+        }
+
+        Ok(Some(Principal {
+            username: Some(claims.username),
+            scopes: split_scopes(&claims.scope),
+            tenant_id: Some(tenant_id),
+            ..Principal::new(claims.sub, AuthMethod::Password)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> JwtConfig {
+        JwtConfig {
+            issuer: "https://api.example.com".to_string(),
+            audience: "template-api".to_string(),
+            signing_keys: vec![("k1".to_string(), "a".repeat(32))],
+            access_token_ttl_seconds: 900,
+            refresh_token_ttl_seconds: 86400,
+            session_cache_seconds: 30,
+            scopes: vec!["items:read".to_string(), "items:write".to_string()],
+        }
+    }
+
+    fn user() -> User {
+        User {
+            id: "user-1".to_string(),
+            email: "jane@example.com".to_string(),
+            username: "jane".to_string(),
+            created_at: "1700000000".to_string(),
+            email_verified: false,
+            password_hash: None,
+            admin: false,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    fn headers(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_issued_token_authenticates_user() {
+        let issuer = TokenIssuer::new(config());
+        let token = issuer
+            .issue(&user(), &TenantId::new("acme").unwrap(), "s1")
+            .unwrap();
+
+        let principal = issuer
+            .authenticate(&headers(&token))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(principal.subject, "user-1");
+        assert_eq!(principal.username.as_deref(), Some("jane"));
+        assert_eq!(principal.method, AuthMethod::Password);
+        assert_eq!(principal.tenant_id, TenantId::new("acme"));
+        assert_eq!(principal.scopes, vec!["items:read", "items:write"]);
+    }
+
+    #[test]
+    fn test_previous_keys_still_verify_after_rotation() {
+        let old = TokenIssuer::new(config());
+        let token = old
+            .issue(&user(), &TenantId::new("acme").unwrap(), "s1")
+            .unwrap();
+        let rotated = TokenIssuer::new(JwtConfig {
+            signing_keys: vec![
+                ("k2".to_string(), "b".repeat(32)),
+                ("k1".to_string(), "a".repeat(32)),
+            ],
+            ..config()
+        });
+        let dropped = TokenIssuer::new(JwtConfig {
+            signing_keys: vec![("k2".to_string(), "b".repeat(32))],
+            ..config()
+        });
+
+        assert_eq!(rotated.verify(&token).unwrap().sub, "user-1");
+        assert!(matches!(
+            dropped.verify(&token),
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_and_foreign_tokens_are_rejected() {
+        let issuer = TokenIssuer::new(config());
+        let forged = TokenIssuer::new(JwtConfig {
+            signing_keys: vec![("k1".to_string(), "c".repeat(32))],
+            ..config()
+        })
+        .issue(&user(), &TenantId::new("acme").unwrap(), "s1")
+        .unwrap();
+        let other_audience = TokenIssuer::new(JwtConfig {
+            audience: "other".to_string(),
+            ..config()
+        })
+        .issue(&user(), &TenantId::new("acme").unwrap(), "s1")
+        .unwrap();
+
+        assert!(matches!(
+            issuer.verify(&forged),
+            Err(AuthError::InvalidSignature)
+        ));
+        assert!(matches!(
+            issuer.verify(&other_audience),
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tokens_of_other_issuers_are_left_to_the_chain() {
+        let issuer = TokenIssuer::new(config());
+        let other = TokenIssuer::new(JwtConfig {
+            issuer: "https://other.example.com".to_string(),
+            ..config()
+        })
+        .issue(&user(), &TenantId::new("acme").unwrap(), "s1")
+        .unwrap();
+
+        assert!(issuer
+            .authenticate(&headers(&other))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
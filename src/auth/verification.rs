@@ -0,0 +1,278 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use std::fmt;
+use uuid::Uuid;
+
+use super::api_key::{hash_secret, verify_secret};
+use super::sessions::now;
+use crate::db::{DynamoDbOperations, OperationResult};
+use crate::mailer::Email;
+use crate::models::user::User;
+use crate::models::verification::{Verification, VerificationPurpose};
+
+pub const VERIFICATION_TOKEN_PREFIX: &str = "vt";
+
+/// A verification token as mailed to users: `vt.<verification id>.<secret>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationToken {
+    pub id: String,
+    pub secret: String,
+}
+
+impl VerificationToken {
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+
+        Self {
+            id: Uuid::new_v4().to_string(),
+            secret: URL_SAFE_NO_PAD.encode(secret),
+        }
+    }
+
+    pub fn parse(token: &str) -> Option<Self> {
+        let mut parts = token.split('.');
+
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(VERIFICATION_TOKEN_PREFIX), Some(id), Some(secret), None)
+                if !id.is_empty() && !secret.is_empty() =>
+            {
+                Some(Self {
+                    id: id.to_string(),
+                    secret: secret.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn hash(&self) -> String {
+        hash_secret(&self.secret)
+    }
+
+    /// `base_url` with the token appended as the `token` query parameter.
+    pub fn link(&self, base_url: &str) -> String {
+        let separator = if base_url.contains('?') { '&' } else { '?' };
+        format!("{}{}token={}", base_url, separator, self)
+    }
+}
+
+impl fmt::Display for VerificationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}",
+            VERIFICATION_TOKEN_PREFIX, self.id, self.secret
+        )
+    }
+}
+
+/// Stores a new token for `purpose`, bound to the user's current email, valid for `ttl`
+/// seconds.
+pub async fn issue(
+    verifications: &dyn DynamoDbOperations<Verification>,
+    user: &User,
+    purpose: VerificationPurpose,
+    ttl: u64,
+) -> OperationResult<VerificationToken> {
+    let token = VerificationToken::generate();
+    let created_at = now();
+    let verification = Verification {
+        id: token.id.clone(),
+        user_id: user.id.clone(),
+        purpose,
+        email: user.email.clone(),
+        token_hash: token.hash(),
+        created_at,
+        ttl: created_at + ttl,
+        deleted_at: None,
+        deleted_by: None,
+    };
+
+    match verifications.create(verification).await {
+        OperationResult::Success(_) => OperationResult::Success(Some(token)),
+        OperationResult::ItemNotFound => OperationResult::ItemNotFound,
+        OperationResult::ItemAlreadyExists => OperationResult::ItemAlreadyExists,
+        OperationResult::FieldAlreadyExists(field) => OperationResult::FieldAlreadyExists(field),
+        OperationResult::CounterOutOfBounds => OperationResult::CounterOutOfBounds,
+        OperationResult::InvalidInput => OperationResult::InvalidInput,
+        OperationResult::InternalError(err) => OperationResult::InternalError(err),
+    }
+}
+
+/// Redeems a token for `purpose`, returning its record. Malformed, unknown, expired, already
+/// used and wrong-purpose tokens all come back as `ItemNotFound`. The record is soft deleted
+/// conditionally, so of two concurrent attempts only one succeeds.
+pub async fn redeem(
+    verifications: &dyn DynamoDbOperations<Verification>,
+    token: &str,
+    purpose: VerificationPurpose,
+) -> OperationResult<Verification> {
+    let token = match VerificationToken::parse(token) {
+        Some(token) => token,
+        None => return OperationResult::ItemNotFound,
+    };
+
+    let verification = match verifications.get_item(token.id.clone()).await {
+        OperationResult::Success(Some(verification)) => verification,
+        OperationResult::Success(None) => return OperationResult::ItemNotFound,
+        err => return err,
+    };
+
+    if verification.purpose != purpose
+        || !verify_secret(&token.secret, &verification.token_hash)
+        || verification.is_expired(now())
+    {
+        return OperationResult::ItemNotFound;
+    }
+
+    match verifications
+        .soft_delete(verification.id.clone(), verification.user_id.clone())
+        .await
+    {
+        OperationResult::Success(_) => OperationResult::Success(Some(verification)),
+        err => err,
+    }
+}
+
+pub fn verification_email(user: &User, link: &str) -> Email {
+    Email {
+        to: user.email.clone(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Hi {},\n\nConfirm your email address by opening this link:\n\n{}\n\nIf you did not sign up, you can ignore this email.",
+            user.username, link
+        ),
+    }
+}
+
+pub fn password_reset_email(user: &User, link: &str) -> Email {
+    Email {
+        to: user.email.clone(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hi {},\n\nChoose a new password by opening this link:\n\n{}\n\nIf you did not ask for a reset, you can ignore this email; your password stays the same.",
+            user.username, link
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ScanFilter, ScanReport};
+    use mockall::mock;
+    use mockall::predicate::eq;
+
+    mock! {
+        pub VerificationRepository {}
+
+        #[async_trait::async_trait]
+        impl DynamoDbOperations<Verification> for VerificationRepository {
+            async fn get_item(&self, id: String) -> OperationResult<Verification>;
+            async fn create(&self, item: Verification) -> OperationResult<Verification>;
+            async fn update(&self, item: Verification) -> OperationResult<Verification>;
+            async fn delete(&self, id: String) -> OperationResult<Verification>;
+            async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<Verification>;
+            async fn scan(&self) -> OperationResult<Vec<Verification>>;
+            async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<Verification>>;
+            async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<Verification>>;
+            async fn get_deleted_items(&self) -> OperationResult<Vec<Verification>>;
+        }
+    }
+
+    fn stored(token: &VerificationToken, ttl: u64) -> Verification {
+        Verification {
+            id: token.id.clone(),
+            user_id: "user-1".to_string(),
+            purpose: VerificationPurpose::EmailVerification,
+            email: "jane@example.com".to_string(),
+            token_hash: token.hash(),
+            created_at: 1700000000,
+            ttl,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    #[test]
+    fn test_token_round_trip() {
+        let token = VerificationToken::generate();
+
+        assert_eq!(
+            VerificationToken::parse(&token.to_string()),
+            Some(token.clone())
+        );
+        assert_eq!(VerificationToken::parse("rt.id.secret"), None);
+        assert_eq!(VerificationToken::parse("vt.id"), None);
+        assert_eq!(
+            token.link("https://app.example.com/verify?lang=en"),
+            format!("https://app.example.com/verify?lang=en&token={}", token)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redeem_consumes_the_token() {
+        let token = VerificationToken::generate();
+        let verification = stored(&token, u64::MAX);
+        let mut verifications = MockVerificationRepository::new();
+        verifications
+            .expect_get_item()
+            .with(eq(token.id.clone()))
+            .returning(move |_| OperationResult::Success(Some(verification.clone())));
+        verifications
+            .expect_soft_delete()
+            .with(eq(token.id.clone()), eq("user-1".to_string()))
+            .times(1)
+            .returning(|_, _| OperationResult::Success(None));
+
+        let redeemed = redeem(
+            &verifications,
+            &token.to_string(),
+            VerificationPurpose::EmailVerification,
+        )
+        .await;
+
+        assert!(matches!(
+            redeemed,
+            OperationResult::Success(Some(Verification { user_id, .. })) if user_id == "user-1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_secret_and_expired_tokens_are_not_redeemed() {
+        let token = VerificationToken::generate();
+        let expired = VerificationToken::generate();
+        let guessed = VerificationToken {
+            id: token.id.clone(),
+            ..VerificationToken::generate()
+        };
+        let (live, stale) = (stored(&token, u64::MAX), stored(&expired, 1));
+        let mut verifications = MockVerificationRepository::new();
+        verifications.expect_get_item().returning(move |id| {
+            OperationResult::Success(Some(if id == live.id {
+                live.clone()
+            } else {
+                stale.clone()
+            }))
+        });
+        verifications.expect_soft_delete().never();
+
+        for token in [
+            guessed.to_string(),
+            expired.to_string(),
+            "garbage".to_string(),
+        ] {
+            let redeemed = redeem(
+                &verifications,
+                &token,
+                VerificationPurpose::EmailVerification,
+            )
+            .await;
+
+            assert!(matches!(redeemed, OperationResult::ItemNotFound));
+        }
+    }
+}
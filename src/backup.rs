@@ -0,0 +1,211 @@
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+use crate::db::{
+    BackupRecord, DynamoDbRepository, EncryptedFields, OperationResult, RestoreMode, SoftDeletable,
+    UniqueFields,
+};
+use crate::tenant::TenantId;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RestoreSummary {
+    pub restored: usize,
+    pub skipped: usize,
+    pub failed: Vec<(usize, String)>,
+}
+
+pub fn write_records<T, W>(records: &[BackupRecord<T>], mut writer: W) -> Result<(), String>
+where
+    T: Serialize,
+    W: Write,
+{
+    for record in records {
+        serde_json::to_writer(&mut writer, record).map_err(|err| err.to_string())?;
+        writeln!(writer).map_err(|err| err.to_string())?;
+    }
+
+    writer.flush().map_err(|err| err.to_string())
+}
+
+/// Parses a JSON lines backup, returning each record with its 1-based line number. Blank
+/// lines are ignored; any other line that does not parse fails the whole file.
+pub fn read_records<T, R>(reader: R) -> Result<Vec<(usize, BackupRecord<T>)>, String>
+where
+    T: for<'de> Deserialize<'de>,
+    R: BufRead,
+{
+    let mut records = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.map_err(|err| format!("line {}: {}", line_number, err))?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record =
+            serde_json::from_str(&line).map_err(|err| format!("line {}: {}", line_number, err))?;
+        records.push((line_number, record));
+    }
+
+    Ok(records)
+}
+
+/// Places records that carry no tenant, i.e. those written before repositories were scoped to
+/// tenants, into `tenant_id`, so restoring them migrates them into its key space.
+pub fn assign_tenant<T>(records: &mut [(usize, BackupRecord<T>)], tenant_id: &TenantId) -> usize {
+    let mut assigned = 0;
+
+    for (_, record) in records.iter_mut() {
+        if record.tenant_id.is_none() {
+            record.tenant_id = Some(tenant_id.as_str().to_string());
+            assigned += 1;
+        }
+    }
+
+    assigned
+}
+
+/// Restores records `batch_size` at a time, with the writes of a batch running concurrently.
+pub async fn restore_records<T>(
+    repository: &DynamoDbRepository<T>,
+    records: Vec<(usize, BackupRecord<T>)>,
+    mode: RestoreMode,
+    batch_size: usize,
+) -> RestoreSummary
+where
+    T: Serialize
+        + for<'de> Deserialize<'de>
+        + Clone
+        + Send
+        + Sync
+        + 'static
+        + SoftDeletable
+        + UniqueFields
+        + EncryptedFields,
+{
+    let mut summary = RestoreSummary::default();
+
+    for batch in records.chunks(batch_size.max(1)) {
+        let results = join_all(batch.iter().map(|(line_number, record)| async move {
+            (*line_number, repository.restore(record.clone(), mode).await)
+        }))
+        .await;
+
+        for (line_number, result) in results {
+            match result {
+                OperationResult::Success(_) => summary.restored += 1,
+                OperationResult::ItemAlreadyExists if mode == RestoreMode::SkipExisting => {
+                    summary.skipped += 1
+                }
+                OperationResult::FieldAlreadyExists(field) => summary
+                    .failed
+                    .push((line_number, format!("{} already in use", field))),
+                OperationResult::InvalidInput => summary
+                    .failed
+                    .push((line_number, "Invalid tenant id".to_string())),
+                OperationResult::InternalError(err) => summary.failed.push((line_number, err)),
+                _ => summary
+                    .failed
+                    .push((line_number, "Unexpected result".to_string())),
+            }
+        }
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::item::Item;
+
+    fn item(id: &str, deleted_at: Option<&str>) -> Item {
+        Item {
+            id: id.to_string(),
+            name: "name".to_string(),
+            age: 30,
+            created_by: None,
+            deleted_at: deleted_at.map(str::to_string),
+            deleted_by: deleted_at.map(|_| "admin".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_keeps_tenant_and_soft_deleted_records() {
+        let records = vec![
+            BackupRecord {
+                tenant_id: Some("acme".to_string()),
+                record: item("1", None),
+            },
+            BackupRecord {
+                tenant_id: None,
+                record: item("2", Some("1700000000")),
+            },
+        ];
+        let mut output = Vec::new();
+
+        write_records(&records, &mut output).unwrap();
+        let restored = read_records::<Item, _>(output.as_slice()).unwrap();
+
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored[0].0, 1);
+        assert_eq!(restored[0].1.tenant_id.as_deref(), Some("acme"));
+        assert_eq!(restored[0].1.record.id, "1");
+        assert_eq!(restored[1].1.tenant_id, None);
+        assert_eq!(
+            restored[1].1.record.deleted_at.as_deref(),
+            Some("1700000000")
+        );
+    }
+
+    #[test]
+    fn test_records_are_model_json_lines() {
+        let records = vec![BackupRecord {
+            tenant_id: Some("acme".to_string()),
+            record: item("1", None),
+        }];
+        let mut output = Vec::new();
+
+        write_records(&records, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"tenant_id\":\"acme\",\"id\":\"1\",\"name\":\"name\",\"age\":30}\n"
+        );
+    }
+
+    #[test]
+    fn test_assign_tenant_only_fills_missing_tenants() {
+        let input = "{\"id\":\"1\",\"name\":\"name\",\"age\":30}\n\
+                     {\"tenant_id\":\"acme\",\"id\":\"2\",\"name\":\"name\",\"age\":30}\n";
+        let mut records = read_records::<Item, _>(input.as_bytes()).unwrap();
+
+        let assigned = assign_tenant(&mut records, &TenantId::new("default").unwrap());
+
+        assert_eq!(assigned, 1);
+        assert_eq!(records[0].1.tenant_id.as_deref(), Some("default"));
+        assert_eq!(records[1].1.tenant_id.as_deref(), Some("acme"));
+    }
+
+    #[test]
+    fn test_read_records_skips_blank_lines() {
+        let input = "\n{\"id\":\"1\",\"name\":\"name\",\"age\":30}\n\n";
+
+        let records = read_records::<Item, _>(input.as_bytes()).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0, 2);
+    }
+
+    #[test]
+    fn test_read_records_reports_line_of_invalid_record() {
+        let input = "{\"id\":\"1\",\"name\":\"name\",\"age\":30}\n{\"id\":\"2\"}\n";
+
+        let err = read_records::<Item, _>(input.as_bytes()).unwrap_err();
+
+        assert!(err.starts_with("line 2:"));
+    }
+}
@@ -0,0 +1,21 @@
+use lambda_http::aws_lambda_events::apigw::ApiGatewayV2CustomAuthorizerV2Request;
+use lambda_http::{lambda_runtime, service_fn, Error, LambdaEvent};
+use serde_json::Value;
+
+use template::{auth::authorizer::Authorizer, config::AuthorizerConfig, logging};
+
+/// HTTP API Lambda authorizer, so unauthenticated requests are turned away before they reach
+/// the API function or any other function behind the same API.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    logging::init_logger();
+    let authorizer = Authorizer::from_config(&AuthorizerConfig::from_env()).await;
+
+    lambda_runtime::run(service_fn(
+        |event: LambdaEvent<ApiGatewayV2CustomAuthorizerV2Request>| {
+            let authorizer = authorizer.clone();
+            async move { Ok::<Value, Error>(authorizer.handle(event.payload).await) }
+        },
+    ))
+    .await
+}
@@ -0,0 +1,313 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use template::{
+    backup::{assign_tenant, read_records, restore_records, write_records},
+    config::KeyProviderConfig,
+    db::{
+        dynamodb_client, DynamoDbRepository, EncryptedFields, OperationResult, RestoreMode,
+        SoftDeletable, UniqueFields,
+    },
+    encryption::FieldEncryptor,
+    lease::{DynamoDbLeaseStore, LeaseGuard},
+    models::{item::Item, user::User},
+    state::load_sdk_config,
+    tenant::TenantId,
+};
+
+/// How long a restore holds its table's lease between heartbeats.
+const RESTORE_LEASE: Duration = Duration::from_secs(60);
+
+/// Export and restore table records as JSON lines. Counters and unique guards are not part
+/// of a backup; guards are recreated when records are restored.
+#[derive(Parser)]
+struct Cli {
+    /// e.g. http://localhost:8000 for DynamoDB Local
+    #[arg(long, global = true, env = "DYNAMODB_ENDPOINT")]
+    endpoint: Option<String>,
+    /// Table of leases that keep two restores from writing the same table at once
+    #[arg(long, global = true, env = "LEASE_TABLE_NAME")]
+    lease_table: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write every record, soft-deleted ones included, one JSON object per line
+    Export {
+        #[arg(long, value_enum)]
+        model: Model,
+        #[arg(long)]
+        table: String,
+        /// Defaults to stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Write the records of a backup file into a table
+    Restore {
+        #[arg(long, value_enum)]
+        model: Model,
+        #[command(flatten)]
+        args: RestoreArgs,
+    },
+}
+
+#[derive(Args)]
+struct RestoreArgs {
+    #[arg(long)]
+    table: String,
+    #[arg(long)]
+    input: PathBuf,
+    #[arg(long, value_enum, default_value_t = Mode::SkipExisting)]
+    mode: Mode,
+    /// Tenant for records the backup holds without one, which migrates records written
+    /// before repositories were scoped to tenants
+    #[arg(long, value_parser = parse_tenant)]
+    tenant: Option<TenantId>,
+    /// Validate the file and report what would be restored without writing
+    #[arg(long)]
+    dry_run: bool,
+    /// Number of records written concurrently
+    #[arg(long, default_value_t = 25)]
+    batch_size: usize,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Model {
+    Item,
+    User,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Mode {
+    Overwrite,
+    SkipExisting,
+}
+
+impl From<Mode> for RestoreMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Overwrite => RestoreMode::Overwrite,
+            Mode::SkipExisting => RestoreMode::SkipExisting,
+        }
+    }
+}
+
+fn parse_tenant(tenant_id: &str) -> Result<TenantId, String> {
+    TenantId::new(tenant_id).ok_or_else(|| "Tenant must not be empty or contain '#'".to_string())
+}
+
+async fn repository<T>(endpoint: Option<&str>, table: String) -> DynamoDbRepository<T> {
+    let sdk_config = load_sdk_config().await;
+    let repository =
+        DynamoDbRepository::<T>::from_client(dynamodb_client(&sdk_config, endpoint), table);
+
+    match KeyProviderConfig::from_env() {
+        Some(config) => repository.with_encryption(Arc::new(
+            FieldEncryptor::from_config(&config, &sdk_config)
+                .expect("Failed to load encryption key provider"),
+        )),
+        None => repository,
+    }
+}
+
+async fn export<T>(
+    endpoint: Option<&str>,
+    table: String,
+    output: Option<PathBuf>,
+) -> Result<(), String>
+where
+    T: Serialize
+        + for<'de> Deserialize<'de>
+        + Clone
+        + Send
+        + Sync
+        + 'static
+        + SoftDeletable
+        + UniqueFields
+        + EncryptedFields,
+{
+    let records = match repository::<T>(endpoint, table).await.export().await {
+        OperationResult::Success(records) => records.unwrap_or_default(),
+        OperationResult::InternalError(err) => return Err(err),
+        _ => return Err("Export failed".to_string()),
+    };
+
+    match output {
+        Some(path) => {
+            let file = File::create(&path).map_err(|err| err.to_string())?;
+            write_records(&records, BufWriter::new(file))?;
+        }
+        None => write_records(&records, io::stdout().lock())?,
+    }
+
+    eprintln!("Exported {} records", records.len());
+    Ok(())
+}
+
+/// Takes the lease named after `table`, renewed until the guard is released, so a second
+/// restore of the same table refuses to start.
+async fn lock_table(
+    endpoint: Option<&str>,
+    lease_table: String,
+    table: &str,
+) -> Result<LeaseGuard, String> {
+    let sdk_config = load_sdk_config().await;
+    let store = Arc::new(DynamoDbLeaseStore::from_client(
+        dynamodb_client(&sdk_config, endpoint),
+        lease_table,
+    ));
+    let owner = format!("backup-restore-{}", std::process::id());
+
+    match LeaseGuard::acquire(store, &format!("restore#{}", table), &owner, RESTORE_LEASE)
+        .await
+        .map_err(|err| err.to_string())?
+    {
+        Some(guard) => Ok(guard.with_heartbeat(RESTORE_LEASE / 3)),
+        None => Err(format!("Another restore of {} is running", table)),
+    }
+}
+
+async fn restore<T>(
+    endpoint: Option<&str>,
+    lease_table: Option<String>,
+    args: RestoreArgs,
+) -> Result<(), String>
+where
+    T: Serialize
+        + for<'de> Deserialize<'de>
+        + Clone
+        + Send
+        + Sync
+        + 'static
+        + SoftDeletable
+        + UniqueFields
+        + EncryptedFields,
+{
+    let RestoreArgs {
+        table,
+        input,
+        mode,
+        tenant,
+        dry_run,
+        batch_size,
+    } = args;
+    let file = File::open(&input).map_err(|err| err.to_string())?;
+    let mut records = read_records::<T, _>(BufReader::new(file))?;
+
+    if let Some(tenant) = &tenant {
+        let assigned = assign_tenant(&mut records, tenant);
+        eprintln!(
+            "{} records without a tenant go to {}",
+            assigned,
+            tenant.as_str()
+        );
+    }
+
+    if dry_run {
+        eprintln!("Dry run: {} records would be restored", records.len());
+        return Ok(());
+    }
+
+    let lease = match lease_table {
+        Some(lease_table) => Some(lock_table(endpoint, lease_table, &table).await?),
+        None => None,
+    };
+
+    let summary = restore_records(
+        &repository::<T>(endpoint, table.clone()).await,
+        records,
+        mode.into(),
+        batch_size,
+    )
+    .await;
+
+    if let Some(lease) = lease {
+        if !lease.is_held() {
+            eprintln!(
+                "Lease on {} was lost; another restore may have overlapped",
+                table
+            );
+        }
+        if let Err(err) = lease.release().await {
+            eprintln!("Failed to release lease: {}", err);
+        }
+    }
+
+    for (line_number, err) in &summary.failed {
+        eprintln!("line {}: {}", line_number, err);
+    }
+    eprintln!(
+        "Restored {} records, skipped {}, failed {}",
+        summary.restored,
+        summary.skipped,
+        summary.failed.len()
+    );
+
+    if summary.failed.is_empty() {
+        Ok(())
+    } else {
+        Err("Some records could not be restored".to_string())
+    }
+}
+
+/// Restored users are written around the admin count, so it is recounted afterwards, also
+/// after a partial restore.
+async fn restore_users(
+    endpoint: Option<&str>,
+    lease_table: Option<String>,
+    args: RestoreArgs,
+) -> Result<(), String> {
+    let table = args.table.clone();
+    let dry_run = args.dry_run;
+    let restored = restore::<User>(endpoint, lease_table, args).await;
+
+    if dry_run {
+        return restored;
+    }
+
+    let recounted = repository::<User>(endpoint, table)
+        .await
+        .recount_admins()
+        .await
+        .map_err(|err| format!("Failed to recount admins: {}", err));
+
+    restored.and(recounted)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let endpoint = cli.endpoint.as_deref();
+    let lease_table = cli.lease_table;
+
+    let result = match cli.command {
+        Command::Export {
+            model,
+            table,
+            output,
+        } => match model {
+            Model::Item => export::<Item>(endpoint, table, output).await,
+            Model::User => export::<User>(endpoint, table, output).await,
+        },
+        Command::Restore { model, args } => match model {
+            Model::Item => restore::<Item>(endpoint, lease_table, args).await,
+            Model::User => restore_users(endpoint, lease_table, args).await,
+        },
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
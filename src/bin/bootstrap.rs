@@ -0,0 +1,86 @@
+use clap::Parser;
+use std::process::ExitCode;
+
+use template::{
+    db::dynamodb_client,
+    idempotency::IDEMPOTENCY_TABLE_SCHEMA,
+    lease::LEASE_TABLE_SCHEMA,
+    models::{
+        api_key::ApiKey, item::Item, session::Session, user::User, verification::Verification,
+    },
+    rate_limit::RATE_LIMIT_TABLE_SCHEMA,
+    schema::{ensure_table, BootstrapOutcome, TableDefinition, TableSchema},
+    state::load_sdk_config,
+};
+
+/// Create missing tables and verify existing ones against the schemas declared in code.
+/// Table names and the endpoint can also be given through the environment.
+#[derive(Parser)]
+struct Cli {
+    /// e.g. http://localhost:8000 for DynamoDB Local
+    #[arg(long, env = "DYNAMODB_ENDPOINT")]
+    endpoint: Option<String>,
+    #[arg(long, env = "TEST_TABLE_NAME")]
+    item_table: Option<String>,
+    #[arg(long, env = "USER_TABLE_NAME")]
+    user_table: Option<String>,
+    #[arg(long, env = "API_KEY_TABLE_NAME")]
+    api_key_table: Option<String>,
+    #[arg(long, env = "SESSION_TABLE_NAME")]
+    session_table: Option<String>,
+    #[arg(long, env = "VERIFICATION_TABLE_NAME")]
+    verification_table: Option<String>,
+    #[arg(long, env = "IDEMPOTENCY_TABLE_NAME")]
+    idempotency_table: Option<String>,
+    #[arg(long, env = "RATE_LIMIT_TABLE_NAME")]
+    rate_limit_table: Option<String>,
+    #[arg(long, env = "LEASE_TABLE_NAME")]
+    lease_table: Option<String>,
+    /// Report missing tables instead of creating them
+    #[arg(long)]
+    verify_only: bool,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let client = dynamodb_client(&load_sdk_config().await, cli.endpoint.as_deref());
+
+    let tables: Vec<(String, TableSchema)> = [
+        (cli.item_table, Item::TABLE_SCHEMA),
+        (cli.user_table, User::TABLE_SCHEMA),
+        (cli.api_key_table, ApiKey::TABLE_SCHEMA),
+        (cli.session_table, Session::TABLE_SCHEMA),
+        (cli.verification_table, Verification::TABLE_SCHEMA),
+        (cli.idempotency_table, IDEMPOTENCY_TABLE_SCHEMA),
+        (cli.rate_limit_table, RATE_LIMIT_TABLE_SCHEMA),
+        (cli.lease_table, LEASE_TABLE_SCHEMA),
+    ]
+    .into_iter()
+    .filter_map(|(table_name, schema)| table_name.map(|table_name| (table_name, schema)))
+    .collect();
+
+    if tables.is_empty() {
+        eprintln!("No tables configured");
+        return ExitCode::FAILURE;
+    }
+
+    let mut failed = false;
+
+    for (table_name, schema) in &tables {
+        match ensure_table(&client, table_name, schema, !cli.verify_only).await {
+            Ok(BootstrapOutcome::Created) => eprintln!("{}: created", table_name),
+            Ok(BootstrapOutcome::Verified) => eprintln!("{}: ok", table_name),
+            Err(err) => {
+                eprintln!("{}: {}", table_name, err);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -1,61 +1,509 @@
+use jsonwebtoken::Algorithm;
 use std::env;
+use std::str::FromStr;
+use std::time::Duration;
 
+use crate::auth::lockout::LockoutPolicy;
+use crate::auth::password::PasswordPolicy;
+use crate::auth::roles::RoleMappings;
+use crate::db::ScanMode;
+use crate::tenant::TenantId;
+
+/// Parses the number in `name`, or returns `default` when it is unset.
+fn env_number<T: FromStr>(name: &str, default: T) -> T {
+    env_number_opt(name).unwrap_or(default)
+}
+
+/// Parses the number in `name`, or returns `None` when it is unset.
+fn env_number_opt<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a number", name))
+    })
+}
+
+/// Ways a caller can authenticate. `AUTH_METHOD` lists the enabled ones, comma separated, in the
+/// order they are tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AuthMethod {
     Cognito,
     Secret,
+    Oidc,
+    ApiKey,
+    Password,
+    Hmac,
+    /// Trust the principal a Lambda authorizer put in the request context; see
+    /// `auth::authorizer`.
+    Authorizer,
+}
+
+impl AuthMethod {
+    /// The method's name in `AUTH_METHOD`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthMethod::Cognito => "COGNITO",
+            AuthMethod::Secret => "SECRET",
+            AuthMethod::Oidc => "OIDC",
+            AuthMethod::ApiKey => "API_KEY",
+            AuthMethod::Password => "PASSWORD",
+            AuthMethod::Hmac => "HMAC",
+            AuthMethod::Authorizer => "AUTHORIZER",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [
+            AuthMethod::Cognito,
+            AuthMethod::Secret,
+            AuthMethod::Oidc,
+            AuthMethod::ApiKey,
+            AuthMethod::Password,
+            AuthMethod::Hmac,
+            AuthMethod::Authorizer,
+        ]
+        .into_iter()
+        .find(|method| method.as_str() == name)
+    }
+}
+
+pub enum KeyProviderConfig {
+    Local { key_file: String },
+    Kms { key_id: String },
+}
+
+impl KeyProviderConfig {
+    pub fn from_env() -> Option<Self> {
+        match env::var("ENCRYPTION_KEY_PROVIDER").as_deref() {
+            Err(_) => None,
+            Ok("LOCAL") => Some(KeyProviderConfig::Local {
+                key_file: env::var("ENCRYPTION_KEY_FILE").expect("ENCRYPTION_KEY_FILE must be set"),
+            }),
+            Ok("KMS") => Some(KeyProviderConfig::Kms {
+                key_id: env::var("KMS_KEY_ID").expect("KMS_KEY_ID must be set"),
+            }),
+            _ => panic!("Invalid ENCRYPTION_KEY_PROVIDER"),
+        }
+    }
+}
+
+/// Where outgoing email goes. `MAILER` picks `SES` (from `MAIL_FROM`), `FILE` (appended to
+/// `MAIL_FILE`) or `STDOUT`, the default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MailerConfig {
+    Ses { from: String },
+    File { path: String },
+    Stdout,
+}
+
+impl MailerConfig {
+    pub fn from_env() -> Self {
+        match env::var("MAILER").as_deref() {
+            Ok("STDOUT") | Err(_) => MailerConfig::Stdout,
+            Ok("SES") => MailerConfig::Ses {
+                from: env::var("MAIL_FROM").expect("MAIL_FROM must be set"),
+            },
+            Ok("FILE") => MailerConfig::File {
+                path: env::var("MAIL_FILE").expect("MAIL_FILE must be set"),
+            },
+            _ => panic!("Invalid MAILER"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum JwksSource {
+    Url(String),
+    File(String),
+}
+
+/// Any OpenID Connect issuer (Auth0, Keycloak, our own) whose tokens are verified against its
+/// published JWKS.
+#[derive(Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub audiences: Vec<String>,
+    pub jwks: JwksSource,
+    pub algorithms: Vec<Algorithm>,
+    pub clock_skew_seconds: u64,
+    pub jwks_cache_seconds: u64,
+    pub tenant_claim: String,
+}
+
+impl OidcConfig {
+    pub fn from_env() -> Self {
+        let list = |name: &str| -> Vec<String> {
+            env::var(name)
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(|item| item.trim().to_string())
+                        .filter(|item| !item.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let jwks = match (env::var("OIDC_JWKS_URL"), env::var("OIDC_JWKS_FILE")) {
+            (Ok(url), _) => JwksSource::Url(url),
+            (Err(_), Ok(file)) => JwksSource::File(file),
+            _ => panic!("OIDC_JWKS_URL or OIDC_JWKS_FILE must be set"),
+        };
+
+        let algorithms = match list("OIDC_ALGORITHMS") {
+            algorithms if algorithms.is_empty() => vec![Algorithm::RS256],
+            algorithms => algorithms
+                .iter()
+                .map(|algorithm| Algorithm::from_str(algorithm).expect("Invalid OIDC_ALGORITHMS"))
+                .collect(),
+        };
+
+        OidcConfig {
+            issuer: env::var("OIDC_ISSUER").expect("OIDC_ISSUER must be set"),
+            audiences: list("OIDC_AUDIENCE"),
+            jwks,
+            algorithms,
+            clock_skew_seconds: env_number("OIDC_CLOCK_SKEW_SECONDS", 60),
+            jwks_cache_seconds: env_number("OIDC_JWKS_CACHE_SECONDS", 3600),
+            tenant_claim: env::var("OIDC_TENANT_CLAIM").unwrap_or_else(|_| "tenant_id".to_string()),
+        }
+    }
+}
+
+/// Keys for the tokens this service issues to password users. Signing keys are `kid:secret`
+/// pairs; the first signs and all of them verify.
+#[derive(Clone)]
+pub struct JwtConfig {
+    pub issuer: String,
+    pub audience: String,
+    pub signing_keys: Vec<(String, String)>,
+    pub access_token_ttl_seconds: u64,
+    pub refresh_token_ttl_seconds: u64,
+    pub session_cache_seconds: u64,
+    pub scopes: Vec<String>,
+}
+
+impl JwtConfig {
+    pub fn from_env() -> Self {
+        let signing_keys: Vec<(String, String)> = env::var("JWT_SIGNING_KEYS")
+            .expect("JWT_SIGNING_KEYS must be set")
+            .split(',')
+            .map(|key| match key.trim().split_once(':') {
+                Some((kid, secret)) if !kid.is_empty() && secret.len() >= 32 => {
+                    (kid.to_string(), secret.to_string())
+                }
+                _ => panic!(
+                    "JWT_SIGNING_KEYS must be kid:secret pairs with secrets of at least 32 bytes"
+                ),
+            })
+            .collect();
+
+        JwtConfig {
+            issuer: env::var("JWT_ISSUER").expect("JWT_ISSUER must be set"),
+            audience: env::var("JWT_AUDIENCE").unwrap_or_else(|_| "template-api".to_string()),
+            signing_keys,
+            access_token_ttl_seconds: env_number("ACCESS_TOKEN_TTL_SECONDS", 900),
+            refresh_token_ttl_seconds: env_number("REFRESH_TOKEN_TTL_SECONDS", 30 * 86400),
+            session_cache_seconds: env_number("SESSION_CACHE_SECONDS", 30),
+            scopes: env::var("PASSWORD_SCOPES")
+                .unwrap_or_else(|_| "items:read items:write".to_string())
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+/// A partner that signs its requests, and the tenant it acts in.
+#[derive(Clone)]
+pub struct HmacClient {
+    pub id: String,
+    pub tenant_id: TenantId,
+    pub secret: String,
+}
+
+/// Clients allowed to sign requests, listed in `HMAC_CLIENTS` as `id:tenant:secret` entries
+/// with secrets of at least 32 bytes.
+#[derive(Clone)]
+pub struct HmacConfig {
+    pub clients: Vec<HmacClient>,
+    pub scopes: Vec<String>,
+    pub max_skew_seconds: u64,
+}
+
+impl HmacConfig {
+    pub fn from_env() -> Self {
+        let clients = env::var("HMAC_CLIENTS")
+            .expect("HMAC_CLIENTS must be set")
+            .split(',')
+            .map(|client| match client.trim().splitn(3, ':').collect::<Vec<_>>()[..] {
+                [id, tenant_id, secret] if !id.is_empty() && secret.len() >= 32 => HmacClient {
+                    id: id.to_string(),
+                    tenant_id: TenantId::new(tenant_id)
+                        .expect("HMAC_CLIENTS tenants must not be empty or contain '#'"),
+                    secret: secret.to_string(),
+                },
+                _ => panic!(
+                    "HMAC_CLIENTS must be id:tenant:secret entries with secrets of at least 32 bytes"
+                ),
+            })
+            .collect();
+
+        HmacConfig {
+            clients,
+            scopes: env::var("HMAC_SCOPES")
+                .unwrap_or_else(|_| "items:read items:write".to_string())
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            max_skew_seconds: env_number("HMAC_MAX_SKEW_SECONDS", 300),
+        }
+    }
+}
+
+/// The response format configured for the Lambda authorizer on the HTTP API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthorizerResponseFormat {
+    Simple,
+    Iam,
+}
+
+/// Settings of the standalone `authorizer` binary. It verifies Cognito tokens, managed API keys
+/// and the shared secret from the same variables as the API; `AUTH_METHOD` may list `COGNITO`,
+/// `API_KEY` and `SECRET`. `AUTHORIZER_RESPONSE_FORMAT` is `SIMPLE`, the default, or `IAM`.
+pub struct AuthorizerConfig {
+    pub auth_methods: Vec<AuthMethod>,
+    pub dynamodb_endpoint: Option<String>,
+    pub dynamodb_api_key_table_name: Option<String>,
+    pub cognito_region: Option<String>,
+    pub cognito_user_pool_id: Option<String>,
+    pub cognito_client_id: Option<String>,
+    pub secret: Option<String>,
+    pub secret_tenant_id: Option<String>,
+    pub secret_scopes: Vec<String>,
+    pub secret_roles: Vec<String>,
+    pub role_mappings: RoleMappings,
+    pub response_format: AuthorizerResponseFormat,
+}
+
+impl AuthorizerConfig {
+    pub fn from_env() -> Self {
+        let auth_methods: Vec<AuthMethod> = env::var("AUTH_METHOD")
+            .expect("AUTH_METHOD must be set")
+            .split(',')
+            .map(|method| match AuthMethod::parse(method.trim()) {
+                Some(method @ (AuthMethod::Cognito | AuthMethod::ApiKey | AuthMethod::Secret)) => {
+                    method
+                }
+                _ => panic!("AUTH_METHOD of the authorizer must list COGNITO, API_KEY or SECRET"),
+            })
+            .collect();
+        let cognito = auth_methods.contains(&AuthMethod::Cognito);
+        let api_key = auth_methods.contains(&AuthMethod::ApiKey);
+        let secret = auth_methods.contains(&AuthMethod::Secret);
+
+        let response_format = match env::var("AUTHORIZER_RESPONSE_FORMAT").as_deref() {
+            Ok("SIMPLE") | Err(_) => AuthorizerResponseFormat::Simple,
+            Ok("IAM") => AuthorizerResponseFormat::Iam,
+            _ => panic!("Invalid AUTHORIZER_RESPONSE_FORMAT"),
+        };
+
+        AuthorizerConfig {
+            auth_methods,
+            dynamodb_endpoint: env::var("DYNAMODB_ENDPOINT").ok(),
+            dynamodb_api_key_table_name: api_key
+                .then(|| env::var("API_KEY_TABLE_NAME").expect("API_KEY_TABLE_NAME must be set")),
+            cognito_region: cognito
+                .then(|| env::var("COGNITO_REGION").expect("COGNITO_REGION must be set")),
+            cognito_user_pool_id: cognito.then(|| {
+                env::var("COGNITO_USER_POOL_ID").expect("COGNITO_USER_POOL_ID must be set")
+            }),
+            cognito_client_id: cognito
+                .then(|| env::var("COGNITO_CLIENT_ID").expect("COGNITO_CLIENT_ID must be set")),
+            secret: secret.then(|| env::var("SECRET").expect("SECRET must be set")),
+            secret_tenant_id: secret
+                .then(|| env::var("SECRET_TENANT_ID").unwrap_or_else(|_| "default".to_string())),
+            secret_scopes: env::var("SECRET_SCOPES")
+                .unwrap_or_else(|_| "items:read items:write".to_string())
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            secret_roles: env::var("SECRET_ROLES")
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            role_mappings: env::var("ROLE_MAPPINGS")
+                .map(|mappings| RoleMappings::parse(&mappings).expect("Invalid ROLE_MAPPINGS"))
+                .unwrap_or_default(),
+            response_format,
+        }
+    }
 }
 
 pub struct Config {
     pub aws_region: String,
     pub dynamodb_table_name: String,
     pub dynamodb_user_table_name: Option<String>,
-    pub auth_method: AuthMethod,
+    pub dynamodb_api_key_table_name: Option<String>,
+    pub dynamodb_session_table_name: Option<String>,
+    pub dynamodb_verification_table_name: Option<String>,
+    pub dynamodb_endpoint: Option<String>,
+    pub auth_methods: Vec<AuthMethod>,
     pub cognito_region: Option<String>,
     pub cognito_user_pool_id: Option<String>,
     pub cognito_client_id: Option<String>,
     pub secret: Option<String>,
+    pub secret_tenant_id: Option<String>,
+    pub secret_scopes: Vec<String>,
+    pub secret_roles: Vec<String>,
+    pub oidc: Option<OidcConfig>,
+    pub jwt: Option<JwtConfig>,
+    pub hmac: Option<HmacConfig>,
+    pub password_tenant_id: Option<String>,
+    pub password_policy: PasswordPolicy,
+    pub mailer: MailerConfig,
+    pub email_verification_url: String,
+    pub email_verification_ttl_seconds: u64,
+    pub password_reset_url: String,
+    pub password_reset_ttl_seconds: u64,
+    pub password_reset_limit: u64,
+    pub password_reset_window_seconds: u64,
+    pub rate_limit_table_name: Option<String>,
+    /// Set by `ENVIRONMENT=development`, where in-memory stores may stand in for shared tables.
+    pub development: bool,
+    pub lockout_policy: LockoutPolicy,
+    pub role_mappings: RoleMappings,
+    pub scan_mode: ScanMode,
+    pub encryption: Option<KeyProviderConfig>,
+    pub idempotency_table_name: Option<String>,
+    pub idempotency_ttl_seconds: u64,
+    pub item_quota: Option<i64>,
+    pub require_verified_email: bool,
 }
 
 impl Config {
     pub fn from_env() -> Self {
-        let auth_method = env::var("AUTH_METHOD").expect("AUTH_METHOD must be set");
-        let auth_method = match auth_method.as_str() {
-            "COGNITO" => AuthMethod::Cognito,
-            "SECRET" => AuthMethod::Secret,
-            _ => panic!("Invalid AUTH_METHOD"),
+        let auth_methods: Vec<AuthMethod> = env::var("AUTH_METHOD")
+            .expect("AUTH_METHOD must be set")
+            .split(',')
+            .map(|method| AuthMethod::parse(method.trim()).expect("Invalid AUTH_METHOD"))
+            .collect();
+        let cognito = auth_methods.contains(&AuthMethod::Cognito);
+        let secret = auth_methods.contains(&AuthMethod::Secret);
+        let oidc = auth_methods
+            .contains(&AuthMethod::Oidc)
+            .then(OidcConfig::from_env);
+
+        let password = auth_methods.contains(&AuthMethod::Password);
+        let api_key = auth_methods.contains(&AuthMethod::ApiKey);
+        let hmac = auth_methods
+            .contains(&AuthMethod::Hmac)
+            .then(HmacConfig::from_env);
+        let password_policy = PasswordPolicy {
+            min_length: env_number("PASSWORD_MIN_LENGTH", PasswordPolicy::default().min_length),
+            required_classes: env::var("PASSWORD_REQUIRED_CLASSES")
+                .map(|classes| {
+                    PasswordPolicy::parse_classes(&classes)
+                        .expect("Invalid PASSWORD_REQUIRED_CLASSES")
+                })
+                .unwrap_or_default(),
+            ..PasswordPolicy::default()
         };
 
-        match auth_method {
-            AuthMethod::Cognito => Config {
-                aws_region: env::var("AWS_REGION").expect("AWS_REGION must be set"),
-                dynamodb_table_name: env::var("TEST_TABLE_NAME")
-                    .expect("TEST_TABLE_NAME must be set"),
-                dynamodb_user_table_name: None,
-                auth_method,
-                cognito_region: Some(
-                    env::var("COGNITO_REGION").expect("COGNITO_REGION must be set"),
-                ),
-                cognito_user_pool_id: Some(
-                    env::var("COGNITO_USER_POOL_ID").expect("COGNITO_USER_POOL_ID must be set"),
-                ),
-                cognito_client_id: Some(
-                    env::var("COGNITO_CLIENT_ID").expect("COGNITO_CLIENT_ID must be set"),
-                ),
-                secret: None,
-            },
-            AuthMethod::Secret => Config {
-                aws_region: env::var("AWS_REGION").expect("AWS_REGION must be set"),
-                dynamodb_table_name: env::var("TEST_TABLE_NAME")
-                    .expect("TEST_TABLE_NAME must be set"),
-                dynamodb_user_table_name: Some(
-                    env::var("USER_TABLE_NAME").expect("USER_TABLE_NAME must be set"),
-                ),
-                auth_method,
-                cognito_region: None,
-                cognito_user_pool_id: None,
-                cognito_client_id: None,
-                secret: Some(env::var("SECRET").expect("SECRET must be set")),
-            },
+        let role_mappings = env::var("ROLE_MAPPINGS")
+            .map(|mappings| RoleMappings::parse(&mappings).expect("Invalid ROLE_MAPPINGS"))
+            .unwrap_or_default();
+
+        let scan_mode = match env::var("SCAN_MODE").as_deref() {
+            Ok("STRICT") | Err(_) => ScanMode::Strict,
+            Ok("TOLERANT") => ScanMode::Tolerant,
+            Ok("TOLERANT_REPORTED") => ScanMode::TolerantReported,
+            _ => panic!("Invalid SCAN_MODE"),
+        };
+
+        let encryption = KeyProviderConfig::from_env();
+
+        let dynamodb_endpoint = env::var("DYNAMODB_ENDPOINT").ok();
+        let idempotency_table_name = env::var("IDEMPOTENCY_TABLE_NAME").ok();
+        let idempotency_ttl_seconds = env_number("IDEMPOTENCY_TTL_SECONDS", 86400);
+        let lockout_defaults = LockoutPolicy::default();
+        let lockout_policy = LockoutPolicy {
+            threshold: env_number("LOCKOUT_THRESHOLD", lockout_defaults.threshold),
+            window: Duration::from_secs(env_number(
+                "LOCKOUT_WINDOW_SECONDS",
+                lockout_defaults.window.as_secs(),
+            )),
+            base: Duration::from_secs(env_number(
+                "LOCKOUT_BASE_SECONDS",
+                lockout_defaults.base.as_secs(),
+            )),
+            max: Duration::from_secs(env_number(
+                "LOCKOUT_MAX_SECONDS",
+                lockout_defaults.max.as_secs(),
+            )),
+        };
+
+        Config {
+            aws_region: env::var("AWS_REGION").expect("AWS_REGION must be set"),
+            dynamodb_table_name: env::var("TEST_TABLE_NAME").expect("TEST_TABLE_NAME must be set"),
+            dynamodb_user_table_name: Some(
+                env::var("USER_TABLE_NAME").expect("USER_TABLE_NAME must be set"),
+            ),
+            dynamodb_api_key_table_name: api_key
+                .then(|| env::var("API_KEY_TABLE_NAME").expect("API_KEY_TABLE_NAME must be set")),
+            dynamodb_session_table_name: password
+                .then(|| env::var("SESSION_TABLE_NAME").expect("SESSION_TABLE_NAME must be set")),
+            dynamodb_verification_table_name: password.then(|| {
+                env::var("VERIFICATION_TABLE_NAME").expect("VERIFICATION_TABLE_NAME must be set")
+            }),
+            dynamodb_endpoint,
+            auth_methods,
+            cognito_region: cognito
+                .then(|| env::var("COGNITO_REGION").expect("COGNITO_REGION must be set")),
+            cognito_user_pool_id: cognito.then(|| {
+                env::var("COGNITO_USER_POOL_ID").expect("COGNITO_USER_POOL_ID must be set")
+            }),
+            cognito_client_id: cognito
+                .then(|| env::var("COGNITO_CLIENT_ID").expect("COGNITO_CLIENT_ID must be set")),
+            secret: secret.then(|| env::var("SECRET").expect("SECRET must be set")),
+            secret_tenant_id: secret
+                .then(|| env::var("SECRET_TENANT_ID").unwrap_or_else(|_| "default".to_string())),
+            secret_scopes: env::var("SECRET_SCOPES")
+                .unwrap_or_else(|_| "items:read items:write".to_string())
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            secret_roles: env::var("SECRET_ROLES")
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            oidc,
+            jwt: password.then(JwtConfig::from_env),
+            hmac,
+            password_tenant_id: password
+                .then(|| env::var("PASSWORD_TENANT_ID").unwrap_or_else(|_| "default".to_string())),
+            password_policy,
+            mailer: MailerConfig::from_env(),
+            email_verification_url: env::var("EMAIL_VERIFICATION_URL")
+                .unwrap_or_else(|_| "http://localhost:3000/verify-email".to_string()),
+            email_verification_ttl_seconds: env_number("EMAIL_VERIFICATION_TTL_SECONDS", 86400),
+            password_reset_url: env::var("PASSWORD_RESET_URL")
+                .unwrap_or_else(|_| "http://localhost:3000/reset-password".to_string()),
+            password_reset_ttl_seconds: env_number("PASSWORD_RESET_TTL_SECONDS", 3600),
+            password_reset_limit: env_number("PASSWORD_RESET_LIMIT", 3),
+            password_reset_window_seconds: env_number("PASSWORD_RESET_WINDOW_SECONDS", 3600),
+            rate_limit_table_name: env::var("RATE_LIMIT_TABLE_NAME").ok(),
+            development: env::var("ENVIRONMENT").as_deref() == Ok("development"),
+            lockout_policy,
+            role_mappings,
+            scan_mode,
+            encryption,
+            idempotency_table_name,
+            idempotency_ttl_seconds,
+            item_quota: env_number_opt("ITEM_QUOTA"),
+            require_verified_email: env::var("REQUIRE_VERIFIED_EMAIL").as_deref() == Ok("true"),
         }
     }
 }
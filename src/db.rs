@@ -1,11 +1,14 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use aws_config::meta::region::RegionProviderChain;
+use aws_config::SdkConfig;
 use aws_sdk_dynamodb::operation::delete_item::DeleteItemError;
 use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
 use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
-use aws_sdk_dynamodb::types::AttributeValue;
-use aws_sdk_dynamodb::{Client, Error};
+use aws_sdk_dynamodb::types::{
+    AttributeValue, CancellationReason, Delete, Put, ReturnValue, TransactWriteItem, Update,
+};
+use aws_sdk_dynamodb::Client;
 use axum::response::IntoResponse;
 use axum::Json;
 use reqwest::StatusCode;
@@ -13,12 +16,19 @@ use serde::{Deserialize, Serialize};
 use serde_dynamo::{from_item, to_item};
 use serde_json::json;
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::encryption::FieldEncryptor;
+use crate::tenant::{self, TenantId};
 
 pub enum OperationResult<T> {
     Success(Option<T>),
     ItemNotFound,
     ItemAlreadyExists,
+    FieldAlreadyExists(String),
+    CounterOutOfBounds,
     InvalidInput,
     InternalError(String),
 }
@@ -37,6 +47,16 @@ impl<T> IntoResponse for OperationResult<T> {
                 Json(json!({ "error": "Item already exists" })),
             )
                 .into_response(),
+            OperationResult::FieldAlreadyExists(field) => (
+                StatusCode::CONFLICT,
+                Json(json!({ "error": "Item already exists", "field": field })),
+            )
+                .into_response(),
+            OperationResult::CounterOutOfBounds => (
+                StatusCode::CONFLICT,
+                Json(json!({ "error": "Counter out of bounds" })),
+            )
+                .into_response(),
             OperationResult::InvalidInput => (
                 StatusCode::BAD_REQUEST,
                 Json(json!({ "error": "Invalid input" })),
@@ -51,11 +71,254 @@ impl<T> IntoResponse for OperationResult<T> {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScanMode {
+    #[default]
+    Strict,
+    Tolerant,
+    TolerantReported,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanFilter {
+    All,
+    Active,
+    Deleted,
+    DeletedBy(String),
+}
+
+impl ScanFilter {
+    fn expression(&self) -> &'static str {
+        match self {
+            ScanFilter::All => {
+                "attribute_not_exists(unique_owner) AND attribute_not_exists(counter_value)"
+            }
+            ScanFilter::Active => {
+                "attribute_not_exists(deleted_at) AND attribute_not_exists(unique_owner) \
+                 AND attribute_not_exists(counter_value)"
+            }
+            ScanFilter::Deleted => "attribute_exists(deleted_at)",
+            ScanFilter::DeletedBy(_) => "attribute_exists(deleted_at) AND deleted_by = :user_id",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedRecord {
+    pub key: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanReport<T> {
+    pub items: Vec<T>,
+    pub skipped_count: usize,
+    pub skipped: Vec<SkippedRecord>,
+}
+
+impl<T> Default for ScanReport<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            skipped_count: 0,
+            skipped: Vec::new(),
+        }
+    }
+}
+
+/// One line of a table backup. The tenant is kept next to the model fields so scoped records
+/// are restored into the tenant they came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupRecord<T> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    #[serde(flatten)]
+    pub record: T,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestoreMode {
+    Overwrite,
+    SkipExisting,
+}
+
 #[async_trait]
 pub trait SoftDeletable: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync {
     fn get_deleted_at(&self) -> &Option<String>;
 }
 
+pub const UNIQUE_GUARD_PREFIX: &str = "UNIQUE#";
+
+/// Attributes that must be unique across a table. `UNIQUE_FIELDS` names them and
+/// `unique_fields` returns their values; each pair is backed by a guard item
+/// `UNIQUE#<field>#<value>` written in the same transaction as the record itself, so values
+/// should already be normalized (e.g. lowercased emails). Only active records hold guards: they
+/// are released on hard and soft delete, and a soft-deleted record restored from a backup comes
+/// back without them.
+pub trait UniqueFields {
+    const UNIQUE_FIELDS: &'static [&'static str] = &[];
+
+    fn unique_fields(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+}
+
+/// Attributes encrypted client-side before they are written. Only applied when the repository
+/// is configured with a [`FieldEncryptor`]; plaintext values from before that still decode.
+pub trait EncryptedFields {
+    const ENCRYPTED_FIELDS: &'static [&'static str] = &[];
+}
+
+pub fn unique_guard_id(field: &str, value: &str) -> String {
+    format!("{}{}#{}", UNIQUE_GUARD_PREFIX, field, value)
+}
+
+/// The unique values `item` holds guards for; none once it is soft-deleted.
+fn guarded_fields<T: SoftDeletable + UniqueFields>(item: &T) -> Vec<(&'static str, String)> {
+    if item.get_deleted_at().is_none() {
+        item.unique_fields()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Times a guarded write is retried when the record changed between reading and writing it.
+const RECORD_WRITE_ATTEMPTS: usize = 3;
+
+/// A field and the value the stored record must still hold in it, `None` meaning absent, so a
+/// write built from a read fails if the record changed in between.
+pub(crate) type ExpectedValue<'a> = (&'a str, Option<AttributeValue>);
+
+/// The values `fields` have in the stored `item`.
+pub(crate) fn stored_values<'a>(
+    item: &HashMap<String, AttributeValue>,
+    fields: &[&'a str],
+) -> Vec<ExpectedValue<'a>> {
+    fields
+        .iter()
+        .map(|field| (*field, item.get(*field).cloned()))
+        .collect()
+}
+
+/// Extends `condition` to require the `expected` values, adding the names and values it uses.
+fn expect_values(
+    condition: &mut String,
+    names: &mut HashMap<String, String>,
+    values: &mut HashMap<String, AttributeValue>,
+    expected: &[ExpectedValue],
+) {
+    for (index, (field, value)) in expected.iter().enumerate() {
+        let name = format!("#expected{}", index);
+        match value {
+            Some(value) => {
+                let placeholder = format!(":expected{}", index);
+                condition.push_str(&format!(" AND {} = {}", name, placeholder));
+                values.insert(placeholder, value.clone());
+            }
+            None => condition.push_str(&format!(" AND attribute_not_exists({})", name)),
+        }
+        names.insert(name, field.to_string());
+    }
+}
+
+pub const COUNTER_PREFIX: &str = "COUNTER#";
+
+pub fn counter_id(owner_id: &str, counter: &str) -> String {
+    format!("{}{}#{}", COUNTER_PREFIX, owner_id, counter)
+}
+
+/// Inclusive limits the counter value must stay within after an increment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CounterBounds {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl CounterBounds {
+    pub fn unbounded() -> Self {
+        Self::default()
+    }
+
+    pub fn non_negative() -> Self {
+        Self::at_least(0)
+    }
+
+    pub fn at_least(min: i64) -> Self {
+        Self {
+            min: Some(min),
+            max: None,
+        }
+    }
+
+    pub fn with_max(mut self, max: Option<i64>) -> Self {
+        self.max = max;
+        self
+    }
+}
+
+/// Builds the condition guarding an `ADD counter_value :delta` so the result stays within
+/// `bounds`. A missing counter counts as zero. Returns `None` if the bounds cannot be
+/// expressed without overflowing.
+fn counter_condition(
+    delta: i64,
+    bounds: CounterBounds,
+) -> Option<(Option<String>, HashMap<String, AttributeValue>)> {
+    let mut conditions = Vec::new();
+    let mut values = HashMap::new();
+
+    if let Some(min) = bounds.min {
+        let lower = min.checked_sub(delta)?;
+        values.insert(":lower".to_string(), AttributeValue::N(lower.to_string()));
+        if delta >= min {
+            conditions.push("(attribute_not_exists(counter_value) OR counter_value >= :lower)");
+        } else {
+            conditions.push("counter_value >= :lower");
+        }
+    }
+
+    if let Some(max) = bounds.max {
+        let upper = max.checked_sub(delta)?;
+        values.insert(":upper".to_string(), AttributeValue::N(upper.to_string()));
+        if delta <= max {
+            conditions.push("(attribute_not_exists(counter_value) OR counter_value <= :upper)");
+        } else {
+            conditions.push("counter_value <= :upper");
+        }
+    }
+
+    if conditions.is_empty() {
+        Some((None, values))
+    } else {
+        Some((Some(conditions.join(" AND ")), values))
+    }
+}
+
+fn counter_value(item: Option<&HashMap<String, AttributeValue>>) -> Result<i64, String> {
+    match item.and_then(|item| item.get("counter_value")) {
+        Some(AttributeValue::N(value)) => value.parse().map_err(|_| "Invalid counter".to_string()),
+        Some(_) => Err("Invalid counter".to_string()),
+        None => Ok(0),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TransactionStep {
+    Record,
+    ClaimGuard(&'static str),
+    ReleaseGuard(&'static str),
+    Counter,
+}
+
+fn failed_step<'a>(
+    steps: &'a [TransactionStep],
+    reasons: &[CancellationReason],
+) -> Option<&'a TransactionStep> {
+    reasons
+        .iter()
+        .position(|reason| reason.code() == Some("ConditionalCheckFailed"))
+        .and_then(|index| steps.get(index))
+}
+
 #[async_trait]
 pub trait DynamoDbOperations<T>: Send + Sync {
     async fn get_item(&self, id: String) -> OperationResult<T>;
@@ -64,41 +327,504 @@ pub trait DynamoDbOperations<T>: Send + Sync {
     async fn delete(&self, id: String) -> OperationResult<T>;
     async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<T>;
     async fn scan(&self) -> OperationResult<Vec<T>>;
+    async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<T>>;
     async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<T>>;
     async fn get_deleted_items(&self) -> OperationResult<Vec<T>>;
 }
 
+/// Hands out repositories restricted to one tenant, so shared application state can hold a
+/// single unscoped backend per entity. Closures returning a fixed repository implement it too,
+/// which is how tests plug in mocks.
+pub trait TenantRepository<R: ?Sized>: Send + Sync {
+    fn for_tenant(&self, tenant_id: &TenantId) -> Arc<R>;
+}
+
+impl<R, F> TenantRepository<R> for F
+where
+    R: ?Sized,
+    F: Fn(&TenantId) -> Arc<R> + Send + Sync,
+{
+    fn for_tenant(&self, tenant_id: &TenantId) -> Arc<R> {
+        self(tenant_id)
+    }
+}
+
+/// Atomic numeric counters stored as `COUNTER#<owner>#<name>` items next to the records they
+/// belong to, so full-record writes never clobber concurrent increments.
+#[async_trait]
+pub trait CounterOperations: Send + Sync {
+    async fn increment(
+        &self,
+        owner_id: String,
+        counter: String,
+        delta: i64,
+        bounds: CounterBounds,
+    ) -> OperationResult<i64>;
+    async fn get_counter(&self, owner_id: String, counter: String) -> OperationResult<i64>;
+}
+
 #[derive(Clone)]
 pub struct DynamoDbRepository<T> {
     pub client: Client,
     pub table_name: String,
+    pub scan_mode: ScanMode,
+    pub encryptor: Option<Arc<FieldEncryptor>>,
+    pub tenant_id: Option<String>,
     pub _phantom: std::marker::PhantomData<T>,
 }
 
-impl<T> DynamoDbRepository<T> {
-    pub async fn new(table_name: String) -> Result<Self, Error> {
-        let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-        let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-            .region(region_provider)
-            .load()
-            .await;
-        let client = Client::new(&config);
+/// Client sending requests to `endpoint` instead of the regional endpoint when set
+/// (e.g. DynamoDB Local or LocalStack).
+pub fn dynamodb_client(sdk_config: &SdkConfig, endpoint: Option<&str>) -> Client {
+    let mut dynamodb_config = aws_sdk_dynamodb::config::Builder::from(sdk_config);
+    dynamodb_config.set_endpoint_url(endpoint.map(str::to_string));
 
-        Ok(Self {
+    Client::from_conf(dynamodb_config.build())
+}
+
+impl<T> DynamoDbRepository<T> {
+    pub fn from_client(client: Client, table_name: String) -> Self {
+        Self {
             client,
             table_name,
+            scan_mode: ScanMode::default(),
+            encryptor: None,
+            tenant_id: None,
             _phantom: std::marker::PhantomData,
+        }
+    }
+
+    pub fn with_scan_mode(mut self, scan_mode: ScanMode) -> Self {
+        self.scan_mode = scan_mode;
+        self
+    }
+
+    pub fn with_encryption(mut self, encryptor: Arc<FieldEncryptor>) -> Self {
+        self.encryptor = Some(encryptor);
+        self
+    }
+
+    /// Restricts every key, condition and scan of the repository to one tenant.
+    pub fn with_tenant(mut self, tenant_id: &TenantId) -> Self {
+        self.tenant_id = Some(tenant_id.as_str().to_string());
+        self
+    }
+
+    pub fn record_id(&self, id: &str) -> String {
+        match &self.tenant_id {
+            Some(tenant_id) => tenant::scoped_id(tenant_id, id),
+            None => id.to_string(),
+        }
+    }
+
+    pub fn condition(&self, condition: &str) -> String {
+        match &self.tenant_id {
+            Some(_) => tenant::scoped_condition(condition),
+            None => condition.to_string(),
+        }
+    }
+
+    /// Expression values referenced by [`Self::condition`]. Set these before adding any
+    /// other values, since `set_expression_attribute_values` replaces the whole map.
+    pub fn condition_values(&self) -> Option<HashMap<String, AttributeValue>> {
+        self.tenant_id.as_ref().map(|tenant_id| {
+            HashMap::from([(
+                ":tenant_id".to_string(),
+                AttributeValue::S(tenant_id.clone()),
+            )])
         })
     }
+
+    fn owns(&self, item: &HashMap<String, AttributeValue>) -> bool {
+        match &self.tenant_id {
+            Some(tenant_id) => tenant::owns_item(tenant_id, item),
+            None => true,
+        }
+    }
+
+    pub(crate) async fn fetch_raw(
+        &self,
+        id: &AttributeValue,
+    ) -> Result<Option<HashMap<String, AttributeValue>>, String> {
+        match self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", id.clone())
+            .consistent_read(true)
+            .send()
+            .await
+        {
+            Ok(result) => Ok(result.item.filter(|item| self.owns(item))),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn claim_guard(
+        &self,
+        field: &str,
+        value: &str,
+        owner: &AttributeValue,
+    ) -> Result<TransactWriteItem, String> {
+        let guard = HashMap::from([
+            (
+                "id".to_string(),
+                AttributeValue::S(self.record_id(&unique_guard_id(field, value))),
+            ),
+            ("unique_owner".to_string(), owner.clone()),
+        ]);
+
+        let put = Put::builder()
+            .table_name(&self.table_name)
+            .set_item(Some(guard))
+            .condition_expression("attribute_not_exists(id) OR unique_owner = :owner")
+            .expression_attribute_values(":owner", owner.clone())
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        Ok(TransactWriteItem::builder().put(put).build())
+    }
+
+    fn release_guard(
+        &self,
+        field: &str,
+        value: &str,
+        owner: &AttributeValue,
+    ) -> Result<TransactWriteItem, String> {
+        let delete = Delete::builder()
+            .table_name(&self.table_name)
+            .key(
+                "id",
+                AttributeValue::S(self.record_id(&unique_guard_id(field, value))),
+            )
+            .condition_expression("attribute_not_exists(id) OR unique_owner = :owner")
+            .expression_attribute_values(":owner", owner.clone())
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        Ok(TransactWriteItem::builder().delete(delete).build())
+    }
+
+    fn create_steps(
+        &self,
+        dynamo_item: HashMap<String, AttributeValue>,
+        owner: &AttributeValue,
+        unique_fields: &[(&'static str, String)],
+    ) -> Result<Vec<(TransactionStep, TransactWriteItem)>, String> {
+        let put = Put::builder()
+            .table_name(&self.table_name)
+            .set_item(Some(dynamo_item))
+            .condition_expression("attribute_not_exists(id)")
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        let mut steps = vec![(
+            TransactionStep::Record,
+            TransactWriteItem::builder().put(put).build(),
+        )];
+
+        for (field, value) in unique_fields {
+            steps.push((
+                TransactionStep::ClaimGuard(field),
+                self.claim_guard(field, value, owner)?,
+            ));
+        }
+
+        Ok(steps)
+    }
+
+    /// Claims the guards of `unique_fields` and releases those of `previous_fields` whose value
+    /// is no longer held. The record must still hold the `expected` values read with
+    /// `previous_fields`; with `overwrite`, `None` expects no record at all.
+    fn update_steps(
+        &self,
+        dynamo_item: HashMap<String, AttributeValue>,
+        owner: &AttributeValue,
+        unique_fields: &[(&'static str, String)],
+        previous_fields: &[(&'static str, String)],
+        overwrite: bool,
+        expected: Option<&[ExpectedValue]>,
+    ) -> Result<Vec<(TransactionStep, TransactWriteItem)>, String> {
+        let mut names = HashMap::new();
+        let mut values = HashMap::new();
+        let condition = match expected {
+            Some(expected) => {
+                let mut condition = if overwrite {
+                    "attribute_exists(id)".to_string()
+                } else {
+                    "attribute_exists(id) AND attribute_not_exists(deleted_at)".to_string()
+                };
+                values = self.condition_values().unwrap_or_default();
+                expect_values(&mut condition, &mut names, &mut values, expected);
+                self.condition(&condition)
+            }
+            None => "attribute_not_exists(id)".to_string(),
+        };
+
+        let put = Put::builder()
+            .table_name(&self.table_name)
+            .set_item(Some(dynamo_item))
+            .condition_expression(condition)
+            .set_expression_attribute_names(Some(names).filter(|names| !names.is_empty()))
+            .set_expression_attribute_values(Some(values).filter(|values| !values.is_empty()))
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        let mut steps = vec![(
+            TransactionStep::Record,
+            TransactWriteItem::builder().put(put).build(),
+        )];
+
+        for (field, value) in unique_fields {
+            steps.push((
+                TransactionStep::ClaimGuard(field),
+                self.claim_guard(field, value, owner)?,
+            ));
+        }
+
+        for (field, previous) in previous_fields {
+            let still_held = unique_fields
+                .iter()
+                .any(|(unique_field, value)| unique_field == field && value == previous);
+
+            if !still_held {
+                steps.push((
+                    TransactionStep::ReleaseGuard(field),
+                    self.release_guard(field, previous, owner)?,
+                ));
+            }
+        }
+
+        Ok(steps)
+    }
+
+    fn delete_steps(
+        &self,
+        id: &AttributeValue,
+        unique_fields: &[(&'static str, String)],
+    ) -> Result<Vec<(TransactionStep, TransactWriteItem)>, String> {
+        let delete = Delete::builder()
+            .table_name(&self.table_name)
+            .key("id", id.clone())
+            .condition_expression(self.condition("attribute_exists(id)"))
+            .set_expression_attribute_values(self.condition_values())
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        let mut steps = vec![(
+            TransactionStep::Record,
+            TransactWriteItem::builder().delete(delete).build(),
+        )];
+
+        for (field, value) in unique_fields {
+            steps.push((
+                TransactionStep::ReleaseGuard(field),
+                self.release_guard(field, value, id)?,
+            ));
+        }
+
+        Ok(steps)
+    }
+
+    /// Soft deletes the record and releases the guards of `unique_fields`. The record must
+    /// still hold the `expected` values, i.e. those the guards and any other writes of the
+    /// transaction, such as a counter step, were derived from.
+    pub(crate) fn soft_delete_steps(
+        &self,
+        id: &AttributeValue,
+        user_id: &str,
+        deleted_at: &str,
+        unique_fields: &[(&'static str, String)],
+        expected: &[ExpectedValue],
+    ) -> Result<Vec<(TransactionStep, TransactWriteItem)>, String> {
+        let mut condition = "attribute_exists(id) AND attribute_not_exists(deleted_at)".to_string();
+        let mut names = HashMap::new();
+        let mut values = self.condition_values().unwrap_or_default();
+        expect_values(&mut condition, &mut names, &mut values, expected);
+
+        let update = Update::builder()
+            .table_name(&self.table_name)
+            .key("id", id.clone())
+            .update_expression("SET deleted_at = :deleted_at, deleted_by = :deleted_by")
+            .condition_expression(self.condition(&condition))
+            .set_expression_attribute_names(Some(names).filter(|names| !names.is_empty()))
+            .set_expression_attribute_values(Some(values))
+            .expression_attribute_values(":deleted_at", AttributeValue::S(deleted_at.to_string()))
+            .expression_attribute_values(":deleted_by", AttributeValue::S(user_id.to_string()))
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        let mut steps = vec![(
+            TransactionStep::Record,
+            TransactWriteItem::builder().update(update).build(),
+        )];
+
+        for (field, value) in unique_fields {
+            steps.push((
+                TransactionStep::ReleaseGuard(field),
+                self.release_guard(field, value, id)?,
+            ));
+        }
+
+        Ok(steps)
+    }
+
+    /// An `increment` of the counter as a transaction step, so it only applies together with
+    /// the record writes it accounts for.
+    pub(crate) fn counter_step(
+        &self,
+        owner_id: &str,
+        counter: &str,
+        delta: i64,
+        bounds: CounterBounds,
+    ) -> Result<(TransactionStep, TransactWriteItem), String> {
+        let (condition, mut values) =
+            counter_condition(delta, bounds).ok_or("Counter bounds overflow")?;
+        values.insert(
+            ":owner".to_string(),
+            AttributeValue::S(owner_id.to_string()),
+        );
+        values.insert(":delta".to_string(), AttributeValue::N(delta.to_string()));
+
+        let update = Update::builder()
+            .table_name(&self.table_name)
+            .key(
+                "id",
+                AttributeValue::S(counter_id(&self.record_id(owner_id), counter)),
+            )
+            .update_expression("SET counter_owner = :owner ADD counter_value :delta")
+            .set_condition_expression(condition)
+            .set_expression_attribute_values(Some(values))
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        Ok((
+            TransactionStep::Counter,
+            TransactWriteItem::builder().update(update).build(),
+        ))
+    }
+
+    pub(crate) async fn has_counter(&self, owner_id: &str, counter: &str) -> Result<bool, String> {
+        match self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(
+                "id",
+                AttributeValue::S(counter_id(&self.record_id(owner_id), counter)),
+            )
+            .consistent_read(true)
+            .send()
+            .await
+        {
+            Ok(result) => Ok(result.item.is_some()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    /// Creates the counter at `value` unless it has been written already.
+    pub(crate) async fn seed_counter(
+        &self,
+        owner_id: &str,
+        counter: &str,
+        value: i64,
+    ) -> Result<(), String> {
+        self.put_counter(owner_id, counter, value, Some("attribute_not_exists(id)"))
+            .await
+    }
+
+    /// Sets the counter to `value` whatever it held before.
+    pub(crate) async fn reset_counter(
+        &self,
+        owner_id: &str,
+        counter: &str,
+        value: i64,
+    ) -> Result<(), String> {
+        self.put_counter(owner_id, counter, value, None).await
+    }
+
+    async fn put_counter(
+        &self,
+        owner_id: &str,
+        counter: &str,
+        value: i64,
+        condition: Option<&str>,
+    ) -> Result<(), String> {
+        match self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item(
+                "id",
+                AttributeValue::S(counter_id(&self.record_id(owner_id), counter)),
+            )
+            .item("counter_owner", AttributeValue::S(owner_id.to_string()))
+            .item("counter_value", AttributeValue::N(value.to_string()))
+            .set_condition_expression(condition.map(str::to_string))
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => match err.into_service_error() {
+                PutItemError::ConditionalCheckFailedException(_) => Ok(()),
+                err => Err(err.to_string()),
+            },
+        }
+    }
+
+    pub(crate) async fn write_transaction(
+        &self,
+        steps: Vec<(TransactionStep, TransactWriteItem)>,
+        record_failure: OperationResult<T>,
+    ) -> OperationResult<T> {
+        let (steps, items): (Vec<_>, Vec<_>) = steps.into_iter().unzip();
+
+        match self
+            .client
+            .transact_write_items()
+            .set_transact_items(Some(items))
+            .send()
+            .await
+        {
+            Ok(_) => OperationResult::Success(None),
+            Err(err) => match err.into_service_error() {
+                TransactWriteItemsError::TransactionCanceledException(err) => {
+                    match failed_step(&steps, err.cancellation_reasons()) {
+                        Some(TransactionStep::Record) => record_failure,
+                        Some(TransactionStep::ClaimGuard(field)) => {
+                            OperationResult::FieldAlreadyExists(field.to_string())
+                        }
+                        Some(TransactionStep::ReleaseGuard(field)) => {
+                            OperationResult::InternalError(format!(
+                                "Unique guard for {} is owned by another item",
+                                field
+                            ))
+                        }
+                        Some(TransactionStep::Counter) => OperationResult::CounterOutOfBounds,
+                        None => OperationResult::InternalError("Transaction cancelled".to_string()),
+                    }
+                }
+                _ => OperationResult::InternalError("Service Error".to_string()),
+            },
+        }
+    }
 }
 
 #[async_trait]
 impl<T> DynamoDbOperations<T> for DynamoDbRepository<T>
 where
-    T: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + 'static + SoftDeletable,
+    T: Serialize
+        + for<'de> Deserialize<'de>
+        + Clone
+        + Send
+        + Sync
+        + 'static
+        + SoftDeletable
+        + UniqueFields
+        + EncryptedFields,
 {
     async fn get_item(&self, id: String) -> OperationResult<T> {
-        let key = HashMap::from([("id".to_string(), AttributeValue::S(id))]);
+        let key = HashMap::from([("id".to_string(), AttributeValue::S(self.record_id(&id)))]);
 
         match self
             .client
@@ -108,11 +834,13 @@ where
             .send()
             .await
         {
-            Ok(result) => match result.item {
-                Some(item) => match from_item::<
-                    HashMap<std::string::String, aws_sdk_dynamodb::types::AttributeValue>,
-                    T,
-                >(item)
+            Ok(result) => match result.item.filter(|item| self.owns(item)) {
+                Some(item) => match decode_record::<T>(
+                    item,
+                    self.encryptor.as_deref(),
+                    self.tenant_id.as_deref(),
+                )
+                .await
                 {
                     Ok(item) => {
                         if item.get_deleted_at().is_none() {
@@ -121,7 +849,7 @@ where
                             OperationResult::ItemNotFound
                         }
                     }
-                    Err(err) => OperationResult::InternalError(err.to_string()),
+                    Err(err) => OperationResult::InternalError(err),
                 },
                 None => OperationResult::ItemNotFound,
             },
@@ -130,26 +858,42 @@ where
     }
 
     async fn scan(&self) -> OperationResult<Vec<T>> {
-        let mut items = Vec::new();
+        into_items(self.scan_report(ScanFilter::Active).await)
+    }
+
+    async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<T>> {
+        let mut report = ScanReport::default();
         let mut last_evaluated_key = None;
 
         loop {
-            match self
+            let mut request = self
                 .client
                 .scan()
                 .table_name(&self.table_name)
-                .filter_expression("attribute_not_exists(deleted_at)")
-                .set_exclusive_start_key(last_evaluated_key)
-                .send()
-                .await
-            {
+                .filter_expression(self.condition(filter.expression()))
+                .set_expression_attribute_values(self.condition_values())
+                .set_exclusive_start_key(last_evaluated_key);
+
+            if let ScanFilter::DeletedBy(user_id) = &filter {
+                request = request.expression_attribute_values(
+                    ":user_id",
+                    AttributeValue::S(user_id.to_string()),
+                );
+            }
+
+            match request.send().await {
                 Ok(result) => {
                     if let Some(scanned_items) = result.items {
-                        for item in scanned_items {
-                            match from_item(item) {
-                                Ok(item) => items.push(item),
-                                Err(err) => return OperationResult::InternalError(err.to_string()),
-                            }
+                        if let Err(err) = decode_items(
+                            scanned_items,
+                            self.scan_mode,
+                            self.encryptor.as_deref(),
+                            self.tenant_id.as_deref(),
+                            &mut report,
+                        )
+                        .await
+                        {
+                            return OperationResult::InternalError(err);
                         }
                     }
 
@@ -163,21 +907,32 @@ where
             }
         }
 
-        OperationResult::Success(Some(items))
+        OperationResult::Success(Some(report))
     }
 
     async fn update(&self, item: T) -> OperationResult<T> {
-        let dynamo_item = match to_item(item) {
-            Ok(item) => item,
-            Err(err) => return OperationResult::InternalError(err.to_string()),
-        };
+        let unique_fields = guarded_fields(&item);
+        let dynamo_item =
+            match encode_record(item, self.encryptor.as_deref(), self.tenant_id.as_deref()).await {
+                Ok(item) => item,
+                Err(err) => return OperationResult::InternalError(err),
+            };
+
+        if !T::UNIQUE_FIELDS.is_empty() {
+            return self
+                .update_with_guards(dynamo_item, unique_fields, false)
+                .await;
+        }
 
         match self
             .client
             .put_item()
             .table_name(&self.table_name)
             .set_item(Some(dynamo_item))
-            .condition_expression("attribute_exists(id) AND attribute_not_exists(deleted_at)")
+            .condition_expression(
+                self.condition("attribute_exists(id) AND attribute_not_exists(deleted_at)"),
+            )
+            .set_expression_attribute_values(self.condition_values())
             .send()
             .await
         {
@@ -190,10 +945,16 @@ where
     }
 
     async fn create(&self, item: T) -> OperationResult<T> {
-        let dynamo_item = match to_item(item) {
-            Ok(item) => item,
-            Err(err) => return OperationResult::InternalError(err.to_string()),
-        };
+        let unique_fields = guarded_fields(&item);
+        let dynamo_item =
+            match encode_record(item, self.encryptor.as_deref(), self.tenant_id.as_deref()).await {
+                Ok(item) => item,
+                Err(err) => return OperationResult::InternalError(err),
+            };
+
+        if !unique_fields.is_empty() {
+            return self.create_with_guards(dynamo_item, unique_fields).await;
+        }
 
         match self
             .client
@@ -214,129 +975,651 @@ where
         }
     }
 
-    async fn delete(&self, id: String) -> OperationResult<T> {
-        let key = HashMap::from([("id".to_string(), AttributeValue::S(id))]);
+    async fn delete(&self, id: String) -> OperationResult<T> {
+        let id = AttributeValue::S(self.record_id(&id));
+
+        if !T::UNIQUE_FIELDS.is_empty() {
+            return self.delete_with_guards(id).await;
+        }
+
+        let key = HashMap::from([("id".to_string(), id)]);
+
+        match self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .set_key(Some(key))
+            .condition_expression(self.condition("attribute_exists(id)"))
+            .set_expression_attribute_values(self.condition_values())
+            .send()
+            .await
+        {
+            Ok(_) => OperationResult::Success(None),
+            Err(err) => match err.into_service_error() {
+                DeleteItemError::ConditionalCheckFailedException(_) => {
+                    OperationResult::ItemNotFound
+                }
+                _ => OperationResult::InternalError("Service Error".to_string()),
+            },
+        }
+    }
+
+    async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<T> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs()
+            .to_string();
+
+        if !T::UNIQUE_FIELDS.is_empty() {
+            return self
+                .soft_delete_with_guards(AttributeValue::S(self.record_id(&id)), user_id, now)
+                .await;
+        }
+
+        match self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(self.record_id(&id)))
+            .update_expression("SET deleted_at = :deleted_at, deleted_by = :deleted_by")
+            .condition_expression(
+                self.condition("attribute_exists(id) AND attribute_not_exists(deleted_at)"),
+            )
+            .set_expression_attribute_values(self.condition_values())
+            .expression_attribute_values(":deleted_at", AttributeValue::S(now))
+            .expression_attribute_values(":deleted_by", AttributeValue::S(user_id.to_string()))
+            .send()
+            .await
+        {
+            Ok(_) => OperationResult::Success(None),
+            Err(err) => match err.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => {
+                    OperationResult::ItemNotFound
+                }
+                _ => OperationResult::InternalError("Service Error".to_string()),
+            },
+        }
+    }
+
+    async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<T>> {
+        into_items(self.scan_report(ScanFilter::DeletedBy(user_id)).await)
+    }
+
+    async fn get_deleted_items(&self) -> OperationResult<Vec<T>> {
+        into_items(self.scan_report(ScanFilter::Deleted).await)
+    }
+}
+
+#[async_trait]
+impl<T> CounterOperations for DynamoDbRepository<T>
+where
+    T: Send + Sync,
+{
+    async fn increment(
+        &self,
+        owner_id: String,
+        counter: String,
+        delta: i64,
+        bounds: CounterBounds,
+    ) -> OperationResult<i64> {
+        let key = AttributeValue::S(counter_id(&self.record_id(&owner_id), &counter));
+        let (condition, mut values) = match counter_condition(delta, bounds) {
+            Some(condition) => condition,
+            None => return OperationResult::InvalidInput,
+        };
+        values.insert(":owner".to_string(), AttributeValue::S(owner_id));
+        values.insert(":delta".to_string(), AttributeValue::N(delta.to_string()));
+
+        match self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", key)
+            .update_expression("SET counter_owner = :owner ADD counter_value :delta")
+            .set_condition_expression(condition)
+            .set_expression_attribute_values(Some(values))
+            .return_values(ReturnValue::UpdatedNew)
+            .send()
+            .await
+        {
+            Ok(result) => match counter_value(result.attributes()) {
+                Ok(value) => OperationResult::Success(Some(value)),
+                Err(err) => OperationResult::InternalError(err),
+            },
+            Err(err) => match err.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => {
+                    OperationResult::CounterOutOfBounds
+                }
+                _ => OperationResult::InternalError("Service Error".to_string()),
+            },
+        }
+    }
+
+    async fn get_counter(&self, owner_id: String, counter: String) -> OperationResult<i64> {
+        match self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(
+                "id",
+                AttributeValue::S(counter_id(&self.record_id(&owner_id), &counter)),
+            )
+            .consistent_read(true)
+            .send()
+            .await
+        {
+            Ok(result) => match counter_value(result.item()) {
+                Ok(value) => OperationResult::Success(Some(value)),
+                Err(err) => OperationResult::InternalError(err),
+            },
+            Err(err) => OperationResult::InternalError(err.to_string()),
+        }
+    }
+}
+
+impl<T> DynamoDbRepository<T>
+where
+    T: for<'de> Deserialize<'de> + SoftDeletable + UniqueFields + EncryptedFields,
+{
+    async fn create_with_guards(
+        &self,
+        dynamo_item: HashMap<String, AttributeValue>,
+        unique_fields: Vec<(&'static str, String)>,
+    ) -> OperationResult<T> {
+        self.create_with_steps(dynamo_item, unique_fields, Vec::new())
+            .await
+    }
+
+    async fn create_with_steps(
+        &self,
+        dynamo_item: HashMap<String, AttributeValue>,
+        unique_fields: Vec<(&'static str, String)>,
+        extra_steps: Vec<(TransactionStep, TransactWriteItem)>,
+    ) -> OperationResult<T> {
+        let owner = match dynamo_item.get("id") {
+            Some(id) => id.clone(),
+            None => return OperationResult::InvalidInput,
+        };
+
+        match self.create_steps(dynamo_item, &owner, &unique_fields) {
+            Ok(mut steps) => {
+                steps.extend(extra_steps);
+                self.write_transaction(steps, OperationResult::ItemAlreadyExists)
+                    .await
+            }
+            Err(err) => OperationResult::InternalError(err),
+        }
+    }
+
+    /// With `overwrite` the record is written whether or not it exists or is soft-deleted.
+    async fn update_with_guards(
+        &self,
+        dynamo_item: HashMap<String, AttributeValue>,
+        unique_fields: Vec<(&'static str, String)>,
+        overwrite: bool,
+    ) -> OperationResult<T> {
+        let owner = match dynamo_item.get("id") {
+            Some(id) => id.clone(),
+            None => return OperationResult::InvalidInput,
+        };
+
+        for _ in 0..RECORD_WRITE_ATTEMPTS {
+            let (previous_fields, expected) = match self.fetch_raw(&owner).await {
+                Ok(Some(existing)) => {
+                    let mut fields = T::UNIQUE_FIELDS.to_vec();
+                    fields.push("deleted_at");
+                    let expected = stored_values(&existing, &fields);
+
+                    match decode_record::<T>(
+                        existing,
+                        self.encryptor.as_deref(),
+                        self.tenant_id.as_deref(),
+                    )
+                    .await
+                    {
+                        Ok(existing) if overwrite || existing.get_deleted_at().is_none() => {
+                            (guarded_fields(&existing), Some(expected))
+                        }
+                        Ok(_) => return OperationResult::ItemNotFound,
+                        Err(err) => return OperationResult::InternalError(err),
+                    }
+                }
+                Ok(None) if overwrite => (Vec::new(), None),
+                Ok(None) => return OperationResult::ItemNotFound,
+                Err(err) => return OperationResult::InternalError(err),
+            };
+
+            let steps = match self.update_steps(
+                dynamo_item.clone(),
+                &owner,
+                &unique_fields,
+                &previous_fields,
+                overwrite,
+                expected.as_deref(),
+            ) {
+                Ok(steps) => steps,
+                Err(err) => return OperationResult::InternalError(err),
+            };
+
+            match self
+                .write_transaction(steps, OperationResult::ItemNotFound)
+                .await
+            {
+                // Changed since it was read, or deleted; the next read tells which.
+                OperationResult::ItemNotFound => continue,
+                result => return result,
+            }
+        }
+
+        OperationResult::InternalError("Record changed while being updated".to_string())
+    }
+
+    async fn delete_with_guards(&self, id: AttributeValue) -> OperationResult<T> {
+        let unique_fields = match self.fetch_raw(&id).await {
+            Ok(Some(existing)) => {
+                match decode_record::<T>(
+                    existing,
+                    self.encryptor.as_deref(),
+                    self.tenant_id.as_deref(),
+                )
+                .await
+                {
+                    Ok(existing) => guarded_fields(&existing),
+                    Err(err) => return OperationResult::InternalError(err),
+                }
+            }
+            Ok(None) => return OperationResult::ItemNotFound,
+            Err(err) => return OperationResult::InternalError(err),
+        };
+
+        match self.delete_steps(&id, &unique_fields) {
+            Ok(steps) => {
+                self.write_transaction(steps, OperationResult::ItemNotFound)
+                    .await
+            }
+            Err(err) => OperationResult::InternalError(err),
+        }
+    }
+
+    async fn soft_delete_with_guards(
+        &self,
+        id: AttributeValue,
+        user_id: String,
+        deleted_at: String,
+    ) -> OperationResult<T> {
+        for _ in 0..RECORD_WRITE_ATTEMPTS {
+            let (unique_fields, expected) = match self.fetch_raw(&id).await {
+                Ok(Some(existing)) => {
+                    let expected = stored_values(&existing, T::UNIQUE_FIELDS);
+
+                    match decode_record::<T>(
+                        existing,
+                        self.encryptor.as_deref(),
+                        self.tenant_id.as_deref(),
+                    )
+                    .await
+                    {
+                        Ok(existing) if existing.get_deleted_at().is_none() => {
+                            (existing.unique_fields(), expected)
+                        }
+                        Ok(_) => return OperationResult::ItemNotFound,
+                        Err(err) => return OperationResult::InternalError(err),
+                    }
+                }
+                Ok(None) => return OperationResult::ItemNotFound,
+                Err(err) => return OperationResult::InternalError(err),
+            };
+
+            let steps =
+                match self.soft_delete_steps(&id, &user_id, &deleted_at, &unique_fields, &expected)
+                {
+                    Ok(steps) => steps,
+                    Err(err) => return OperationResult::InternalError(err),
+                };
+
+            match self
+                .write_transaction(steps, OperationResult::ItemNotFound)
+                .await
+            {
+                OperationResult::ItemNotFound => continue,
+                result => return result,
+            }
+        }
+
+        OperationResult::InternalError("Record changed while being deleted".to_string())
+    }
+}
+
+impl<T> DynamoDbRepository<T>
+where
+    T: Serialize
+        + for<'de> Deserialize<'de>
+        + Clone
+        + Send
+        + Sync
+        + 'static
+        + SoftDeletable
+        + UniqueFields
+        + EncryptedFields,
+{
+    /// The active record holding `value` for the unique `field`, found through its guard
+    /// item. `value` must be normalized the same way `unique_fields` normalizes it.
+    pub async fn find_by_unique(&self, field: &str, value: &str) -> OperationResult<T> {
+        let key = HashMap::from([(
+            "id".to_string(),
+            AttributeValue::S(self.record_id(&unique_guard_id(field, value))),
+        )]);
 
         match self
             .client
-            .delete_item()
+            .get_item()
             .table_name(&self.table_name)
             .set_key(Some(key))
-            .condition_expression("attribute_exists(id)")
             .send()
             .await
         {
-            Ok(_) => OperationResult::Success(None),
-            Err(err) => match err.into_service_error() {
-                DeleteItemError::ConditionalCheckFailedException(_) => {
-                    OperationResult::ItemNotFound
+            Ok(result) => match result
+                .item
+                .as_ref()
+                .and_then(|item| item.get("unique_owner"))
+            {
+                Some(AttributeValue::S(owner)) => {
+                    let scope = self.record_id("");
+                    match owner.strip_prefix(&scope) {
+                        Some(id) => self.get_item(id.to_string()).await,
+                        None => OperationResult::ItemNotFound,
+                    }
                 }
-                _ => OperationResult::InternalError("Service Error".to_string()),
+                _ => OperationResult::ItemNotFound,
             },
+            Err(err) => OperationResult::InternalError(err.to_string()),
         }
     }
 
-    async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<T> {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs()
-            .to_string();
+    /// The active records of this tenant whose `attribute` equals `value`, queried through the
+    /// global secondary index `index`, which must be keyed by that attribute.
+    pub async fn find_by_index(
+        &self,
+        index: &str,
+        attribute: &str,
+        value: &str,
+    ) -> OperationResult<Vec<T>> {
+        let mut items = Vec::new();
+        let mut last_evaluated_key = None;
+
+        loop {
+            let result = match self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .index_name(index)
+                .key_condition_expression("#attribute = :value")
+                .expression_attribute_names("#attribute", attribute)
+                .expression_attribute_values(":value", AttributeValue::S(value.to_string()))
+                .set_exclusive_start_key(last_evaluated_key)
+                .send()
+                .await
+            {
+                Ok(result) => result,
+                Err(err) => return OperationResult::InternalError(err.to_string()),
+            };
+
+            for raw_item in result.items.unwrap_or_default() {
+                if !self.owns(&raw_item) {
+                    continue;
+                }
+                match decode_record::<T>(
+                    raw_item,
+                    self.encryptor.as_deref(),
+                    self.tenant_id.as_deref(),
+                )
+                .await
+                {
+                    Ok(item) if item.get_deleted_at().is_none() => items.push(item),
+                    Ok(_) => {}
+                    Err(err) => return OperationResult::InternalError(err),
+                }
+            }
+
+            last_evaluated_key = result.last_evaluated_key;
+            if last_evaluated_key.is_none() {
+                return OperationResult::Success(Some(items));
+            }
+        }
+    }
+
+    /// Creates the record in one transaction with `extra_steps`, such as counter updates that
+    /// must only apply if the record is written.
+    pub(crate) async fn create_with(
+        &self,
+        item: T,
+        extra_steps: Vec<(TransactionStep, TransactWriteItem)>,
+    ) -> OperationResult<T> {
+        let unique_fields = guarded_fields(&item);
+        let dynamo_item =
+            match encode_record(item, self.encryptor.as_deref(), self.tenant_id.as_deref()).await {
+                Ok(item) => item,
+                Err(err) => return OperationResult::InternalError(err),
+            };
+
+        self.create_with_steps(dynamo_item, unique_fields, extra_steps)
+            .await
+    }
+
+    /// Writes the record unconditionally, replacing an existing or soft-deleted one.
+    pub async fn overwrite(&self, item: T) -> OperationResult<T> {
+        let unique_fields = guarded_fields(&item);
+        let dynamo_item =
+            match encode_record(item, self.encryptor.as_deref(), self.tenant_id.as_deref()).await {
+                Ok(item) => item,
+                Err(err) => return OperationResult::InternalError(err),
+            };
+
+        if !T::UNIQUE_FIELDS.is_empty() {
+            return self
+                .update_with_guards(dynamo_item, unique_fields, true)
+                .await;
+        }
 
         match self
             .client
-            .update_item()
+            .put_item()
             .table_name(&self.table_name)
-            .key("id", AttributeValue::S(id.to_string()))
-            .update_expression("SET deleted_at = :deleted_at, deleted_by = :deleted_by")
-            .condition_expression("attribute_exists(id) AND attribute_not_exists(deleted_at)")
-            .expression_attribute_values(":deleted_at", AttributeValue::S(now))
-            .expression_attribute_values(":deleted_by", AttributeValue::S(user_id.to_string()))
+            .set_item(Some(dynamo_item))
             .send()
             .await
         {
             Ok(_) => OperationResult::Success(None),
-            Err(err) => match err.into_service_error() {
-                UpdateItemError::ConditionalCheckFailedException(_) => {
-                    OperationResult::ItemNotFound
-                }
-                _ => OperationResult::InternalError("Service Error".to_string()),
-            },
+            Err(_) => OperationResult::InternalError("Service Error".to_string()),
         }
     }
 
-    async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<T>> {
-        let mut items = Vec::new();
+    /// Every record of the table, soft-deleted ones included. An unscoped repository exports
+    /// the records of all tenants, each tagged with its tenant.
+    pub async fn export(&self) -> OperationResult<Vec<BackupRecord<T>>> {
+        let mut records = Vec::new();
         let mut last_evaluated_key = None;
 
         loop {
-            match self
+            let result = match self
                 .client
                 .scan()
                 .table_name(&self.table_name)
-                .filter_expression("attribute_exists(deleted_at) AND deleted_by = :user_id")
-                .expression_attribute_values(":user_id", AttributeValue::S(user_id.to_string()))
+                .filter_expression(self.condition(ScanFilter::All.expression()))
+                .set_expression_attribute_values(self.condition_values())
                 .set_exclusive_start_key(last_evaluated_key)
                 .send()
                 .await
             {
-                Ok(result) => {
-                    if let Some(scanned_items) = result.items {
-                        for item in scanned_items {
-                            match from_item(item) {
-                                Ok(item) => items.push(item),
-                                Err(err) => return OperationResult::InternalError(err.to_string()),
-                            }
-                        }
-                    }
+                Ok(result) => result,
+                Err(err) => return OperationResult::InternalError(err.to_string()),
+            };
 
-                    last_evaluated_key = result.last_evaluated_key;
+            for raw_item in result.items.unwrap_or_default() {
+                let key = record_key(&raw_item);
+                let tenant_id = self
+                    .tenant_id
+                    .clone()
+                    .or_else(|| tenant::item_tenant(&raw_item));
 
-                    if last_evaluated_key.is_none() {
-                        break;
-                    }
+                match decode_record(raw_item, self.encryptor.as_deref(), tenant_id.as_deref()).await
+                {
+                    Ok(record) => records.push(BackupRecord { tenant_id, record }),
+                    Err(err) => return OperationResult::InternalError(format!("{}: {}", key, err)),
                 }
-                Err(err) => return OperationResult::InternalError(err.to_string()),
+            }
+
+            last_evaluated_key = result.last_evaluated_key;
+
+            if last_evaluated_key.is_none() {
+                break;
             }
         }
-        OperationResult::Success(Some(items))
+
+        OperationResult::Success(Some(records))
     }
 
-    async fn get_deleted_items(&self) -> OperationResult<Vec<T>> {
-        let mut items = Vec::new();
-        let mut last_evaluated_key = None;
+    /// Writes a backed up record into its tenant. `SkipExisting` reports records that are
+    /// already present as `ItemAlreadyExists`.
+    pub async fn restore(&self, backup: BackupRecord<T>, mode: RestoreMode) -> OperationResult<T> {
+        let repository = match backup.tenant_id {
+            Some(tenant_id) => match TenantId::new(tenant_id) {
+                Some(tenant_id) => self.clone().with_tenant(&tenant_id),
+                None => return OperationResult::InvalidInput,
+            },
+            None => self.clone(),
+        };
 
-        loop {
-            match self
-                .client
-                .scan()
-                .table_name(&self.table_name)
-                .filter_expression("attribute_exists(deleted_at)")
-                .set_exclusive_start_key(last_evaluated_key)
-                .send()
-                .await
-            {
-                Ok(result) => {
-                    if let Some(scanned_items) = result.items {
-                        for item in scanned_items {
-                            match from_item(item) {
-                                Ok(item) => items.push(item),
-                                Err(err) => return OperationResult::InternalError(err.to_string()),
-                            }
-                        }
-                    }
+        match mode {
+            RestoreMode::Overwrite => repository.overwrite(backup.record).await,
+            RestoreMode::SkipExisting => repository.create(backup.record).await,
+        }
+    }
+}
 
-                    last_evaluated_key = result.last_evaluated_key;
+impl<T> TenantRepository<dyn DynamoDbOperations<T>> for DynamoDbRepository<T>
+where
+    T: Serialize
+        + for<'de> Deserialize<'de>
+        + Clone
+        + Send
+        + Sync
+        + 'static
+        + SoftDeletable
+        + UniqueFields
+        + EncryptedFields,
+{
+    fn for_tenant(&self, tenant_id: &TenantId) -> Arc<dyn DynamoDbOperations<T>> {
+        Arc::new(self.clone().with_tenant(tenant_id))
+    }
+}
 
-                    if last_evaluated_key.is_none() {
-                        break;
-                    }
+fn into_items<T>(result: OperationResult<ScanReport<T>>) -> OperationResult<Vec<T>> {
+    match result {
+        OperationResult::Success(report) => {
+            OperationResult::Success(Some(report.unwrap_or_default().items))
+        }
+        OperationResult::ItemNotFound => OperationResult::ItemNotFound,
+        OperationResult::ItemAlreadyExists => OperationResult::ItemAlreadyExists,
+        OperationResult::FieldAlreadyExists(field) => OperationResult::FieldAlreadyExists(field),
+        OperationResult::CounterOutOfBounds => OperationResult::CounterOutOfBounds,
+        OperationResult::InvalidInput => OperationResult::InvalidInput,
+        OperationResult::InternalError(err) => OperationResult::InternalError(err),
+    }
+}
+
+fn record_key(item: &HashMap<String, AttributeValue>) -> String {
+    match item.get("id") {
+        Some(AttributeValue::S(id)) => id.clone(),
+        Some(other) => format!("{:?}", other),
+        None => "<missing id>".to_string(),
+    }
+}
+
+async fn encode_record<T>(
+    item: T,
+    encryptor: Option<&FieldEncryptor>,
+    tenant_id: Option<&str>,
+) -> Result<HashMap<String, AttributeValue>, String>
+where
+    T: Serialize + EncryptedFields,
+{
+    let mut dynamo_item = to_item(item).map_err(|err| err.to_string())?;
+
+    if let Some(tenant_id) = tenant_id {
+        tenant::scope_item(tenant_id, &mut dynamo_item);
+    }
+
+    if let Some(encryptor) = encryptor.filter(|_| !T::ENCRYPTED_FIELDS.is_empty()) {
+        encryptor
+            .encrypt_item(&mut dynamo_item, T::ENCRYPTED_FIELDS)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+
+    Ok(dynamo_item)
+}
+
+async fn decode_record<T>(
+    mut raw_item: HashMap<String, AttributeValue>,
+    encryptor: Option<&FieldEncryptor>,
+    tenant_id: Option<&str>,
+) -> Result<T, String>
+where
+    T: for<'de> Deserialize<'de> + EncryptedFields,
+{
+    if let Some(tenant_id) = tenant_id {
+        if !tenant::owns_item(tenant_id, &raw_item) {
+            return Err("Record belongs to another tenant".to_string());
+        }
+    }
+
+    if let Some(encryptor) = encryptor.filter(|_| !T::ENCRYPTED_FIELDS.is_empty()) {
+        encryptor
+            .decrypt_item(&mut raw_item, T::ENCRYPTED_FIELDS)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+
+    if let Some(tenant_id) = tenant_id {
+        tenant::unscope_item(tenant_id, &mut raw_item);
+    }
+
+    from_item(raw_item).map_err(|err| err.to_string())
+}
+
+async fn decode_items<T>(
+    raw_items: Vec<HashMap<String, AttributeValue>>,
+    mode: ScanMode,
+    encryptor: Option<&FieldEncryptor>,
+    tenant_id: Option<&str>,
+    report: &mut ScanReport<T>,
+) -> Result<(), String>
+where
+    T: for<'de> Deserialize<'de> + EncryptedFields,
+{
+    for raw_item in raw_items {
+        let key = record_key(&raw_item);
+
+        match decode_record(raw_item, encryptor, tenant_id).await {
+            Ok(item) => report.items.push(item),
+            Err(err) => {
+                if mode == ScanMode::Strict {
+                    return Err(err);
+                }
+
+                warn!(key = %key, error = %err, "Skipping undecodable record");
+                report.skipped_count += 1;
+
+                if mode == ScanMode::TolerantReported {
+                    report.skipped.push(SkippedRecord { key, error: err });
                 }
-                Err(err) => return OperationResult::InternalError(err.to_string()),
             }
         }
-        OperationResult::Success(Some(items))
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -362,6 +1645,16 @@ mod tests {
         }
     }
 
+    impl EncryptedFields for TestItem {}
+
+    impl UniqueFields for TestItem {
+        const UNIQUE_FIELDS: &'static [&'static str] = &["name"];
+
+        fn unique_fields(&self) -> Vec<(&'static str, String)> {
+            vec![("name", self.name.clone())]
+        }
+    }
+
     mock! {
         pub DynamoDbTestItem {}
 
@@ -373,6 +1666,7 @@ mod tests {
             async fn delete(&self, id: String) -> OperationResult<TestItem>;
             async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<TestItem>;
             async fn scan(&self) -> OperationResult<Vec<TestItem>>;
+            async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<TestItem>>;
             async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<TestItem>>;
             async fn get_deleted_items(&self) -> OperationResult<Vec<TestItem>>;
         }
@@ -709,4 +2003,590 @@ mod tests {
             _ => panic!("Expected Success with empty items"),
         }
     }
+
+    fn raw_test_item(id: &str, age: AttributeValue) -> HashMap<String, AttributeValue> {
+        HashMap::from([
+            ("id".to_string(), AttributeValue::S(id.to_string())),
+            ("name".to_string(), AttributeValue::S("name".to_string())),
+            ("age".to_string(), age),
+        ])
+    }
+
+    fn raw_test_items() -> Vec<HashMap<String, AttributeValue>> {
+        vec![
+            raw_test_item("good_id", AttributeValue::N("30".to_string())),
+            raw_test_item("bad_id", AttributeValue::S("thirty".to_string())),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_decode_items_strict_fails_on_bad_record() {
+        let mut report = ScanReport::<TestItem>::default();
+
+        let result =
+            decode_items(raw_test_items(), ScanMode::Strict, None, None, &mut report).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_items_tolerant_skips_bad_record() {
+        let mut report = ScanReport::<TestItem>::default();
+
+        let result = decode_items(
+            raw_test_items(),
+            ScanMode::Tolerant,
+            None,
+            None,
+            &mut report,
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(report.items.len(), 1);
+        assert_eq!(report.items[0].id, "good_id");
+        assert_eq!(report.skipped_count, 1);
+        assert!(report.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_decode_items_tolerant_reported_lists_bad_record() {
+        let mut report = ScanReport::<TestItem>::default();
+
+        let result = decode_items(
+            raw_test_items(),
+            ScanMode::TolerantReported,
+            None,
+            None,
+            &mut report,
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(report.items.len(), 1);
+        assert_eq!(report.skipped_count, 1);
+        assert_eq!(report.skipped[0].key, "bad_id");
+    }
+
+    fn test_repository() -> DynamoDbRepository<TestItem> {
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(aws_sdk_dynamodb::config::BehaviorVersion::latest())
+            .region(aws_sdk_dynamodb::config::Region::new("us-east-1"))
+            .build();
+
+        DynamoDbRepository {
+            client: Client::from_conf(config),
+            table_name: "test".to_string(),
+            scan_mode: ScanMode::default(),
+            encryptor: None,
+            tenant_id: None,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    fn test_item(id: &str) -> TestItem {
+        TestItem {
+            id: id.to_string(),
+            name: "name".to_string(),
+            age: 30,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    #[test]
+    fn test_tenant_repository_scopes_keys_and_conditions() {
+        let acme = test_repository().with_tenant(&TenantId::new("acme").unwrap());
+        let globex = test_repository().with_tenant(&TenantId::new("globex").unwrap());
+
+        assert_ne!(acme.record_id("1"), globex.record_id("1"));
+        assert_eq!(
+            acme.condition("attribute_exists(id)"),
+            "(attribute_exists(id)) AND tenant_id = :tenant_id"
+        );
+        assert_eq!(
+            acme.condition_values().unwrap()[":tenant_id"],
+            AttributeValue::S("acme".to_string())
+        );
+    }
+
+    #[test]
+    fn test_repository_without_tenant_is_unscoped() {
+        let repository = test_repository();
+
+        assert_eq!(repository.record_id("1"), "1");
+        assert_eq!(
+            repository.condition("attribute_exists(id)"),
+            "attribute_exists(id)"
+        );
+        assert!(repository.condition_values().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_record_round_trip() {
+        let raw = encode_record(test_item("1"), None, Some("acme"))
+            .await
+            .unwrap();
+
+        assert_eq!(raw["id"], AttributeValue::S("TENANT#acme#1".to_string()));
+
+        let item: TestItem = decode_record(raw, None, Some("acme")).await.unwrap();
+        assert_eq!(item.id, "1");
+    }
+
+    #[tokio::test]
+    async fn test_tenant_cannot_read_other_tenants_record() {
+        let raw = encode_record(test_item("1"), None, Some("acme"))
+            .await
+            .unwrap();
+        let globex = test_repository().with_tenant(&TenantId::new("globex").unwrap());
+
+        assert!(!globex.owns(&raw));
+        assert!(decode_record::<TestItem>(raw, None, Some("globex"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_scan_never_returns_other_tenants_records() {
+        let raw_items = vec![
+            encode_record(test_item("1"), None, Some("acme"))
+                .await
+                .unwrap(),
+            encode_record(test_item("2"), None, Some("globex"))
+                .await
+                .unwrap(),
+        ];
+        let mut report = ScanReport::<TestItem>::default();
+
+        decode_items(
+            raw_items,
+            ScanMode::Tolerant,
+            None,
+            Some("acme"),
+            &mut report,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.items.len(), 1);
+        assert_eq!(report.items[0].id, "1");
+    }
+
+    #[test]
+    fn test_unique_guard_id() {
+        assert_eq!(
+            unique_guard_id("email", "someone@example.com"),
+            "UNIQUE#email#someone@example.com"
+        );
+    }
+
+    #[test]
+    fn test_failed_step_points_at_conflicting_guard() {
+        let steps = vec![
+            TransactionStep::Record,
+            TransactionStep::ClaimGuard("email"),
+            TransactionStep::ClaimGuard("username"),
+        ];
+        let reasons = vec![
+            CancellationReason::builder().code("None").build(),
+            CancellationReason::builder().code("None").build(),
+            CancellationReason::builder()
+                .code("ConditionalCheckFailed")
+                .build(),
+        ];
+
+        assert_eq!(
+            failed_step(&steps, &reasons),
+            Some(&TransactionStep::ClaimGuard("username"))
+        );
+    }
+
+    #[test]
+    fn test_failed_step_without_conditional_failure() {
+        let steps = vec![TransactionStep::Record];
+        let reasons = vec![CancellationReason::builder()
+            .code("TransactionConflict")
+            .build()];
+
+        assert_eq!(failed_step(&steps, &reasons), None);
+    }
+
+    fn guard_key(item: &TransactWriteItem) -> String {
+        let key = match (item.put(), item.delete()) {
+            (Some(put), _) => put.item().get("id"),
+            (_, Some(delete)) => delete.key().get("id"),
+            _ => None,
+        };
+
+        match key {
+            Some(AttributeValue::S(id)) => id.clone(),
+            _ => panic!("transaction item without an id"),
+        }
+    }
+
+    fn user_fields(email: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("email", email.to_string()),
+            ("username", "jane".to_string()),
+        ]
+    }
+
+    #[test]
+    fn test_create_steps_put_record_and_claim_guards() {
+        let repository = test_repository().with_tenant(&TenantId::new("acme").unwrap());
+        let owner = AttributeValue::S(repository.record_id("1"));
+        let record = HashMap::from([("id".to_string(), owner.clone())]);
+
+        let steps = repository
+            .create_steps(record, &owner, &user_fields("jane@example.com"))
+            .unwrap();
+
+        let kinds: Vec<_> = steps.iter().map(|(step, _)| step.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TransactionStep::Record,
+                TransactionStep::ClaimGuard("email"),
+                TransactionStep::ClaimGuard("username"),
+            ]
+        );
+
+        let record = steps[0].1.put().unwrap();
+        assert_eq!(
+            record.condition_expression(),
+            Some("attribute_not_exists(id)")
+        );
+
+        let guard = steps[1].1.put().unwrap();
+        assert_eq!(
+            guard_key(&steps[1].1),
+            "TENANT#acme#UNIQUE#email#jane@example.com"
+        );
+        assert_eq!(guard.item().get("unique_owner"), Some(&owner));
+        assert_eq!(
+            guard.condition_expression(),
+            Some("attribute_not_exists(id) OR unique_owner = :owner")
+        );
+        assert_eq!(
+            guard.expression_attribute_values().unwrap().get(":owner"),
+            Some(&owner)
+        );
+    }
+
+    #[test]
+    fn test_update_steps_release_only_changed_values() {
+        let repository = test_repository();
+        let owner = AttributeValue::S("1".to_string());
+        let record = HashMap::from([("id".to_string(), owner.clone())]);
+
+        let steps = repository
+            .update_steps(
+                record,
+                &owner,
+                &user_fields("new@example.com"),
+                &user_fields("old@example.com"),
+                false,
+                Some(&[
+                    (
+                        "email",
+                        Some(AttributeValue::S("old@example.com".to_string())),
+                    ),
+                    ("deleted_at", None),
+                ]),
+            )
+            .unwrap();
+
+        let kinds: Vec<_> = steps.iter().map(|(step, _)| step.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TransactionStep::Record,
+                TransactionStep::ClaimGuard("email"),
+                TransactionStep::ClaimGuard("username"),
+                TransactionStep::ReleaseGuard("email"),
+            ]
+        );
+        let record = steps[0].1.put().unwrap();
+        assert_eq!(
+            record.condition_expression(),
+            Some(
+                "attribute_exists(id) AND attribute_not_exists(deleted_at) \
+                 AND #expected0 = :expected0 AND attribute_not_exists(#expected1)"
+            )
+        );
+        let names = record.expression_attribute_names().unwrap();
+        assert_eq!(names.get("#expected0").map(String::as_str), Some("email"));
+        assert_eq!(
+            names.get("#expected1").map(String::as_str),
+            Some("deleted_at")
+        );
+        assert_eq!(
+            record
+                .expression_attribute_values()
+                .unwrap()
+                .get(":expected0"),
+            Some(&AttributeValue::S("old@example.com".to_string()))
+        );
+        assert_eq!(guard_key(&steps[3].1), "UNIQUE#email#old@example.com");
+        assert_eq!(
+            steps[3].1.delete().unwrap().condition_expression(),
+            Some("attribute_not_exists(id) OR unique_owner = :owner")
+        );
+    }
+
+    #[test]
+    fn test_update_steps_overwrite_with_soft_deleted_record_releases_all_guards() {
+        let repository = test_repository();
+        let owner = AttributeValue::S("1".to_string());
+        let record = HashMap::from([("id".to_string(), owner.clone())]);
+
+        let steps = repository
+            .update_steps(
+                record.clone(),
+                &owner,
+                &[],
+                &user_fields("jane@example.com"),
+                true,
+                Some(&[(
+                    "deleted_at",
+                    Some(AttributeValue::S("1700000000".to_string())),
+                )]),
+            )
+            .unwrap();
+
+        let kinds: Vec<_> = steps.iter().map(|(step, _)| step.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TransactionStep::Record,
+                TransactionStep::ReleaseGuard("email"),
+                TransactionStep::ReleaseGuard("username"),
+            ]
+        );
+        assert_eq!(
+            steps[0].1.put().unwrap().condition_expression(),
+            Some("attribute_exists(id) AND #expected0 = :expected0")
+        );
+
+        let steps = repository
+            .update_steps(record, &owner, &[], &[], true, None)
+            .unwrap();
+
+        let record = steps[0].1.put().unwrap();
+        assert_eq!(
+            record.condition_expression(),
+            Some("attribute_not_exists(id)")
+        );
+        assert!(record.expression_attribute_names().is_none());
+        assert!(record.expression_attribute_values().is_none());
+    }
+
+    #[test]
+    fn test_delete_steps_delete_record_and_release_guards() {
+        let repository = test_repository().with_tenant(&TenantId::new("acme").unwrap());
+        let id = AttributeValue::S(repository.record_id("1"));
+
+        let steps = repository
+            .delete_steps(&id, &user_fields("jane@example.com"))
+            .unwrap();
+
+        let kinds: Vec<_> = steps.iter().map(|(step, _)| step.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TransactionStep::Record,
+                TransactionStep::ReleaseGuard("email"),
+                TransactionStep::ReleaseGuard("username"),
+            ]
+        );
+
+        let record = steps[0].1.delete().unwrap();
+        assert_eq!(guard_key(&steps[0].1), "TENANT#acme#1");
+        assert_eq!(
+            record.condition_expression(),
+            Some("(attribute_exists(id)) AND tenant_id = :tenant_id")
+        );
+        assert_eq!(guard_key(&steps[2].1), "TENANT#acme#UNIQUE#username#jane");
+        assert_eq!(
+            steps[2]
+                .1
+                .delete()
+                .unwrap()
+                .expression_attribute_values()
+                .unwrap()
+                .get(":owner"),
+            Some(&id)
+        );
+    }
+
+    #[test]
+    fn test_soft_delete_steps_release_guards() {
+        let repository = test_repository();
+        let id = AttributeValue::S("1".to_string());
+
+        let steps = repository
+            .soft_delete_steps(
+                &id,
+                "admin",
+                "1700000000",
+                &user_fields("jane@example.com"),
+                &[],
+            )
+            .unwrap();
+
+        let kinds: Vec<_> = steps.iter().map(|(step, _)| step.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TransactionStep::Record,
+                TransactionStep::ReleaseGuard("email"),
+                TransactionStep::ReleaseGuard("username"),
+            ]
+        );
+
+        let record = steps[0].1.update().unwrap();
+        assert_eq!(
+            record.update_expression(),
+            "SET deleted_at = :deleted_at, deleted_by = :deleted_by"
+        );
+        assert_eq!(
+            record.condition_expression(),
+            Some("attribute_exists(id) AND attribute_not_exists(deleted_at)")
+        );
+        assert_eq!(
+            record
+                .expression_attribute_values()
+                .unwrap()
+                .get(":deleted_by"),
+            Some(&AttributeValue::S("admin".to_string()))
+        );
+        assert_eq!(guard_key(&steps[1].1), "UNIQUE#email#jane@example.com");
+    }
+
+    #[test]
+    fn test_soft_delete_steps_check_expected_value() {
+        let repository = test_repository().with_tenant(&TenantId::new("acme").unwrap());
+        let id = AttributeValue::S("TENANT#acme#1".to_string());
+
+        let steps = repository
+            .soft_delete_steps(
+                &id,
+                "admin",
+                "1700000000",
+                &[],
+                &[("admin", Some(AttributeValue::Bool(true))), ("name", None)],
+            )
+            .unwrap();
+
+        let record = steps[0].1.update().unwrap();
+        assert_eq!(
+            record.condition_expression(),
+            Some(
+                "(attribute_exists(id) AND attribute_not_exists(deleted_at) \
+                 AND #expected0 = :expected0 AND attribute_not_exists(#expected1)) \
+                 AND tenant_id = :tenant_id"
+            )
+        );
+        let names = record.expression_attribute_names().unwrap();
+        assert_eq!(names.get("#expected0").map(String::as_str), Some("admin"));
+        assert_eq!(names.get("#expected1").map(String::as_str), Some("name"));
+        let values = record.expression_attribute_values().unwrap();
+        assert_eq!(values.get(":expected0"), Some(&AttributeValue::Bool(true)));
+        assert_eq!(
+            values.get(":tenant_id"),
+            Some(&AttributeValue::S("acme".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_counter_step_keeps_counter_within_bounds() {
+        let repository = test_repository().with_tenant(&TenantId::new("acme").unwrap());
+
+        let (step, item) = repository
+            .counter_step("users", "admin_count", -1, CounterBounds::at_least(1))
+            .unwrap();
+
+        assert_eq!(step, TransactionStep::Counter);
+        let update = item.update().unwrap();
+        assert_eq!(
+            update.key().get("id"),
+            Some(&AttributeValue::S(
+                "COUNTER#TENANT#acme#users#admin_count".to_string()
+            ))
+        );
+        assert_eq!(
+            update.update_expression(),
+            "SET counter_owner = :owner ADD counter_value :delta"
+        );
+        assert_eq!(
+            update.condition_expression(),
+            Some("counter_value >= :lower")
+        );
+        let values = update.expression_attribute_values().unwrap();
+        assert_eq!(
+            values.get(":lower"),
+            Some(&AttributeValue::N("2".to_string()))
+        );
+        assert_eq!(
+            values.get(":delta"),
+            Some(&AttributeValue::N("-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_guarded_fields_empty_for_soft_deleted_record() {
+        let mut item = test_item("1");
+        assert_eq!(guarded_fields(&item), vec![("name", "name".to_string())]);
+
+        item.deleted_at = Some("1700000000".to_string());
+        assert!(guarded_fields(&item).is_empty());
+    }
+
+    #[test]
+    fn test_counter_condition_unbounded() {
+        let (condition, values) = counter_condition(5, CounterBounds::unbounded()).unwrap();
+
+        assert!(condition.is_none());
+        assert!(values.is_empty());
+    }
+
+    #[test]
+    fn test_counter_condition_non_negative_decrement() {
+        let (condition, values) = counter_condition(-1, CounterBounds::non_negative()).unwrap();
+
+        assert_eq!(condition.as_deref(), Some("counter_value >= :lower"));
+        assert_eq!(
+            values.get(":lower"),
+            Some(&AttributeValue::N("1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_counter_condition_allows_missing_counter_within_bounds() {
+        let bounds = CounterBounds::non_negative().with_max(Some(10));
+        let (condition, values) = counter_condition(1, bounds).unwrap();
+
+        assert_eq!(
+            condition.as_deref(),
+            Some(
+                "(attribute_not_exists(counter_value) OR counter_value >= :lower) \
+                 AND (attribute_not_exists(counter_value) OR counter_value <= :upper)"
+            )
+        );
+        assert_eq!(
+            values.get(":upper"),
+            Some(&AttributeValue::N("9".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_counter_condition_overflow() {
+        let bounds = CounterBounds {
+            min: Some(i64::MIN),
+            max: None,
+        };
+
+        assert!(counter_condition(1, bounds).is_none());
+    }
 }
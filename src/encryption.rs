@@ -0,0 +1,596 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use async_trait::async_trait;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::primitives::Blob;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_kms::error::DisplayErrorContext;
+use aws_sdk_kms::primitives::Blob as KmsBlob;
+use aws_sdk_kms::types::DataKeySpec;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::config::KeyProviderConfig;
+
+const ENVELOPE_VERSION: &str = "v1";
+const NONCE_LENGTH: usize = 12;
+const KEY_LENGTH: usize = 32;
+const DATA_KEY_CACHE_LIMIT: usize = 1024;
+
+#[derive(Debug)]
+pub enum EncryptionError {
+    KeyProvider(String),
+    UnknownKey(String),
+    InvalidKey(String),
+    MalformedEnvelope(String),
+    Encrypt,
+    Decrypt,
+    Serialization(String),
+}
+
+impl fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptionError::KeyProvider(err) => write!(f, "Key provider error: {}", err),
+            EncryptionError::UnknownKey(key_id) => write!(f, "Unknown key id: {}", key_id),
+            EncryptionError::InvalidKey(err) => write!(f, "Invalid key: {}", err),
+            EncryptionError::MalformedEnvelope(field) => {
+                write!(f, "Malformed encrypted attribute: {}", field)
+            }
+            EncryptionError::Encrypt => write!(f, "Encryption failed"),
+            EncryptionError::Decrypt => write!(f, "Decryption failed"),
+            EncryptionError::Serialization(err) => write!(f, "Serialization error: {}", err),
+        }
+    }
+}
+
+pub struct DataKey {
+    pub key_id: String,
+    pub plaintext: Vec<u8>,
+    pub encrypted: Vec<u8>,
+}
+
+/// Source of data keys for envelope encryption. `key_id` identifies the wrapping key and is
+/// stored next to every ciphertext, so older key ids stay decryptable after rotation.
+#[async_trait]
+pub trait KeyProvider: Send + Sync {
+    async fn generate_data_key(&self) -> Result<DataKey, EncryptionError>;
+    async fn decrypt_data_key(
+        &self,
+        key_id: &str,
+        encrypted: &[u8],
+    ) -> Result<Vec<u8>, EncryptionError>;
+}
+
+fn seal(key: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+    if key.len() != KEY_LENGTH {
+        return Err(EncryptionError::InvalidKey(format!(
+            "expected {} bytes, got {}",
+            KEY_LENGTH,
+            key.len()
+        )));
+    }
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| EncryptionError::Encrypt)?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+fn open(key: &[u8], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+    if key.len() != KEY_LENGTH {
+        return Err(EncryptionError::InvalidKey(format!(
+            "expected {} bytes, got {}",
+            KEY_LENGTH,
+            key.len()
+        )));
+    }
+    if sealed.len() < NONCE_LENGTH {
+        return Err(EncryptionError::Decrypt);
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| EncryptionError::Decrypt)
+}
+
+#[derive(Deserialize)]
+struct KeyFile {
+    active_key_id: String,
+    keys: HashMap<String, String>,
+}
+
+/// Wraps data keys with AES-256 master keys held in memory, loaded from a JSON key file of
+/// the form `{"active_key_id": "k2", "keys": {"k1": "<base64>", "k2": "<base64>"}}`.
+pub struct LocalKeyProvider {
+    active_key_id: String,
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl LocalKeyProvider {
+    pub fn new(
+        active_key_id: String,
+        keys: HashMap<String, Vec<u8>>,
+    ) -> Result<Self, EncryptionError> {
+        if let Some((key_id, _)) = keys.iter().find(|(_, key)| key.len() != KEY_LENGTH) {
+            return Err(EncryptionError::InvalidKey(key_id.clone()));
+        }
+        if !keys.contains_key(&active_key_id) {
+            return Err(EncryptionError::UnknownKey(active_key_id));
+        }
+
+        Ok(Self {
+            active_key_id,
+            keys,
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, EncryptionError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| EncryptionError::KeyProvider(err.to_string()))?;
+        let key_file: KeyFile = serde_json::from_str(&contents)
+            .map_err(|err| EncryptionError::KeyProvider(err.to_string()))?;
+
+        let mut keys = HashMap::new();
+        for (key_id, key) in key_file.keys {
+            let key = STANDARD
+                .decode(key)
+                .map_err(|_| EncryptionError::InvalidKey(key_id.clone()))?;
+            keys.insert(key_id, key);
+        }
+
+        Self::new(key_file.active_key_id, keys)
+    }
+}
+
+#[async_trait]
+impl KeyProvider for LocalKeyProvider {
+    async fn generate_data_key(&self) -> Result<DataKey, EncryptionError> {
+        let plaintext = Aes256Gcm::generate_key(&mut OsRng).to_vec();
+        let master = &self.keys[&self.active_key_id];
+        let encrypted = seal(master, &plaintext, self.active_key_id.as_bytes())?;
+
+        Ok(DataKey {
+            key_id: self.active_key_id.clone(),
+            plaintext,
+            encrypted,
+        })
+    }
+
+    async fn decrypt_data_key(
+        &self,
+        key_id: &str,
+        encrypted: &[u8],
+    ) -> Result<Vec<u8>, EncryptionError> {
+        match self.keys.get(key_id) {
+            Some(master) => open(master, encrypted, key_id.as_bytes()),
+            None => Err(EncryptionError::UnknownKey(key_id.to_string())),
+        }
+    }
+}
+
+/// Generates and unwraps data keys with AWS KMS (`GenerateDataKey` / `Decrypt`).
+pub struct KmsKeyProvider {
+    client: aws_sdk_kms::Client,
+    key_id: String,
+}
+
+impl KmsKeyProvider {
+    pub fn new(client: aws_sdk_kms::Client, key_id: String) -> Self {
+        Self { client, key_id }
+    }
+
+    fn missing(field: &str) -> EncryptionError {
+        EncryptionError::KeyProvider(format!("KMS response missing {}", field))
+    }
+}
+
+#[async_trait]
+impl KeyProvider for KmsKeyProvider {
+    async fn generate_data_key(&self) -> Result<DataKey, EncryptionError> {
+        let response = self
+            .client
+            .generate_data_key()
+            .key_id(&self.key_id)
+            .key_spec(DataKeySpec::Aes256)
+            .send()
+            .await
+            .map_err(|err| EncryptionError::KeyProvider(DisplayErrorContext(err).to_string()))?;
+
+        Ok(DataKey {
+            key_id: response.key_id().unwrap_or(&self.key_id).to_string(),
+            plaintext: response
+                .plaintext()
+                .ok_or_else(|| Self::missing("Plaintext"))?
+                .as_ref()
+                .to_vec(),
+            encrypted: response
+                .ciphertext_blob()
+                .ok_or_else(|| Self::missing("CiphertextBlob"))?
+                .as_ref()
+                .to_vec(),
+        })
+    }
+
+    async fn decrypt_data_key(
+        &self,
+        key_id: &str,
+        encrypted: &[u8],
+    ) -> Result<Vec<u8>, EncryptionError> {
+        let response = self
+            .client
+            .decrypt()
+            .key_id(key_id)
+            .ciphertext_blob(KmsBlob::new(encrypted))
+            .send()
+            .await
+            .map_err(|err| EncryptionError::KeyProvider(DisplayErrorContext(err).to_string()))?;
+
+        response
+            .plaintext()
+            .map(|plaintext| plaintext.as_ref().to_vec())
+            .ok_or_else(|| Self::missing("Plaintext"))
+    }
+}
+
+type WrappedKey = (String, Vec<u8>);
+
+/// Encrypts selected attributes of a DynamoDB item in place. Every write uses a fresh data key
+/// shared by the record's encrypted attributes; each attribute is stored as a map holding the
+/// key id, the wrapped data key, and the AES-GCM ciphertext bound to the record id and field.
+pub struct FieldEncryptor {
+    provider: Arc<dyn KeyProvider>,
+    data_keys: Mutex<HashMap<WrappedKey, Vec<u8>>>,
+}
+
+impl FieldEncryptor {
+    pub fn new(provider: Arc<dyn KeyProvider>) -> Self {
+        Self {
+            provider,
+            data_keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_config(
+        config: &KeyProviderConfig,
+        sdk_config: &SdkConfig,
+    ) -> Result<Self, EncryptionError> {
+        let provider: Arc<dyn KeyProvider> = match config {
+            KeyProviderConfig::Local { key_file } => {
+                Arc::new(LocalKeyProvider::from_file(key_file)?)
+            }
+            KeyProviderConfig::Kms { key_id } => Arc::new(KmsKeyProvider::new(
+                aws_sdk_kms::Client::new(sdk_config),
+                key_id.clone(),
+            )),
+        };
+
+        Ok(Self::new(provider))
+    }
+
+    pub async fn encrypt_item(
+        &self,
+        item: &mut HashMap<String, AttributeValue>,
+        fields: &[&str],
+    ) -> Result<(), EncryptionError> {
+        let targets: Vec<&str> = fields
+            .iter()
+            .copied()
+            .filter(|field| matches!(item.get(*field), Some(value) if !value.is_null()))
+            .collect();
+
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        let data_key = self.provider.generate_data_key().await?;
+        let record_id = record_id(item);
+
+        for field in targets {
+            let value = match item.remove(field) {
+                Some(value) => value,
+                None => continue,
+            };
+            let plaintext = serde_dynamo::from_attribute_value::<_, serde_json::Value>(value)
+                .map_err(|err| EncryptionError::Serialization(err.to_string()))?;
+            let plaintext = serde_json::to_vec(&plaintext)
+                .map_err(|err| EncryptionError::Serialization(err.to_string()))?;
+            let ciphertext = seal(
+                &data_key.plaintext,
+                &plaintext,
+                associated_data(&record_id, field).as_bytes(),
+            )?;
+
+            item.insert(
+                field.to_string(),
+                AttributeValue::M(HashMap::from([
+                    (
+                        "v".to_string(),
+                        AttributeValue::S(ENVELOPE_VERSION.to_string()),
+                    ),
+                    (
+                        "key_id".to_string(),
+                        AttributeValue::S(data_key.key_id.clone()),
+                    ),
+                    (
+                        "data_key".to_string(),
+                        AttributeValue::B(Blob::new(data_key.encrypted.clone())),
+                    ),
+                    (
+                        "ciphertext".to_string(),
+                        AttributeValue::B(Blob::new(ciphertext)),
+                    ),
+                ])),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Decrypts the given attributes in place. Attributes that are not in envelope form are
+    /// left untouched so records written before encryption was enabled stay readable.
+    pub async fn decrypt_item(
+        &self,
+        item: &mut HashMap<String, AttributeValue>,
+        fields: &[&str],
+    ) -> Result<(), EncryptionError> {
+        let record_id = record_id(item);
+
+        for field in fields {
+            let envelope = match item.get(*field) {
+                Some(AttributeValue::M(envelope)) if envelope.contains_key("ciphertext") => {
+                    envelope
+                }
+                _ => continue,
+            };
+
+            let key_id = envelope_string(envelope, "key_id", field)?;
+            let wrapped_key = envelope_bytes(envelope, "data_key", field)?;
+            let ciphertext = envelope_bytes(envelope, "ciphertext", field)?;
+
+            let data_key = self.data_key(key_id, wrapped_key).await?;
+            let plaintext = open(
+                &data_key,
+                &ciphertext,
+                associated_data(&record_id, field).as_bytes(),
+            )?;
+            let plaintext: serde_json::Value = serde_json::from_slice(&plaintext)
+                .map_err(|err| EncryptionError::Serialization(err.to_string()))?;
+            let value: AttributeValue = serde_dynamo::to_attribute_value(plaintext)
+                .map_err(|err| EncryptionError::Serialization(err.to_string()))?;
+
+            item.insert(field.to_string(), value);
+        }
+
+        Ok(())
+    }
+
+    async fn data_key(
+        &self,
+        key_id: String,
+        wrapped_key: Vec<u8>,
+    ) -> Result<Vec<u8>, EncryptionError> {
+        let cache_key = (key_id, wrapped_key);
+
+        if let Some(data_key) = self.data_keys.lock().unwrap().get(&cache_key) {
+            return Ok(data_key.clone());
+        }
+
+        let data_key = self
+            .provider
+            .decrypt_data_key(&cache_key.0, &cache_key.1)
+            .await?;
+
+        let mut data_keys = self.data_keys.lock().unwrap();
+        if data_keys.len() >= DATA_KEY_CACHE_LIMIT {
+            data_keys.clear();
+        }
+        data_keys.insert(cache_key, data_key.clone());
+
+        Ok(data_key)
+    }
+}
+
+fn record_id(item: &HashMap<String, AttributeValue>) -> String {
+    match item.get("id") {
+        Some(AttributeValue::S(id)) => id.clone(),
+        _ => String::new(),
+    }
+}
+
+fn associated_data(record_id: &str, field: &str) -> String {
+    format!("{}#{}", record_id, field)
+}
+
+fn envelope_string(
+    envelope: &HashMap<String, AttributeValue>,
+    name: &str,
+    field: &str,
+) -> Result<String, EncryptionError> {
+    match envelope.get(name) {
+        Some(AttributeValue::S(value)) => Ok(value.clone()),
+        _ => Err(EncryptionError::MalformedEnvelope(field.to_string())),
+    }
+}
+
+fn envelope_bytes(
+    envelope: &HashMap<String, AttributeValue>,
+    name: &str,
+    field: &str,
+) -> Result<Vec<u8>, EncryptionError> {
+    match envelope.get(name) {
+        Some(AttributeValue::B(value)) => Ok(value.as_ref().to_vec()),
+        _ => Err(EncryptionError::MalformedEnvelope(field.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn local_provider(active_key_id: &str) -> LocalKeyProvider {
+        let keys = HashMap::from([
+            ("k1".to_string(), vec![1u8; KEY_LENGTH]),
+            ("k2".to_string(), vec![2u8; KEY_LENGTH]),
+        ]);
+        LocalKeyProvider::new(active_key_id.to_string(), keys).unwrap()
+    }
+
+    fn test_record() -> HashMap<String, AttributeValue> {
+        HashMap::from([
+            ("id".to_string(), AttributeValue::S("user_1".to_string())),
+            (
+                "password_hash".to_string(),
+                AttributeValue::S("secret-hash".to_string()),
+            ),
+            (
+                "email".to_string(),
+                AttributeValue::S("someone@example.com".to_string()),
+            ),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_decrypt_roundtrip() {
+        let encryptor = FieldEncryptor::new(Arc::new(local_provider("k1")));
+        let mut record = test_record();
+
+        encryptor
+            .encrypt_item(&mut record, &["password_hash"])
+            .await
+            .unwrap();
+        assert!(matches!(
+            record.get("password_hash"),
+            Some(AttributeValue::M(_))
+        ));
+        assert_eq!(
+            record.get("email"),
+            Some(&AttributeValue::S("someone@example.com".to_string()))
+        );
+
+        encryptor
+            .decrypt_item(&mut record, &["password_hash"])
+            .await
+            .unwrap();
+        assert_eq!(
+            record.get("password_hash"),
+            Some(&AttributeValue::S("secret-hash".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decrypt_after_key_rotation() {
+        let mut record = test_record();
+        FieldEncryptor::new(Arc::new(local_provider("k1")))
+            .encrypt_item(&mut record, &["password_hash"])
+            .await
+            .unwrap();
+
+        let rotated = FieldEncryptor::new(Arc::new(local_provider("k2")));
+        rotated
+            .decrypt_item(&mut record, &["password_hash"])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            record.get("password_hash"),
+            Some(&AttributeValue::S("secret-hash".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ciphertext_is_bound_to_record() {
+        let encryptor = FieldEncryptor::new(Arc::new(local_provider("k1")));
+        let mut record = test_record();
+        encryptor
+            .encrypt_item(&mut record, &["password_hash"])
+            .await
+            .unwrap();
+
+        record.insert("id".to_string(), AttributeValue::S("user_2".to_string()));
+        let result = encryptor
+            .decrypt_item(&mut record, &["password_hash"])
+            .await;
+
+        assert!(matches!(result, Err(EncryptionError::Decrypt)));
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_and_null_values_pass_through() {
+        let encryptor = FieldEncryptor::new(Arc::new(local_provider("k1")));
+        let mut record = test_record();
+        record.insert("password_hash".to_string(), AttributeValue::Null(true));
+
+        encryptor
+            .encrypt_item(&mut record, &["password_hash"])
+            .await
+            .unwrap();
+        assert_eq!(
+            record.get("password_hash"),
+            Some(&AttributeValue::Null(true))
+        );
+
+        encryptor
+            .decrypt_item(&mut record, &["email"])
+            .await
+            .unwrap();
+        assert_eq!(
+            record.get("email"),
+            Some(&AttributeValue::S("someone@example.com".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_local_key_provider_from_file() {
+        let path = std::env::temp_dir().join(format!("keys-{}.json", uuid::Uuid::new_v4()));
+        let key_file = json!({
+            "active_key_id": "k1",
+            "keys": { "k1": STANDARD.encode([7u8; KEY_LENGTH]) }
+        });
+        std::fs::write(&path, key_file.to_string()).unwrap();
+
+        let provider = LocalKeyProvider::from_file(path.to_str().unwrap()).unwrap();
+        let data_key = provider.generate_data_key().await.unwrap();
+        let unwrapped = provider
+            .decrypt_data_key(&data_key.key_id, &data_key.encrypted)
+            .await
+            .unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data_key.key_id, "k1");
+        assert_eq!(unwrapped, data_key.plaintext);
+    }
+
+    #[test]
+    fn test_local_key_provider_rejects_unknown_active_key() {
+        let keys = HashMap::from([("k1".to_string(), vec![1u8; KEY_LENGTH])]);
+
+        let result = LocalKeyProvider::new("missing".to_string(), keys);
+
+        assert!(matches!(result, Err(EncryptionError::UnknownKey(_))));
+    }
+}
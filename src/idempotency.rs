@@ -0,0 +1,793 @@
+use async_trait::async_trait;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::primitives::Blob;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderName, Method};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use reqwest::StatusCode;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::auth::principal::Principal;
+use crate::schema::TableSchema;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+pub const IDEMPOTENCY_TABLE_SCHEMA: TableSchema = TableSchema::keyed_by_id().with_ttl("ttl");
+
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+const IN_PROGRESS_LOCK: Duration = Duration::from_secs(60);
+
+/// Headers that describe one connection rather than the response, plus `content-length`,
+/// which is set again for the replayed body.
+const UNSTORED_HEADERS: [HeaderName; 9] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+    header::CONTENT_LENGTH,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status: u16,
+    /// Response headers in order, repeated names included.
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Vec<u8>,
+}
+
+impl StoredResponse {
+    fn stored_headers(headers: &HeaderMap) -> Vec<(String, Vec<u8>)> {
+        headers
+            .iter()
+            .filter(|(name, _)| !UNSTORED_HEADERS.contains(name))
+            .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BeginOutcome {
+    Started,
+    InProgress {
+        fingerprint: String,
+    },
+    Completed {
+        fingerprint: String,
+        response: StoredResponse,
+    },
+}
+
+/// Persists request fingerprints and their responses under an idempotency key. `begin` claims
+/// the key (or reports what is already stored for it), `complete` records the response to
+/// replay, and `abandon` releases the claim so a failed request can be retried.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    async fn begin(&self, key: &str, fingerprint: &str) -> Result<BeginOutcome, String>;
+    async fn complete(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        response: StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), String>;
+    async fn abandon(&self, key: &str) -> Result<(), String>;
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+#[derive(Clone)]
+struct MemoryRecord {
+    fingerprint: String,
+    response: Option<StoredResponse>,
+    expires_at: u64,
+}
+
+#[derive(Default)]
+pub struct InMemoryIdempotencyStore {
+    records: Mutex<HashMap<String, MemoryRecord>>,
+}
+
+impl InMemoryIdempotencyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for InMemoryIdempotencyStore {
+    async fn begin(&self, key: &str, fingerprint: &str) -> Result<BeginOutcome, String> {
+        let mut records = self.records.lock().unwrap();
+        let now = now();
+
+        match records.get(key) {
+            Some(record) if record.expires_at > now => match &record.response {
+                Some(response) => Ok(BeginOutcome::Completed {
+                    fingerprint: record.fingerprint.clone(),
+                    response: response.clone(),
+                }),
+                None => Ok(BeginOutcome::InProgress {
+                    fingerprint: record.fingerprint.clone(),
+                }),
+            },
+            _ => {
+                records.insert(
+                    key.to_string(),
+                    MemoryRecord {
+                        fingerprint: fingerprint.to_string(),
+                        response: None,
+                        expires_at: now + IN_PROGRESS_LOCK.as_secs(),
+                    },
+                );
+                Ok(BeginOutcome::Started)
+            }
+        }
+    }
+
+    async fn complete(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        response: StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), String> {
+        self.records.lock().unwrap().insert(
+            key.to_string(),
+            MemoryRecord {
+                fingerprint: fingerprint.to_string(),
+                response: Some(response),
+                expires_at: now() + ttl.as_secs(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn abandon(&self, key: &str) -> Result<(), String> {
+        self.records.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// Stores idempotency records in a DynamoDB table keyed by `id`, with `ttl` as the table's
+/// time-to-live attribute.
+#[derive(Clone)]
+pub struct DynamoDbIdempotencyStore {
+    pub client: Client,
+    pub table_name: String,
+}
+
+impl DynamoDbIdempotencyStore {
+    pub fn from_client(client: Client, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+
+    fn parse_outcome(item: &HashMap<String, AttributeValue>) -> Result<BeginOutcome, String> {
+        let fingerprint = match item.get("fingerprint") {
+            Some(AttributeValue::S(fingerprint)) => fingerprint.clone(),
+            _ => return Err("Idempotency record without fingerprint".to_string()),
+        };
+
+        let status = match item.get("status") {
+            Some(AttributeValue::N(status)) => status.parse::<u16>().map_err(|e| e.to_string())?,
+            _ => return Ok(BeginOutcome::InProgress { fingerprint }),
+        };
+
+        let headers = match (item.get("headers"), item.get("content_type")) {
+            (Some(AttributeValue::L(headers)), _) => headers
+                .iter()
+                .map(|header| match header.as_l().map(Vec::as_slice) {
+                    Ok([AttributeValue::S(name), AttributeValue::B(value)]) => {
+                        Ok((name.clone(), value.as_ref().to_vec()))
+                    }
+                    _ => Err("Malformed stored response header".to_string()),
+                })
+                .collect::<Result<_, _>>()?,
+            // Written before all headers were stored.
+            (None, Some(AttributeValue::S(content_type))) => vec![(
+                header::CONTENT_TYPE.as_str().to_string(),
+                content_type.as_bytes().to_vec(),
+            )],
+            _ => Vec::new(),
+        };
+
+        let body = match item.get("body") {
+            Some(AttributeValue::B(body)) => body.as_ref().to_vec(),
+            _ => Vec::new(),
+        };
+
+        Ok(BeginOutcome::Completed {
+            fingerprint,
+            response: StoredResponse {
+                status,
+                headers,
+                body,
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for DynamoDbIdempotencyStore {
+    async fn begin(&self, key: &str, fingerprint: &str) -> Result<BeginOutcome, String> {
+        let now = now();
+        let expires_at = (now + IN_PROGRESS_LOCK.as_secs()).to_string();
+
+        match self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("id", AttributeValue::S(key.to_string()))
+            .item("fingerprint", AttributeValue::S(fingerprint.to_string()))
+            .item("ttl", AttributeValue::N(expires_at))
+            .condition_expression("attribute_not_exists(id) OR #ttl < :now")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await
+        {
+            Ok(_) => Ok(BeginOutcome::Started),
+            Err(err) => match err.into_service_error() {
+                PutItemError::ConditionalCheckFailedException(_) => {
+                    let existing = self
+                        .client
+                        .get_item()
+                        .table_name(&self.table_name)
+                        .key("id", AttributeValue::S(key.to_string()))
+                        .consistent_read(true)
+                        .send()
+                        .await
+                        .map_err(|err| err.to_string())?;
+
+                    match existing.item() {
+                        Some(item) => Self::parse_outcome(item),
+                        None => Err("Idempotency record disappeared".to_string()),
+                    }
+                }
+                err => Err(err.to_string()),
+            },
+        }
+    }
+
+    async fn complete(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        response: StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), String> {
+        let headers = response
+            .headers
+            .into_iter()
+            .map(|(name, value)| {
+                AttributeValue::L(vec![
+                    AttributeValue::S(name),
+                    AttributeValue::B(Blob::new(value)),
+                ])
+            })
+            .collect();
+
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("id", AttributeValue::S(key.to_string()))
+            .item("fingerprint", AttributeValue::S(fingerprint.to_string()))
+            .item("status", AttributeValue::N(response.status.to_string()))
+            .item("headers", AttributeValue::L(headers))
+            .item("body", AttributeValue::B(Blob::new(response.body)))
+            .item(
+                "ttl",
+                AttributeValue::N((now() + ttl.as_secs()).to_string()),
+            )
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    async fn abandon(&self, key: &str) -> Result<(), String> {
+        self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(key.to_string()))
+            .condition_expression("attribute_not_exists(#status)")
+            .expression_attribute_names("#status", "status")
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct Idempotency {
+    pub store: Arc<dyn IdempotencyStore>,
+    pub ttl: Duration,
+}
+
+impl Idempotency {
+    pub fn new(store: Arc<dyn IdempotencyStore>, ttl: Duration) -> Self {
+        Self { store, ttl }
+    }
+}
+
+fn is_mutating(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    )
+}
+
+fn hash(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+fn replay(response: StoredResponse) -> Response {
+    let mut builder = Response::builder().status(response.status);
+
+    for (name, value) in response.headers {
+        builder = builder.header(name, value);
+    }
+
+    builder
+        .header(IDEMPOTENT_REPLAYED_HEADER, "true")
+        .body(Body::from(response.body))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+/// Honors an `Idempotency-Key` header on mutating requests: the first request runs and its
+/// response is stored, retries with the same key and body replay it, and reusing a key for a
+/// different request is rejected with 422. Keys are scoped to the authenticated caller, its
+/// tenant and subject, so they hold across fresh signatures and refreshed tokens; it runs
+/// inside the auth layers and lets requests without a caller through untouched.
+fn is_no_store(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"))
+}
+
+pub async fn idempotency_middleware(
+    State(state): State<Idempotency>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !is_mutating(request.method()) {
+        return next.run(request).await;
+    }
+
+    let caller = match request.extensions().get::<Principal>() {
+        Some(principal) => (
+            principal
+                .tenant_id
+                .as_ref()
+                .map(|tenant_id| tenant_id.as_str().to_string())
+                .unwrap_or_default(),
+            principal.subject.clone(),
+        ),
+        None => return next.run(request).await,
+    };
+
+    let key = match request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(key) if !key.is_empty() && key.len() <= 255 => key.to_string(),
+        Some(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid Idempotency-Key"),
+        None => return next.run(request).await,
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(_) => return error_response(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large"),
+    };
+
+    let (tenant_id, subject) = caller;
+    let scoped_key = hash(&[tenant_id.as_bytes(), subject.as_bytes(), key.as_bytes()]);
+    let fingerprint = hash(&[
+        parts.method.as_str().as_bytes(),
+        parts.uri.path().as_bytes(),
+        parts.uri.query().unwrap_or_default().as_bytes(),
+        &body,
+    ]);
+
+    match state.store.begin(&scoped_key, &fingerprint).await {
+        Ok(BeginOutcome::Started) => {}
+        Ok(BeginOutcome::Completed {
+            fingerprint: stored,
+            response,
+        }) => {
+            if stored == fingerprint {
+                return replay(response);
+            }
+            return error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used with a different request",
+            );
+        }
+        Ok(BeginOutcome::InProgress {
+            fingerprint: stored,
+        }) => {
+            if stored == fingerprint {
+                return error_response(
+                    StatusCode::CONFLICT,
+                    "A request with this Idempotency-Key is still in progress",
+                );
+            }
+            return error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used with a different request",
+            );
+        }
+        Err(err) => {
+            warn!(error = %err, "Idempotency store unavailable");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Service Error");
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    // Responses marked `no-store`, such as those carrying a secret, are never kept for replay;
+    // the key is released and a retry runs the request again.
+    if response.status().is_server_error() || is_no_store(response.headers()) {
+        if let Err(err) = state.store.abandon(&scoped_key).await {
+            warn!(error = %err, "Failed to release idempotency key");
+        }
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let stored = StoredResponse {
+        status: parts.status.as_u16(),
+        headers: StoredResponse::stored_headers(&parts.headers),
+        body: body.to_vec(),
+    };
+
+    if let Err(err) = state
+        .store
+        .complete(&scoped_key, &fingerprint, stored, state.ttl)
+        .await
+    {
+        warn!(error = %err, "Failed to store idempotent response");
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::signing::{authenticate_signed, HmacAuth, RequestSigner};
+    use crate::config::{AuthMethod, HmacClient, HmacConfig};
+    use crate::rate_limit::InMemoryRateLimitStore;
+    use crate::tenant::TenantId;
+    use axum::http::Uri;
+    use axum::middleware::from_fn_with_state;
+    use axum::response::AppendHeaders;
+    use axum::routing::post;
+    use axum::{Extension, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn principal(tenant_id: &str, subject: &str) -> Principal {
+        Principal {
+            tenant_id: TenantId::new(tenant_id),
+            ..Principal::new(subject, AuthMethod::Secret)
+        }
+    }
+
+    fn routes(calls: Arc<AtomicUsize>) -> Router {
+        let idempotency = Idempotency::new(
+            Arc::new(InMemoryIdempotencyStore::new()),
+            Duration::from_secs(3600),
+        );
+        let secret_calls = calls.clone();
+
+        Router::new()
+            .route(
+                "/foo",
+                post(move || {
+                    let calls = calls.clone();
+                    async move {
+                        let call = calls.fetch_add(1, Ordering::SeqCst);
+                        (
+                            StatusCode::CREATED,
+                            AppendHeaders([
+                                (header::LOCATION, format!("/foo/{call}")),
+                                (header::SET_COOKIE, "a=1".to_string()),
+                                (header::SET_COOKIE, "b=2".to_string()),
+                            ]),
+                            Json(json!({ "item_id": call })),
+                        )
+                    }
+                }),
+            )
+            .route(
+                "/secret",
+                post(move || {
+                    let calls = secret_calls.clone();
+                    async move {
+                        let call = calls.fetch_add(1, Ordering::SeqCst);
+                        (
+                            StatusCode::CREATED,
+                            [(header::CACHE_CONTROL, "no-store")],
+                            Json(json!({ "key": format!("secret-{call}") })),
+                        )
+                    }
+                }),
+            )
+            .route_layer(from_fn_with_state(idempotency, idempotency_middleware))
+    }
+
+    fn test_app(calls: Arc<AtomicUsize>) -> Router {
+        routes(calls).layer(Extension(principal("acme", "user123")))
+    }
+
+    fn post_request(key: Option<&str>, body: &str) -> Request {
+        let mut builder = Request::builder()
+            .method(Method::POST)
+            .uri("/foo")
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(key) = key {
+            builder = builder.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+        builder.body(Body::from(body.to_string())).unwrap()
+    }
+
+    async fn body_string(response: Response) -> String {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retry_replays_original_response() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = test_app(calls.clone());
+
+        let first = app
+            .clone()
+            .oneshot(post_request(Some("key-1"), r#"{"name":"a"}"#))
+            .await
+            .unwrap();
+        assert_eq!(first.status(), StatusCode::CREATED);
+        let first_body = body_string(first).await;
+
+        let retry = app
+            .oneshot(post_request(Some("key-1"), r#"{"name":"a"}"#))
+            .await
+            .unwrap();
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(
+            retry.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(),
+            "true"
+        );
+        assert_eq!(body_string(retry).await, first_body);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replay_restores_response_headers() {
+        let app = test_app(Arc::new(AtomicUsize::new(0)));
+
+        app.clone()
+            .oneshot(post_request(Some("key-1"), r#"{"name":"a"}"#))
+            .await
+            .unwrap();
+        let retry = app
+            .oneshot(post_request(Some("key-1"), r#"{"name":"a"}"#))
+            .await
+            .unwrap();
+
+        let headers = retry.headers();
+        assert_eq!(headers.get(header::LOCATION).unwrap(), "/foo/0");
+        assert_eq!(
+            headers.get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        let cookies: Vec<_> = headers.get_all(header::SET_COOKIE).iter().collect();
+        assert_eq!(cookies, vec!["a=1", "b=2"]);
+    }
+
+    #[test]
+    fn test_hop_by_hop_headers_are_not_stored() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, "keep-alive".parse().unwrap());
+        headers.insert(header::TRANSFER_ENCODING, "chunked".parse().unwrap());
+        headers.insert(header::CONTENT_LENGTH, "2".parse().unwrap());
+        headers.insert(header::LOCATION, "/foo/1".parse().unwrap());
+
+        assert_eq!(
+            StoredResponse::stored_headers(&headers),
+            vec![("location".to_string(), b"/foo/1".to_vec())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_store_responses_are_not_replayed() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = test_app(calls.clone());
+        let request = || {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/secret")
+                .header(IDEMPOTENCY_KEY_HEADER, "key-1")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let first = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(body_string(first).await, r#"{"key":"secret-0"}"#);
+
+        let retry = app.oneshot(request()).await.unwrap();
+        assert!(retry.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+        assert_eq!(body_string(retry).await, r#"{"key":"secret-1"}"#);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_scoped_to_the_caller() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = routes(calls.clone());
+
+        for caller in [
+            principal("acme", "user123"),
+            principal("acme", "user456"),
+            principal("globex", "user123"),
+        ] {
+            let response = app
+                .clone()
+                .layer(Extension(caller))
+                .oneshot(post_request(Some("key-1"), r#"{"name":"a"}"#))
+                .await
+                .unwrap();
+            assert!(response.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_caller_are_not_deduplicated() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = routes(calls.clone());
+
+        for _ in 0..2 {
+            app.clone()
+                .oneshot(post_request(Some("key-1"), r#"{"name":"a"}"#))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_signed_retry_with_new_signature_replays() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let hmac = HmacAuth::new(
+            HmacConfig {
+                clients: vec![HmacClient {
+                    id: "partner".to_string(),
+                    tenant_id: TenantId::new("acme").unwrap(),
+                    secret: SECRET.to_string(),
+                }],
+                scopes: vec!["items:write".to_string()],
+                max_skew_seconds: 300,
+            },
+            Arc::new(InMemoryRateLimitStore::new()),
+        );
+        let app = routes(calls.clone()).route_layer(from_fn_with_state(hmac, authenticate_signed));
+        let signer = RequestSigner::new("partner", SECRET);
+        let uri: Uri = "/foo".parse().unwrap();
+        let body = r#"{"name":"a"}"#;
+
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            // Each attempt is signed afresh, with its own nonce and signature.
+            let mut request = post_request(Some("key-1"), body);
+            request
+                .headers_mut()
+                .extend(signer.sign(&Method::POST, &uri, body.as_bytes()));
+            responses.push(app.clone().oneshot(request).await.unwrap());
+        }
+
+        assert_eq!(responses[0].status(), StatusCode::CREATED);
+        assert_eq!(responses[1].status(), StatusCode::CREATED);
+        assert_eq!(
+            responses[1]
+                .headers()
+                .get(IDEMPOTENT_REPLAYED_HEADER)
+                .unwrap(),
+            "true"
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reused_key_with_different_body_is_rejected() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = test_app(calls.clone());
+
+        app.clone()
+            .oneshot(post_request(Some("key-1"), r#"{"name":"a"}"#))
+            .await
+            .unwrap();
+        let reused = app
+            .oneshot(post_request(Some("key-1"), r#"{"name":"b"}"#))
+            .await
+            .unwrap();
+
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_key_are_not_deduplicated() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = test_app(calls.clone());
+
+        app.clone()
+            .oneshot(post_request(None, r#"{"name":"a"}"#))
+            .await
+            .unwrap();
+        app.oneshot(post_request(None, r#"{"name":"a"}"#))
+            .await
+            .unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store_reports_in_progress() {
+        let store = InMemoryIdempotencyStore::new();
+
+        assert_eq!(store.begin("key", "a").await, Ok(BeginOutcome::Started));
+        assert_eq!(
+            store.begin("key", "a").await,
+            Ok(BeginOutcome::InProgress {
+                fingerprint: "a".to_string()
+            })
+        );
+
+        store.abandon("key").await.unwrap();
+        assert_eq!(store.begin("key", "a").await, Ok(BeginOutcome::Started));
+    }
+}
@@ -0,0 +1,556 @@
+use async_trait::async_trait;
+use aws_sdk_dynamodb::operation::delete_item::DeleteItemError;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::schema::TableSchema;
+
+pub const LEASE_PREFIX: &str = "LEASE#";
+
+pub const LEASE_TABLE_SCHEMA: TableSchema = TableSchema::keyed_by_id().with_ttl("ttl");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub name: String,
+    pub owner: String,
+    pub token: String,
+    pub expires_at: u64,
+}
+
+impl Lease {
+    pub fn is_expired_at(&self, now_millis: u64) -> bool {
+        self.expires_at <= now_millis
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LeaseError {
+    Lost,
+    Store(String),
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeaseError::Lost => write!(f, "Lease is no longer held"),
+            LeaseError::Store(err) => write!(f, "Lease store error: {}", err),
+        }
+    }
+}
+
+/// Time-bounded exclusive ownership of a named resource. `acquire` succeeds when the lease is
+/// free or its holder let it expire (takeover) and returns `None` while someone else holds it.
+/// Every acquisition gets a fresh token, so a holder that was taken over cannot renew or
+/// release the new holder's lease.
+#[async_trait]
+pub trait LeaseStore: Send + Sync {
+    async fn acquire(
+        &self,
+        name: &str,
+        owner: &str,
+        duration: Duration,
+    ) -> Result<Option<Lease>, LeaseError>;
+    async fn renew(&self, lease: &Lease, duration: Duration) -> Result<Lease, LeaseError>;
+    async fn release(&self, lease: &Lease) -> Result<(), LeaseError>;
+
+    /// The time leases expire against, in epoch milliseconds.
+    fn now_millis(&self) -> u64 {
+        now_millis()
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+fn new_lease(name: &str, owner: &str, duration: Duration, now_millis: u64) -> Lease {
+    Lease {
+        name: name.to_string(),
+        owner: owner.to_string(),
+        token: Uuid::new_v4().to_string(),
+        expires_at: now_millis + duration.as_millis() as u64,
+    }
+}
+
+type Clock = Box<dyn Fn() -> u64 + Send + Sync>;
+
+pub struct InMemoryLeaseStore {
+    leases: Mutex<HashMap<String, Lease>>,
+    clock: Clock,
+}
+
+impl Default for InMemoryLeaseStore {
+    fn default() -> Self {
+        Self::with_clock(now_millis)
+    }
+}
+
+impl InMemoryLeaseStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store whose leases expire against `clock`, in epoch milliseconds, instead of the
+    /// system time.
+    pub fn with_clock(clock: impl Fn() -> u64 + Send + Sync + 'static) -> Self {
+        Self {
+            leases: Mutex::new(HashMap::new()),
+            clock: Box::new(clock),
+        }
+    }
+}
+
+#[async_trait]
+impl LeaseStore for InMemoryLeaseStore {
+    async fn acquire(
+        &self,
+        name: &str,
+        owner: &str,
+        duration: Duration,
+    ) -> Result<Option<Lease>, LeaseError> {
+        let now = self.now_millis();
+        let mut leases = self.leases.lock().unwrap();
+
+        match leases.get(name) {
+            Some(current) if !current.is_expired_at(now) => Ok(None),
+            _ => {
+                let lease = new_lease(name, owner, duration, now);
+                leases.insert(name.to_string(), lease.clone());
+                Ok(Some(lease))
+            }
+        }
+    }
+
+    async fn renew(&self, lease: &Lease, duration: Duration) -> Result<Lease, LeaseError> {
+        let now = self.now_millis();
+        let mut leases = self.leases.lock().unwrap();
+
+        match leases.get_mut(&lease.name) {
+            Some(current) if current.token == lease.token && !current.is_expired_at(now) => {
+                current.expires_at = now + duration.as_millis() as u64;
+                Ok(current.clone())
+            }
+            _ => Err(LeaseError::Lost),
+        }
+    }
+
+    async fn release(&self, lease: &Lease) -> Result<(), LeaseError> {
+        let mut leases = self.leases.lock().unwrap();
+
+        match leases.get(&lease.name) {
+            Some(current) if current.token == lease.token => {
+                leases.remove(&lease.name);
+                Ok(())
+            }
+            _ => Err(LeaseError::Lost),
+        }
+    }
+
+    fn now_millis(&self) -> u64 {
+        (self.clock)()
+    }
+}
+
+/// Leases stored as `LEASE#<name>` items. `expires_at` holds epoch milliseconds and `ttl`
+/// epoch seconds so DynamoDB's time-to-live can clean up abandoned leases.
+#[derive(Clone)]
+pub struct DynamoDbLeaseStore {
+    pub client: Client,
+    pub table_name: String,
+}
+
+impl DynamoDbLeaseStore {
+    pub fn from_client(client: Client, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+
+    fn key(name: &str) -> AttributeValue {
+        AttributeValue::S(format!("{}{}", LEASE_PREFIX, name))
+    }
+}
+
+#[async_trait]
+impl LeaseStore for DynamoDbLeaseStore {
+    async fn acquire(
+        &self,
+        name: &str,
+        owner: &str,
+        duration: Duration,
+    ) -> Result<Option<Lease>, LeaseError> {
+        let now = now_millis();
+        let lease = new_lease(name, owner, duration, now);
+
+        match self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("id", Self::key(name))
+            .item("lease_owner", AttributeValue::S(lease.owner.clone()))
+            .item("lease_token", AttributeValue::S(lease.token.clone()))
+            .item(
+                "expires_at",
+                AttributeValue::N(lease.expires_at.to_string()),
+            )
+            .item(
+                "ttl",
+                AttributeValue::N((lease.expires_at / 1000 + 1).to_string()),
+            )
+            .condition_expression("attribute_not_exists(id) OR expires_at <= :now")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await
+        {
+            Ok(_) => Ok(Some(lease)),
+            Err(err) => match err.into_service_error() {
+                PutItemError::ConditionalCheckFailedException(_) => Ok(None),
+                err => Err(LeaseError::Store(err.to_string())),
+            },
+        }
+    }
+
+    async fn renew(&self, lease: &Lease, duration: Duration) -> Result<Lease, LeaseError> {
+        let now = now_millis();
+        let expires_at = now + duration.as_millis() as u64;
+
+        match self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", Self::key(&lease.name))
+            .update_expression("SET expires_at = :expires_at, #ttl = :ttl")
+            .condition_expression("lease_token = :token AND expires_at > :now")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":expires_at", AttributeValue::N(expires_at.to_string()))
+            .expression_attribute_values(
+                ":ttl",
+                AttributeValue::N((expires_at / 1000 + 1).to_string()),
+            )
+            .expression_attribute_values(":token", AttributeValue::S(lease.token.clone()))
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await
+        {
+            Ok(_) => Ok(Lease {
+                expires_at,
+                ..lease.clone()
+            }),
+            Err(err) => match err.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => Err(LeaseError::Lost),
+                err => Err(LeaseError::Store(err.to_string())),
+            },
+        }
+    }
+
+    async fn release(&self, lease: &Lease) -> Result<(), LeaseError> {
+        match self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("id", Self::key(&lease.name))
+            .condition_expression("lease_token = :token")
+            .expression_attribute_values(":token", AttributeValue::S(lease.token.clone()))
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => match err.into_service_error() {
+                DeleteItemError::ConditionalCheckFailedException(_) => Err(LeaseError::Lost),
+                err => Err(LeaseError::Store(err.to_string())),
+            },
+        }
+    }
+}
+
+/// Holds a lease for the lifetime of the guard. Call `release` when the work is done; a guard
+/// that is dropped without it releases the lease in the background on a best-effort basis.
+///
+/// ```ignore
+/// let store = Arc::new(DynamoDbLeaseStore::from_client(state.dynamodb.clone(), table_name));
+/// if let Some(guard) = LeaseGuard::acquire(store, "nightly-cleanup", &owner, ttl).await? {
+///     let guard = guard.with_heartbeat(ttl / 3);
+///     run_cleanup(&guard).await;
+///     guard.release().await?;
+/// }
+/// ```
+pub struct LeaseGuard {
+    store: Arc<dyn LeaseStore>,
+    lease: Arc<Mutex<Lease>>,
+    duration: Duration,
+    lost: Arc<AtomicBool>,
+    heartbeat: Option<JoinHandle<()>>,
+    released: bool,
+}
+
+impl LeaseGuard {
+    pub async fn acquire(
+        store: Arc<dyn LeaseStore>,
+        name: &str,
+        owner: &str,
+        duration: Duration,
+    ) -> Result<Option<Self>, LeaseError> {
+        Ok(store
+            .acquire(name, owner, duration)
+            .await?
+            .map(|lease| Self {
+                store,
+                lease: Arc::new(Mutex::new(lease)),
+                duration,
+                lost: Arc::new(AtomicBool::new(false)),
+                heartbeat: None,
+                released: false,
+            }))
+    }
+
+    pub fn lease(&self) -> Lease {
+        self.lease.lock().unwrap().clone()
+    }
+
+    pub fn is_held(&self) -> bool {
+        !self.lost.load(Ordering::SeqCst) && !self.lease().is_expired_at(self.store.now_millis())
+    }
+
+    pub async fn renew(&self) -> Result<(), LeaseError> {
+        renew_shared(&self.store, &self.lease, &self.lost, self.duration).await
+    }
+
+    /// Renews the lease every `interval` until the guard is released or the lease is lost.
+    pub fn with_heartbeat(mut self, interval: Duration) -> Self {
+        let store = self.store.clone();
+        let lease = self.lease.clone();
+        let lost = self.lost.clone();
+        let duration = self.duration;
+
+        self.heartbeat = Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match renew_shared(&store, &lease, &lost, duration).await {
+                    Ok(()) => {}
+                    Err(LeaseError::Lost) => break,
+                    Err(err) => warn!(error = %err, "Failed to renew lease"),
+                }
+            }
+        }));
+
+        self
+    }
+
+    pub async fn release(mut self) -> Result<(), LeaseError> {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.abort();
+        }
+        self.released = true;
+        self.store.release(&self.lease()).await
+    }
+}
+
+async fn renew_shared(
+    store: &Arc<dyn LeaseStore>,
+    lease: &Arc<Mutex<Lease>>,
+    lost: &AtomicBool,
+    duration: Duration,
+) -> Result<(), LeaseError> {
+    let current = lease.lock().unwrap().clone();
+
+    match store.renew(&current, duration).await {
+        Ok(renewed) => {
+            *lease.lock().unwrap() = renewed;
+            Ok(())
+        }
+        Err(LeaseError::Lost) => {
+            lost.store(true, Ordering::SeqCst);
+            Err(LeaseError::Lost)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+impl Drop for LeaseGuard {
+    fn drop(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.abort();
+        }
+
+        if self.released || self.lost.load(Ordering::SeqCst) {
+            return;
+        }
+
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let store = self.store.clone();
+            let lease = self.lease();
+            runtime.spawn(async move {
+                if let Err(err) = store.release(&lease).await {
+                    warn!(lease = %lease.name, error = %err, "Failed to release dropped lease");
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{advance, Instant};
+
+    /// A store on tokio's clock, so paused tests can move lease expiry with `advance`.
+    fn paused_store() -> InMemoryLeaseStore {
+        let start = Instant::now();
+        InMemoryLeaseStore::with_clock(move || start.elapsed().as_millis() as u64)
+    }
+
+    #[tokio::test]
+    async fn test_acquire_is_exclusive() {
+        let store = InMemoryLeaseStore::new();
+
+        let first = store
+            .acquire("job", "worker-1", Duration::from_secs(30))
+            .await
+            .unwrap();
+        let second = store
+            .acquire("job", "worker-2", Duration::from_secs(30))
+            .await
+            .unwrap();
+
+        assert_eq!(first.unwrap().owner, "worker-1");
+        assert!(second.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_lease_can_be_taken_over() {
+        let store = paused_store();
+
+        let stale = store
+            .acquire("job", "worker-1", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .unwrap();
+        advance(Duration::from_secs(30)).await;
+
+        let takeover = store
+            .acquire("job", "worker-2", Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert_eq!(takeover.unwrap().owner, "worker-2");
+
+        assert_eq!(
+            store.renew(&stale, Duration::from_secs(30)).await,
+            Err(LeaseError::Lost)
+        );
+        assert_eq!(store.release(&stale).await, Err(LeaseError::Lost));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_renew_extends_lease() {
+        let store = paused_store();
+        let lease = store
+            .acquire("job", "worker-1", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .unwrap();
+
+        advance(Duration::from_secs(20)).await;
+        let renewed = store.renew(&lease, Duration::from_secs(30)).await.unwrap();
+        advance(Duration::from_secs(20)).await;
+
+        assert_eq!(renewed.expires_at, lease.expires_at + 20_000);
+        assert_eq!(renewed.token, lease.token);
+        assert!(store
+            .acquire("job", "worker-2", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_release_frees_lease() {
+        let store = InMemoryLeaseStore::new();
+        let lease = store
+            .acquire("job", "worker-1", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .unwrap();
+
+        store.release(&lease).await.unwrap();
+
+        assert!(store
+            .acquire("job", "worker-2", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_guard_heartbeat_keeps_lease() {
+        let store: Arc<dyn LeaseStore> = Arc::new(paused_store());
+        let guard = LeaseGuard::acquire(store.clone(), "job", "worker-1", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .unwrap()
+            .with_heartbeat(Duration::from_secs(10));
+
+        // Sleeping lets the heartbeat run at each of its ticks as paused time auto-advances.
+        tokio::time::sleep(Duration::from_secs(120)).await;
+
+        assert!(guard.is_held());
+        assert!(store
+            .acquire("job", "worker-2", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .is_none());
+
+        guard.release().await.unwrap();
+        assert!(store
+            .acquire("job", "worker-2", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_guard_detects_lost_lease() {
+        let store: Arc<dyn LeaseStore> = Arc::new(paused_store());
+        let guard = LeaseGuard::acquire(store.clone(), "job", "worker-1", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .unwrap();
+
+        advance(Duration::from_secs(30)).await;
+        assert!(!guard.is_held());
+        store
+            .acquire("job", "worker-2", Duration::from_secs(30))
+            .await
+            .unwrap();
+
+        assert_eq!(guard.renew().await, Err(LeaseError::Lost));
+        assert!(!guard.is_held());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_guard_releases_lease() {
+        let store: Arc<dyn LeaseStore> = Arc::new(InMemoryLeaseStore::new());
+        let guard = LeaseGuard::acquire(store.clone(), "job", "worker-1", Duration::from_secs(30))
+            .await
+            .unwrap();
+
+        drop(guard);
+        tokio::task::yield_now().await;
+
+        assert!(store
+            .acquire("job", "worker-2", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
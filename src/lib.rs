@@ -1,6 +1,15 @@
 pub mod auth;
+pub mod backup;
 pub mod config;
 pub mod db;
+pub mod encryption;
+pub mod idempotency;
+pub mod lease;
 pub mod logging;
+pub mod mailer;
 pub mod models;
+pub mod rate_limit;
 pub mod routes;
+pub mod schema;
+pub mod state;
+pub mod tenant;
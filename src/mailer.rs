@@ -0,0 +1,160 @@
+use async_trait::async_trait;
+use aws_config::SdkConfig;
+use aws_sdk_sesv2::error::DisplayErrorContext;
+use aws_sdk_sesv2::types::{Body, Content, Destination, EmailContent, Message};
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::config::MailerConfig;
+
+/// A plain text email.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), String>;
+}
+
+pub fn mailer_from_config(config: &MailerConfig, sdk_config: &SdkConfig) -> Arc<dyn Mailer> {
+    match config {
+        MailerConfig::Ses { from } => Arc::new(SesMailer::new(
+            aws_sdk_sesv2::Client::new(sdk_config),
+            from.clone(),
+        )),
+        MailerConfig::File { path } => Arc::new(FileMailer::new(Some(path.clone()))),
+        MailerConfig::Stdout => Arc::new(FileMailer::new(None)),
+    }
+}
+
+/// Sends through the SES v2 `SendEmail` API.
+pub struct SesMailer {
+    client: aws_sdk_sesv2::Client,
+    from: String,
+}
+
+impl SesMailer {
+    pub fn new(client: aws_sdk_sesv2::Client, from: String) -> Self {
+        Self { client, from }
+    }
+
+    fn content(email: &Email) -> Result<EmailContent, String> {
+        let text = |data: &str| {
+            Content::builder()
+                .data(data)
+                .charset("UTF-8")
+                .build()
+                .map_err(|err| err.to_string())
+        };
+
+        Ok(EmailContent::builder()
+            .simple(
+                Message::builder()
+                    .subject(text(&email.subject)?)
+                    .body(Body::builder().text(text(&email.body)?).build())
+                    .build(),
+            )
+            .build())
+    }
+}
+
+#[async_trait]
+impl Mailer for SesMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        self.client
+            .send_email()
+            .from_email_address(&self.from)
+            .destination(Destination::builder().to_addresses(&email.to).build())
+            .content(Self::content(email)?)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| format!("SendEmail failed: {}", DisplayErrorContext(err)))
+    }
+}
+
+/// Appends emails to a file, or prints them to stdout without one, for local runs where
+/// nothing should leave the machine.
+pub struct FileMailer {
+    path: Option<String>,
+}
+
+impl FileMailer {
+    pub fn new(path: Option<String>) -> Self {
+        Self { path }
+    }
+
+    fn format(email: &Email) -> String {
+        format!(
+            "To: {}\nSubject: {}\n\n{}\n\n",
+            email.to, email.subject, email.body
+        )
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        let message = Self::format(email);
+
+        match &self.path {
+            Some(path) => std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(message.as_bytes()))
+                .map_err(|err| err.to_string()),
+            None => {
+                print!("{}", message);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email() -> Email {
+        Email {
+            to: "jane@example.com".to_string(),
+            subject: "Hello".to_string(),
+            body: "Line one\nLine two".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_mailer_appends_emails() {
+        let path = std::env::temp_dir().join(format!("mailer-{}.txt", uuid::Uuid::new_v4()));
+        let mailer = FileMailer::new(Some(path.to_string_lossy().to_string()));
+
+        mailer.send(&email()).await.unwrap();
+        mailer.send(&email()).await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, FileMailer::format(&email()).repeat(2));
+        assert!(contents.starts_with("To: jane@example.com\nSubject: Hello\n\nLine one"));
+    }
+
+    #[test]
+    fn test_ses_content() {
+        let content = SesMailer::content(&email()).unwrap();
+        let message = content.simple().unwrap();
+
+        assert_eq!(message.subject().unwrap().data(), "Hello");
+        assert_eq!(
+            message.body().unwrap().text().unwrap().data(),
+            "Line one\nLine two"
+        );
+        assert_eq!(
+            message.body().unwrap().text().unwrap().charset(),
+            Some("UTF-8")
+        );
+    }
+}
@@ -1,49 +1,37 @@
 use axum::{
-    middleware::from_fn_with_state,
-    routing::{delete, get, patch},
-    Extension, Router,
+    middleware::{from_fn, from_fn_with_state},
+    Router,
 };
 use lambda_http::{run, Error};
 
 use template::{
-    auth::secret_auth_middleware::{secret_middleware, SecretAuth},
-    config::{AuthMethod, Config},
-    db::DynamoDbRepository,
-    logging,
-    models::{item::Item, user::User},
-    routes::{foo, parameters, user},
+    auth::authorizer::authenticate_authorizer, auth::chain::authenticate,
+    auth::signing::authenticate_signed, config::AuthMethod, config::Config,
+    idempotency::idempotency_middleware, logging, routes, state::AppState,
 };
 
 async fn create_app(config: Config) -> Router {
-    match config.auth_method {
-        AuthMethod::Cognito => {
-            panic!("We are using the secret method for this api");
-        }
-        AuthMethod::Secret => {
-            let auth = SecretAuth::new(config.secret.unwrap());
+    let state = AppState::from_config(config).await;
 
-            let user_db = DynamoDbRepository::<User>::new(config.dynamodb_user_table_name.unwrap())
-                .await
-                .expect("Failed to initialize DynamoDB client for user table");
+    let app = routes::router(state.clone())
+        .route_layer(from_fn_with_state(
+            state.idempotency.clone(),
+            idempotency_middleware,
+        ))
+        .route_layer(from_fn_with_state(state.auth.clone(), authenticate));
+    let app = match state.hmac {
+        Some(hmac) => app.route_layer(from_fn_with_state(hmac, authenticate_signed)),
+        None => app,
+    };
+    let app = if state.config.auth_methods.contains(&AuthMethod::Authorizer) {
+        app.route_layer(from_fn(authenticate_authorizer))
+    } else {
+        app
+    };
 
-            let db = DynamoDbRepository::<Item>::new(config.dynamodb_table_name)
-                .await
-                .expect("Failed to initialize DynamoDB client for item table");
-
-            Router::new()
-                .route("/parameters", get(parameters::handler))
-                .route("/foo", get(foo::get).post(foo::create))
-                .route("/user", get(user::get))
-                .route("/user/:id", delete(user::delete))
-                .route(
-                    "/foo/:id",
-                    get(foo::get_by_id).post(foo::update).delete(foo::delete),
-                )
-                .route("/user/:id/admin-status", patch(user::patch_admin_status))
-                .route_layer(from_fn_with_state(auth.clone(), secret_middleware))
-                .layer(Extension(db))
-                .layer(Extension(user_db))
-        }
+    match state.accounts {
+        Some(accounts) => app.merge(routes::auth::router(accounts)),
+        None => app,
     }
 }
 
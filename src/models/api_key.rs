@@ -0,0 +1,109 @@
+use aws_sdk_dynamodb::{operation::update_item::UpdateItemError, types::AttributeValue};
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::db::{
+    DynamoDbOperations, DynamoDbRepository, EncryptedFields, OperationResult, SoftDeletable,
+    TenantRepository, UniqueFields,
+};
+use crate::schema::{TableDefinition, TableSchema};
+use crate::tenant::TenantId;
+
+/// A managed API key. Only a hash of its secret is stored; the key itself is shown once, when
+/// it is created or rotated. Revoking a key soft deletes it, recording who revoked it.
+#[derive(Debug, Serialize, Clone, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub owner: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
+}
+
+impl ApiKey {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[async_trait]
+impl SoftDeletable for ApiKey {
+    fn get_deleted_at(&self) -> &Option<String> {
+        &self.deleted_at
+    }
+}
+
+impl UniqueFields for ApiKey {}
+
+impl EncryptedFields for ApiKey {}
+
+impl TableDefinition for ApiKey {
+    const TABLE_SCHEMA: TableSchema = TableSchema::keyed_by_id();
+}
+
+#[async_trait]
+pub trait ApiKeyDynamoDbRepository: DynamoDbOperations<ApiKey> {
+    async fn replace_hash(&self, id: String, key_hash: String) -> OperationResult<ApiKey>;
+    async fn record_use(&self, id: String, used_at: u64) -> OperationResult<ApiKey>;
+}
+
+impl DynamoDbRepository<ApiKey> {
+    async fn set_active(
+        &self,
+        id: String,
+        attribute: &str,
+        value: AttributeValue,
+    ) -> OperationResult<ApiKey> {
+        match self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(self.record_id(&id)))
+            .update_expression(format!("SET {} = :value", attribute))
+            .set_expression_attribute_values(self.condition_values())
+            .expression_attribute_values(":value", value)
+            .condition_expression(
+                self.condition("attribute_exists(id) AND attribute_not_exists(deleted_at)"),
+            )
+            .send()
+            .await
+        {
+            Ok(_) => OperationResult::Success(None),
+            Err(err) => match err.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => {
+                    OperationResult::ItemNotFound
+                }
+                _ => OperationResult::InternalError("Service Error".to_string()),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl ApiKeyDynamoDbRepository for DynamoDbRepository<ApiKey> {
+    async fn replace_hash(&self, id: String, key_hash: String) -> OperationResult<ApiKey> {
+        self.set_active(id, "key_hash", AttributeValue::S(key_hash))
+            .await
+    }
+
+    async fn record_use(&self, id: String, used_at: u64) -> OperationResult<ApiKey> {
+        self.set_active(id, "last_used_at", AttributeValue::N(used_at.to_string()))
+            .await
+    }
+}
+
+impl TenantRepository<dyn ApiKeyDynamoDbRepository> for DynamoDbRepository<ApiKey> {
+    fn for_tenant(&self, tenant_id: &TenantId) -> Arc<dyn ApiKeyDynamoDbRepository> {
+        Arc::new(self.clone().with_tenant(tenant_id))
+    }
+}
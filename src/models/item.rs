@@ -1,13 +1,24 @@
+use aws_sdk_dynamodb::types::{AttributeValue, TransactWriteItem};
 use axum::async_trait;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::db::SoftDeletable;
+use crate::db::{
+    DynamoDbOperations, DynamoDbRepository, EncryptedFields, OperationResult, SoftDeletable,
+    TenantRepository, TransactionStep, UniqueFields,
+};
+use crate::schema::{TableDefinition, TableSchema};
+use crate::tenant::TenantId;
 
 #[derive(Debug, Serialize, Clone, Deserialize)]
 pub struct Item {
     pub id: String,
     pub name: String,
     pub age: u32,
+    /// Subject of the principal that created the item, whose item count it adds to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub deleted_at: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -26,3 +37,61 @@ impl SoftDeletable for Item {
         &self.deleted_at
     }
 }
+
+impl UniqueFields for Item {}
+
+impl EncryptedFields for Item {}
+
+impl TableDefinition for Item {
+    const TABLE_SCHEMA: TableSchema = TableSchema::keyed_by_id();
+}
+
+#[async_trait]
+pub trait ItemDynamoDbRepository: DynamoDbOperations<Item> {
+    /// Soft deletes the item in one transaction with `count_update`, which takes it off its
+    /// creator's item count; a count that cannot go down fails with `CounterOutOfBounds`.
+    async fn soft_delete_counted(
+        &self,
+        id: String,
+        deleted_by: String,
+        count_update: TransactWriteItem,
+    ) -> OperationResult<Item>;
+}
+
+#[async_trait]
+impl ItemDynamoDbRepository for DynamoDbRepository<Item> {
+    async fn soft_delete_counted(
+        &self,
+        id: String,
+        deleted_by: String,
+        count_update: TransactWriteItem,
+    ) -> OperationResult<Item> {
+        let deleted_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs()
+            .to_string();
+
+        let steps = self.soft_delete_steps(
+            &AttributeValue::S(self.record_id(&id)),
+            &deleted_by,
+            &deleted_at,
+            &[],
+            &[],
+        );
+        match steps {
+            Ok(mut steps) => {
+                steps.push((TransactionStep::Counter, count_update));
+                self.write_transaction(steps, OperationResult::ItemNotFound)
+                    .await
+            }
+            Err(err) => OperationResult::InternalError(err),
+        }
+    }
+}
+
+impl TenantRepository<dyn ItemDynamoDbRepository> for DynamoDbRepository<Item> {
+    fn for_tenant(&self, tenant_id: &TenantId) -> Arc<dyn ItemDynamoDbRepository> {
+        Arc::new(self.clone().with_tenant(tenant_id))
+    }
+}
@@ -1,2 +1,5 @@
+pub mod api_key;
 pub mod item;
+pub mod session;
 pub mod user;
+pub mod verification;
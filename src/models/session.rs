@@ -0,0 +1,168 @@
+use aws_sdk_dynamodb::{operation::update_item::UpdateItemError, types::AttributeValue};
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::db::{
+    DynamoDbOperations, DynamoDbRepository, EncryptedFields, OperationResult, SoftDeletable,
+    TenantRepository, UniqueFields,
+};
+use crate::schema::{IndexSchema, KeyAttribute, TableDefinition, TableSchema};
+use crate::tenant::TenantId;
+
+/// How many superseded refresh tokens a session remembers, to recognise their reuse.
+pub const MAX_RETIRED_HASHES: usize = 20;
+
+pub const SESSION_USER_INDEX: &str = "template-sessions-user-index";
+
+/// A login session, holding the hash of its current refresh token and of the ones rotated out
+/// before it. Revoking a session soft deletes it; DynamoDB removes it after `ttl`, when its
+/// refresh token would have expired anyway.
+#[derive(Debug, Serialize, Clone, Deserialize)]
+pub struct Session {
+    pub id: String,
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub refresh_hash: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retired_hashes: Vec<String>,
+    pub created_at: u64,
+    pub ttl: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
+}
+
+impl Session {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.ttl <= now
+    }
+}
+
+#[async_trait]
+impl SoftDeletable for Session {
+    fn get_deleted_at(&self) -> &Option<String> {
+        &self.deleted_at
+    }
+}
+
+impl UniqueFields for Session {}
+
+impl EncryptedFields for Session {}
+
+impl TableDefinition for Session {
+    const TABLE_SCHEMA: TableSchema = TableSchema::keyed_by_id()
+        .with_indexes(&[IndexSchema {
+            name: SESSION_USER_INDEX,
+            partition_key: KeyAttribute::string("userId"),
+            sort_key: None,
+        }])
+        .with_ttl("ttl");
+}
+
+#[async_trait]
+pub trait SessionDynamoDbRepository: DynamoDbOperations<Session> {
+    /// Swaps in a new refresh token hash, provided `current_hash` is still the current one, so
+    /// two refreshes racing with the same token cannot both succeed.
+    async fn rotate_refresh_hash(
+        &self,
+        id: String,
+        current_hash: String,
+        new_hash: String,
+        retired_hashes: Vec<String>,
+    ) -> OperationResult<Session>;
+    /// Revokes every active session of the user, returning the ids of those it revoked.
+    async fn revoke_user_sessions(
+        &self,
+        user_id: String,
+        revoked_by: String,
+    ) -> OperationResult<Vec<String>>;
+}
+
+#[async_trait]
+impl SessionDynamoDbRepository for DynamoDbRepository<Session> {
+    async fn rotate_refresh_hash(
+        &self,
+        id: String,
+        current_hash: String,
+        new_hash: String,
+        retired_hashes: Vec<String>,
+    ) -> OperationResult<Session> {
+        let retired_hashes = retired_hashes.into_iter().map(AttributeValue::S).collect();
+
+        match self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(self.record_id(&id)))
+            .update_expression("SET refresh_hash = :new_hash, retired_hashes = :retired_hashes")
+            .set_expression_attribute_values(self.condition_values())
+            .expression_attribute_values(":current_hash", AttributeValue::S(current_hash))
+            .expression_attribute_values(":new_hash", AttributeValue::S(new_hash))
+            .expression_attribute_values(":retired_hashes", AttributeValue::L(retired_hashes))
+            .condition_expression(self.condition(
+                "attribute_exists(id) AND attribute_not_exists(deleted_at) AND refresh_hash = :current_hash",
+            ))
+            .send()
+            .await
+        {
+            Ok(_) => OperationResult::Success(None),
+            Err(err) => match err.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => {
+                    OperationResult::ItemNotFound
+                }
+                _ => OperationResult::InternalError("Service Error".to_string()),
+            },
+        }
+    }
+
+    async fn revoke_user_sessions(
+        &self,
+        user_id: String,
+        revoked_by: String,
+    ) -> OperationResult<Vec<String>> {
+        let sessions = match self
+            .find_by_index(SESSION_USER_INDEX, "userId", &user_id)
+            .await
+        {
+            OperationResult::Success(sessions) => sessions.unwrap_or_default(),
+            OperationResult::ItemNotFound => Vec::new(),
+            err => return into_ids(err),
+        };
+        let mut revoked = Vec::new();
+
+        for session in sessions {
+            match self
+                .soft_delete(session.id.clone(), revoked_by.clone())
+                .await
+            {
+                OperationResult::Success(_) => revoked.push(session.id),
+                // Ended by someone else in the meantime.
+                OperationResult::ItemNotFound => {}
+                err => return into_ids(err),
+            }
+        }
+
+        OperationResult::Success(Some(revoked))
+    }
+}
+
+/// Carries a failure over to the result of [`SessionDynamoDbRepository::revoke_user_sessions`].
+fn into_ids<T>(result: OperationResult<T>) -> OperationResult<Vec<String>> {
+    match result {
+        OperationResult::Success(_) => OperationResult::Success(Some(Vec::new())),
+        OperationResult::ItemNotFound => OperationResult::ItemNotFound,
+        OperationResult::ItemAlreadyExists => OperationResult::ItemAlreadyExists,
+        OperationResult::FieldAlreadyExists(field) => OperationResult::FieldAlreadyExists(field),
+        OperationResult::CounterOutOfBounds => OperationResult::CounterOutOfBounds,
+        OperationResult::InvalidInput => OperationResult::InvalidInput,
+        OperationResult::InternalError(err) => OperationResult::InternalError(err),
+    }
+}
+
+impl TenantRepository<dyn SessionDynamoDbRepository> for DynamoDbRepository<Session> {
+    fn for_tenant(&self, tenant_id: &TenantId) -> Arc<dyn SessionDynamoDbRepository> {
+        Arc::new(self.clone().with_tenant(tenant_id))
+    }
+}
@@ -1,8 +1,26 @@
-use aws_sdk_dynamodb::{operation::update_item::UpdateItemError, types::AttributeValue};
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, TransactWriteItem, Update};
 use axum::async_trait;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::db::{DynamoDbOperations, DynamoDbRepository, OperationResult, SoftDeletable};
+use crate::db::{
+    CounterBounds, CounterOperations, DynamoDbOperations, DynamoDbRepository, EncryptedFields,
+    OperationResult, SoftDeletable, TenantRepository, TransactionStep, UniqueFields,
+};
+use crate::schema::{IndexSchema, KeyAttribute, TableDefinition, TableSchema};
+use crate::tenant::TenantId;
+
+pub const ITEM_COUNT_COUNTER: &str = "item_count";
+
+/// Each tenant's count of active admins is kept as counter `admin_count` of owner `users`.
+pub const ADMIN_COUNT_OWNER: &str = "users";
+pub const ADMIN_COUNT_COUNTER: &str = "admin_count";
+
+/// Times an admin change is retried when the user changed between reading and writing it.
+const ADMIN_CHANGE_ATTEMPTS: usize = 3;
 
 #[derive(Debug, Serialize, Clone, Deserialize)]
 pub struct User {
@@ -26,24 +44,207 @@ impl SoftDeletable for User {
     }
 }
 
+impl UniqueFields for User {
+    const UNIQUE_FIELDS: &'static [&'static str] = &["email", "username"];
+
+    fn unique_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("email", self.email.to_lowercase()),
+            ("username", self.username.to_lowercase()),
+        ]
+    }
+}
+
+impl EncryptedFields for User {
+    const ENCRYPTED_FIELDS: &'static [&'static str] = &["password_hash"];
+}
+
+impl TableDefinition for User {
+    const TABLE_SCHEMA: TableSchema = TableSchema::keyed_by_id().with_indexes(&[IndexSchema {
+        name: "template-user-email-index",
+        partition_key: KeyAttribute::string("email"),
+        sort_key: None,
+    }]);
+}
+
+/// Users are created, deleted and promoted through these methods, which keep the admin count;
+/// the generic writes of `DynamoDbOperations` do not.
 #[async_trait]
 pub trait UserDynamoDbRepository: DynamoDbOperations<User> {
-    async fn update_admin_status(self, id: String, admin: bool) -> OperationResult<User>;
+    /// Creates the user, adding an admin to the admin count in the same transaction.
+    async fn create_user(&self, user: User) -> OperationResult<User>;
+    /// Moves the tenant's admin count with the user's status in one transaction; demoting
+    /// the last admin fails with `CounterOutOfBounds`.
+    async fn update_admin_status(&self, id: String, admin: bool) -> OperationResult<User>;
+    /// Soft deletes the user, taking an admin off the admin count in the same transaction;
+    /// deleting the last admin fails with `CounterOutOfBounds`.
+    async fn soft_delete_user(&self, id: String, deleted_by: String) -> OperationResult<User>;
+    async fn adjust_item_count(
+        &self,
+        id: String,
+        delta: i64,
+        quota: Option<i64>,
+    ) -> OperationResult<i64>;
+    async fn get_item_count(&self, id: String) -> OperationResult<i64>;
+    /// Moves the item count by `delta` as a step of a transaction on another table, such as
+    /// the soft delete of an item; it fails there if the count would go below zero.
+    fn item_count_step(&self, id: String, delta: i64) -> Result<TransactWriteItem, String>;
+    async fn find_by_email(&self, email: String) -> OperationResult<User>;
+    /// Only succeeds while the user still has `email`, so a token mailed to an address the
+    /// user has since replaced cannot verify the new one.
+    async fn mark_email_verified(&self, id: String, email: String) -> OperationResult<User>;
+    /// Sets only the password hash, under the same condition as `mark_email_verified`.
+    async fn set_password_hash(
+        &self,
+        id: String,
+        email: String,
+        password_hash: String,
+    ) -> OperationResult<User>;
 }
 
 #[async_trait]
 impl UserDynamoDbRepository for DynamoDbRepository<User> {
-    async fn update_admin_status(self, id: String, admin: bool) -> OperationResult<User> {
-        let admin_value = AttributeValue::Bool(admin);
+    async fn create_user(&self, user: User) -> OperationResult<User> {
+        if !user.admin {
+            return self.create_with(user, Vec::new()).await;
+        }
+
+        if let Err(err) = self.ensure_admin_count().await {
+            return OperationResult::InternalError(err);
+        }
+
+        match self.admin_count_step(1) {
+            Ok(step) => self.create_with(user, vec![step]).await,
+            Err(err) => OperationResult::InternalError(err),
+        }
+    }
+
+    async fn update_admin_status(&self, id: String, admin: bool) -> OperationResult<User> {
+        if let Err(err) = self.ensure_admin_count().await {
+            return OperationResult::InternalError(err);
+        }
+
+        for _ in 0..ADMIN_CHANGE_ATTEMPTS {
+            let steps = match self.admin_status_steps(&id, admin) {
+                Ok(steps) => steps,
+                Err(err) => return OperationResult::InternalError(err),
+            };
+
+            match self
+                .write_transaction(steps, OperationResult::ItemNotFound)
+                .await
+            {
+                // The record condition also fails when the status is already the requested one.
+                OperationResult::ItemNotFound => match self.get_item(id.clone()).await {
+                    OperationResult::Success(Some(user)) if user.admin == admin => {
+                        return OperationResult::Success(None)
+                    }
+                    OperationResult::Success(Some(_)) => continue,
+                    err => return err,
+                },
+                result => return result,
+            }
+        }
+
+        OperationResult::InternalError("User changed while updating admin status".to_string())
+    }
+
+    async fn soft_delete_user(&self, id: String, deleted_by: String) -> OperationResult<User> {
+        if let Err(err) = self.ensure_admin_count().await {
+            return OperationResult::InternalError(err);
+        }
+
+        for _ in 0..ADMIN_CHANGE_ATTEMPTS {
+            let user = match self.get_item(id.clone()).await {
+                OperationResult::Success(Some(user)) => user,
+                OperationResult::Success(None) => return OperationResult::ItemNotFound,
+                err => return err,
+            };
+            let deleted_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backwards")
+                .as_secs()
+                .to_string();
+
+            let steps = self
+                .soft_delete_steps(
+                    &AttributeValue::S(self.record_id(&id)),
+                    &deleted_by,
+                    &deleted_at,
+                    &user.unique_fields(),
+                    &[
+                        ("admin", Some(AttributeValue::Bool(user.admin))),
+                        ("email", Some(AttributeValue::S(user.email.clone()))),
+                        ("username", Some(AttributeValue::S(user.username.clone()))),
+                    ],
+                )
+                .and_then(|mut steps| {
+                    if user.admin {
+                        steps.push(self.admin_count_step(-1)?);
+                    }
+                    Ok(steps)
+                });
+            let steps = match steps {
+                Ok(steps) => steps,
+                Err(err) => return OperationResult::InternalError(err),
+            };
+
+            match self
+                .write_transaction(steps, OperationResult::ItemNotFound)
+                .await
+            {
+                // Deleted or changed meanwhile; the next read tells which.
+                OperationResult::ItemNotFound => continue,
+                result => return result,
+            }
+        }
+
+        OperationResult::InternalError("User changed while being deleted".to_string())
+    }
+
+    async fn adjust_item_count(
+        &self,
+        id: String,
+        delta: i64,
+        quota: Option<i64>,
+    ) -> OperationResult<i64> {
+        let bounds = CounterBounds::non_negative().with_max(quota);
 
+        self.increment(id, ITEM_COUNT_COUNTER.to_string(), delta, bounds)
+            .await
+    }
+
+    async fn get_item_count(&self, id: String) -> OperationResult<i64> {
+        self.get_counter(id, ITEM_COUNT_COUNTER.to_string()).await
+    }
+
+    fn item_count_step(&self, id: String, delta: i64) -> Result<TransactWriteItem, String> {
+        self.counter_step(
+            &id,
+            ITEM_COUNT_COUNTER,
+            delta,
+            CounterBounds::non_negative(),
+        )
+        .map(|(_, update)| update)
+    }
+
+    async fn find_by_email(&self, email: String) -> OperationResult<User> {
+        self.find_by_unique("email", &email.to_lowercase()).await
+    }
+
+    async fn mark_email_verified(&self, id: String, email: String) -> OperationResult<User> {
         match self
             .client
             .update_item()
             .table_name(&self.table_name)
-            .key("id", AttributeValue::S(id.to_string()))
-            .update_expression("SET admin = :admin")
-            .expression_attribute_values(":admin", admin_value)
-            .condition_expression("attribute_exists(id) AND attribute_not_exists(deleted_at)")
+            .key("id", AttributeValue::S(self.record_id(&id)))
+            .update_expression("SET email_verified = :verified")
+            .set_expression_attribute_values(self.condition_values())
+            .expression_attribute_values(":verified", AttributeValue::Bool(true))
+            .expression_attribute_values(":email", AttributeValue::S(email))
+            .condition_expression(self.condition(
+                "attribute_exists(id) AND attribute_not_exists(deleted_at) AND email = :email",
+            ))
             .send()
             .await
         {
@@ -56,4 +257,302 @@ impl UserDynamoDbRepository for DynamoDbRepository<User> {
             },
         }
     }
+
+    async fn set_password_hash(
+        &self,
+        id: String,
+        email: String,
+        password_hash: String,
+    ) -> OperationResult<User> {
+        let password_hash = match self.encode_password_hash(&id, password_hash).await {
+            Ok(password_hash) => password_hash,
+            Err(err) => return OperationResult::InternalError(err),
+        };
+
+        match self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(self.record_id(&id)))
+            .update_expression("SET password_hash = :password_hash")
+            .set_expression_attribute_values(self.condition_values())
+            .expression_attribute_values(":password_hash", password_hash)
+            .expression_attribute_values(":email", AttributeValue::S(email))
+            .condition_expression(self.condition(
+                "attribute_exists(id) AND attribute_not_exists(deleted_at) AND email = :email",
+            ))
+            .send()
+            .await
+        {
+            Ok(_) => OperationResult::Success(None),
+            Err(err) => match err.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => {
+                    OperationResult::ItemNotFound
+                }
+                _ => OperationResult::InternalError("Service Error".to_string()),
+            },
+        }
+    }
+}
+
+impl DynamoDbRepository<User> {
+    /// Recounts the active admins of every tenant in the table and overwrites their admin
+    /// counts, for tables whose users were written around `UserDynamoDbRepository`, such as
+    /// by a restore.
+    pub async fn recount_admins(&self) -> Result<(), String> {
+        let records = match self.export().await {
+            OperationResult::Success(records) => records.unwrap_or_default(),
+            OperationResult::InternalError(err) => return Err(err),
+            _ => return Err("Failed to export users".to_string()),
+        };
+
+        let mut admins: HashMap<Option<String>, i64> = HashMap::new();
+        for backup in records {
+            let count = admins.entry(backup.tenant_id).or_default();
+            if backup.record.admin && backup.record.deleted_at.is_none() {
+                *count += 1;
+            }
+        }
+
+        for (tenant_id, count) in admins {
+            let repository = match tenant_id {
+                Some(tenant_id) => match TenantId::new(tenant_id.clone()) {
+                    Some(tenant_id) => self.clone().with_tenant(&tenant_id),
+                    None => return Err(format!("Invalid tenant {}", tenant_id)),
+                },
+                None => self.clone(),
+            };
+            repository
+                .reset_counter(ADMIN_COUNT_OWNER, ADMIN_COUNT_COUNTER, count)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// The attribute value `password_hash` is stored as, encrypted like a whole record would
+    /// have it.
+    async fn encode_password_hash(
+        &self,
+        id: &str,
+        password_hash: String,
+    ) -> Result<AttributeValue, String> {
+        let mut fields = HashMap::from([
+            ("id".to_string(), AttributeValue::S(self.record_id(id))),
+            (
+                "password_hash".to_string(),
+                AttributeValue::S(password_hash),
+            ),
+        ]);
+
+        if let Some(encryptor) = &self.encryptor {
+            encryptor
+                .encrypt_item(&mut fields, User::ENCRYPTED_FIELDS)
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+
+        fields
+            .remove("password_hash")
+            .ok_or_else(|| "Missing password hash".to_string())
+    }
+
+    /// Seeds the admin count from the tenant's active users the first time it is needed, in
+    /// tables that hold users from before it was kept.
+    async fn ensure_admin_count(&self) -> Result<(), String> {
+        if self
+            .has_counter(ADMIN_COUNT_OWNER, ADMIN_COUNT_COUNTER)
+            .await?
+        {
+            return Ok(());
+        }
+
+        let admins = match self.scan().await {
+            OperationResult::Success(users) => users
+                .unwrap_or_default()
+                .iter()
+                .filter(|user| user.admin)
+                .count(),
+            OperationResult::InternalError(err) => return Err(err),
+            _ => return Err("Failed to count admins".to_string()),
+        };
+
+        self.seed_counter(ADMIN_COUNT_OWNER, ADMIN_COUNT_COUNTER, admins as i64)
+            .await
+    }
+
+    /// Keeps at least one admin when taking one off the count.
+    fn admin_count_step(&self, delta: i64) -> Result<(TransactionStep, TransactWriteItem), String> {
+        let bounds = if delta < 0 {
+            CounterBounds::at_least(1)
+        } else {
+            CounterBounds::non_negative()
+        };
+
+        self.counter_step(ADMIN_COUNT_OWNER, ADMIN_COUNT_COUNTER, delta, bounds)
+    }
+
+    /// Sets the status only when it changes, which the record condition checks, and moves the
+    /// admin count to match.
+    fn admin_status_steps(
+        &self,
+        id: &str,
+        admin: bool,
+    ) -> Result<Vec<(TransactionStep, TransactWriteItem)>, String> {
+        let changed = if admin {
+            "NOT admin = :true"
+        } else {
+            "admin = :true"
+        };
+
+        let update = Update::builder()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(self.record_id(id)))
+            .update_expression("SET admin = :admin")
+            .condition_expression(self.condition(&format!(
+                "attribute_exists(id) AND attribute_not_exists(deleted_at) AND {}",
+                changed
+            )))
+            .set_expression_attribute_values(self.condition_values())
+            .expression_attribute_values(":admin", AttributeValue::Bool(admin))
+            .expression_attribute_values(":true", AttributeValue::Bool(true))
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        Ok(vec![
+            (
+                TransactionStep::Record,
+                TransactWriteItem::builder().update(update).build(),
+            ),
+            self.admin_count_step(if admin { 1 } else { -1 })?,
+        ])
+    }
+}
+
+impl TenantRepository<dyn UserDynamoDbRepository> for DynamoDbRepository<User> {
+    fn for_tenant(&self, tenant_id: &TenantId) -> Arc<dyn UserDynamoDbRepository> {
+        Arc::new(self.clone().with_tenant(tenant_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ScanMode;
+    use crate::encryption::{FieldEncryptor, LocalKeyProvider};
+    use aws_sdk_dynamodb::Client;
+
+    fn repository() -> DynamoDbRepository<User> {
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(aws_sdk_dynamodb::config::BehaviorVersion::latest())
+            .region(aws_sdk_dynamodb::config::Region::new("us-east-1"))
+            .build();
+
+        DynamoDbRepository {
+            client: Client::from_conf(config),
+            table_name: "users".to_string(),
+            scan_mode: ScanMode::default(),
+            encryptor: None,
+            tenant_id: None,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_password_hash_decrypts_as_part_of_the_record() {
+        let keys = HashMap::from([("k1".to_string(), vec![1u8; 32])]);
+        let provider = LocalKeyProvider::new("k1".to_string(), keys).unwrap();
+        let encryptor = Arc::new(FieldEncryptor::new(Arc::new(provider)));
+        let repository = repository()
+            .with_encryption(encryptor.clone())
+            .with_tenant(&TenantId::new("acme").unwrap());
+
+        let encoded = repository
+            .encode_password_hash("user-1", "$argon2id$hash".to_string())
+            .await
+            .unwrap();
+        assert!(matches!(encoded, AttributeValue::M(_)));
+
+        let mut record = HashMap::from([
+            (
+                "id".to_string(),
+                AttributeValue::S(repository.record_id("user-1")),
+            ),
+            ("password_hash".to_string(), encoded),
+        ]);
+        encryptor
+            .decrypt_item(&mut record, User::ENCRYPTED_FIELDS)
+            .await
+            .unwrap();
+        assert_eq!(
+            record["password_hash"],
+            AttributeValue::S("$argon2id$hash".to_string())
+        );
+    }
+
+    #[test]
+    fn test_item_count_step_cannot_go_below_zero() {
+        let repository = repository().with_tenant(&TenantId::new("acme").unwrap());
+
+        let step = repository
+            .item_count_step("user-1".to_string(), -1)
+            .unwrap();
+
+        let counter = step.update().unwrap();
+        assert_eq!(counter.table_name(), "users");
+        assert_eq!(
+            counter.key().get("id"),
+            Some(&AttributeValue::S(
+                "COUNTER#TENANT#acme#user-1#item_count".to_string()
+            ))
+        );
+        assert_eq!(
+            counter.condition_expression(),
+            Some("counter_value >= :lower")
+        );
+    }
+
+    #[test]
+    fn test_demotion_requires_an_admin_to_remain() {
+        let steps = repository().admin_status_steps("1", false).unwrap();
+
+        let kinds: Vec<_> = steps.iter().map(|(step, _)| step.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![TransactionStep::Record, TransactionStep::Counter]
+        );
+        assert_eq!(
+            steps[0].1.update().unwrap().condition_expression(),
+            Some("attribute_exists(id) AND attribute_not_exists(deleted_at) AND admin = :true")
+        );
+        let counter = steps[1].1.update().unwrap();
+        assert_eq!(
+            counter.condition_expression(),
+            Some("counter_value >= :lower")
+        );
+        assert_eq!(
+            counter.expression_attribute_values().unwrap().get(":delta"),
+            Some(&AttributeValue::N("-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_promotion_only_counts_a_change() {
+        let steps = repository().admin_status_steps("1", true).unwrap();
+
+        assert_eq!(
+            steps[0].1.update().unwrap().condition_expression(),
+            Some("attribute_exists(id) AND attribute_not_exists(deleted_at) AND NOT admin = :true")
+        );
+        assert_eq!(
+            steps[1]
+                .1
+                .update()
+                .unwrap()
+                .expression_attribute_values()
+                .unwrap()
+                .get(":delta"),
+            Some(&AttributeValue::N("1".to_string()))
+        );
+    }
 }
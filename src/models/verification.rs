@@ -0,0 +1,52 @@
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{EncryptedFields, SoftDeletable, UniqueFields};
+use crate::schema::{TableDefinition, TableSchema};
+
+/// What a verification token may be redeemed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum VerificationPurpose {
+    EmailVerification,
+    PasswordReset,
+}
+
+/// A single-use token mailed to a user, of which only the hash is stored. Redeeming it soft
+/// deletes the record, so a second attempt finds nothing; DynamoDB removes it after `ttl`.
+#[derive(Debug, Serialize, Clone, Deserialize)]
+pub struct Verification {
+    pub id: String,
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub purpose: VerificationPurpose,
+    pub email: String,
+    pub token_hash: String,
+    pub created_at: u64,
+    pub ttl: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
+}
+
+impl Verification {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.ttl <= now
+    }
+}
+
+#[async_trait]
+impl SoftDeletable for Verification {
+    fn get_deleted_at(&self) -> &Option<String> {
+        &self.deleted_at
+    }
+}
+
+impl UniqueFields for Verification {}
+
+impl EncryptedFields for Verification {}
+
+impl TableDefinition for Verification {
+    const TABLE_SCHEMA: TableSchema = TableSchema::keyed_by_id().with_ttl("ttl");
+}
@@ -0,0 +1,359 @@
+use async_trait::async_trait;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_sdk_dynamodb::Client;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::schema::TableSchema;
+
+pub const RATE_LIMIT_TABLE_SCHEMA: TableSchema = TableSchema::keyed_by_id().with_ttl("ttl");
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+/// Counts hits per key in fixed time windows. A window's count is kept until `expires_at`.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Adds a hit to `key` in the window starting at `window_start` and returns the window's
+    /// count, this hit included.
+    async fn hit(&self, key: &str, window_start: u64, expires_at: u64) -> Result<u64, String>;
+}
+
+/// Failed attempts of one client or account, and until when it is locked out.
+#[async_trait]
+pub trait LockoutStore: Send + Sync {
+    async fn locked_until(&self, key: &str) -> Result<Option<u64>, String>;
+    /// Counts a failure and keeps the count until `expires_at`, starting over from one if the
+    /// previous count had expired. Returns the count, this failure included.
+    async fn record_failure(&self, key: &str, expires_at: u64) -> Result<u64, String>;
+    async fn lock(&self, key: &str, until: u64, expires_at: u64) -> Result<(), String>;
+    async fn clear(&self, key: &str) -> Result<(), String>;
+}
+
+/// Nonces of signed requests seen within their replay window.
+#[async_trait]
+pub trait NonceStore: Send + Sync {
+    /// Records `key` until `expires_at`. Returns false if it was already recorded and has not
+    /// expired, i.e. the request is a replay.
+    async fn claim(&self, key: &str, expires_at: u64) -> Result<bool, String>;
+}
+
+#[derive(Clone, Default)]
+struct LockoutRecord {
+    failures: u64,
+    locked_until: Option<u64>,
+    expires_at: u64,
+}
+
+#[derive(Default)]
+pub struct InMemoryRateLimitStore {
+    windows: Mutex<HashMap<(String, u64), (u64, u64)>>,
+    lockouts: Mutex<HashMap<String, LockoutRecord>>,
+    nonces: Mutex<HashMap<String, u64>>,
+}
+
+impl InMemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn hit(&self, key: &str, window_start: u64, expires_at: u64) -> Result<u64, String> {
+        let mut windows = self.windows.lock().unwrap();
+        let now = now();
+        windows.retain(|_, (_, expires_at)| *expires_at > now);
+
+        let (count, _) = windows
+            .entry((key.to_string(), window_start))
+            .or_insert((0, expires_at));
+        *count += 1;
+
+        Ok(*count)
+    }
+}
+
+#[async_trait]
+impl LockoutStore for InMemoryRateLimitStore {
+    async fn locked_until(&self, key: &str) -> Result<Option<u64>, String> {
+        let lockouts = self.lockouts.lock().unwrap();
+
+        Ok(lockouts
+            .get(key)
+            .filter(|record| record.expires_at > now())
+            .and_then(|record| record.locked_until))
+    }
+
+    async fn record_failure(&self, key: &str, expires_at: u64) -> Result<u64, String> {
+        let mut lockouts = self.lockouts.lock().unwrap();
+        let now = now();
+        lockouts.retain(|_, record| record.expires_at > now);
+
+        let record = lockouts.entry(key.to_string()).or_default();
+        record.failures += 1;
+        record.expires_at = expires_at;
+
+        Ok(record.failures)
+    }
+
+    async fn lock(&self, key: &str, until: u64, expires_at: u64) -> Result<(), String> {
+        let mut lockouts = self.lockouts.lock().unwrap();
+        let record = lockouts.entry(key.to_string()).or_default();
+        record.locked_until = Some(until);
+        record.expires_at = expires_at;
+
+        Ok(())
+    }
+
+    async fn clear(&self, key: &str) -> Result<(), String> {
+        self.lockouts.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NonceStore for InMemoryRateLimitStore {
+    async fn claim(&self, key: &str, expires_at: u64) -> Result<bool, String> {
+        let mut nonces = self.nonces.lock().unwrap();
+        let now = now();
+        nonces.retain(|_, expires_at| *expires_at > now);
+
+        if nonces.contains_key(key) {
+            return Ok(false);
+        }
+        nonces.insert(key.to_string(), expires_at);
+
+        Ok(true)
+    }
+}
+
+/// Stores one counter item per key and window, `<key>#<window start>`, incremented atomically
+/// and removed by the table's time-to-live once the window is over. Lockouts and nonces share
+/// the table, one item per key. Expired items may linger until DynamoDB removes them, so reads check
+/// `ttl` themselves.
+#[derive(Clone)]
+pub struct DynamoDbRateLimitStore {
+    pub client: Client,
+    pub table_name: String,
+}
+
+impl DynamoDbRateLimitStore {
+    pub fn from_client(client: Client, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for DynamoDbRateLimitStore {
+    async fn hit(&self, key: &str, window_start: u64, expires_at: u64) -> Result<u64, String> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(format!("{}#{}", key, window_start)))
+            .update_expression("ADD hits :one SET #ttl = :ttl")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":ttl", AttributeValue::N(expires_at.to_string()))
+            .return_values(ReturnValue::UpdatedNew)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+
+        match result
+            .attributes()
+            .and_then(|attributes| attributes.get("hits"))
+        {
+            Some(AttributeValue::N(hits)) => hits.parse().map_err(|_| "Invalid hit count".into()),
+            _ => Err("Rate limit counter missing from response".to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl LockoutStore for DynamoDbRateLimitStore {
+    async fn locked_until(&self, key: &str) -> Result<Option<u64>, String> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        let number = |name: &str| match result.item().and_then(|item| item.get(name)) {
+            Some(AttributeValue::N(value)) => value.parse::<u64>().ok(),
+            _ => None,
+        };
+
+        match number("ttl") {
+            Some(expires_at) if expires_at > now() => Ok(number("locked_until")),
+            _ => Ok(None),
+        }
+    }
+
+    async fn record_failure(&self, key: &str, expires_at: u64) -> Result<u64, String> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(key.to_string()))
+            .update_expression("ADD failures :one SET #ttl = :ttl")
+            .condition_expression("attribute_not_exists(id) OR #ttl > :now")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":ttl", AttributeValue::N(expires_at.to_string()))
+            .expression_attribute_values(":now", AttributeValue::N(now().to_string()))
+            .return_values(ReturnValue::UpdatedNew)
+            .send()
+            .await;
+
+        match result {
+            Ok(result) => match result
+                .attributes()
+                .and_then(|attributes| attributes.get("failures"))
+            {
+                Some(AttributeValue::N(failures)) => {
+                    failures.parse().map_err(|_| "Invalid failure count".into())
+                }
+                _ => Err("Failure count missing from response".to_string()),
+            },
+            Err(err) => match err.into_service_error() {
+                // The previous count expired but is still stored; start over.
+                UpdateItemError::ConditionalCheckFailedException(_) => self
+                    .client
+                    .put_item()
+                    .table_name(&self.table_name)
+                    .item("id", AttributeValue::S(key.to_string()))
+                    .item("failures", AttributeValue::N("1".to_string()))
+                    .item("ttl", AttributeValue::N(expires_at.to_string()))
+                    .send()
+                    .await
+                    .map(|_| 1)
+                    .map_err(|err| err.to_string()),
+                err => Err(err.to_string()),
+            },
+        }
+    }
+
+    async fn lock(&self, key: &str, until: u64, expires_at: u64) -> Result<(), String> {
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(key.to_string()))
+            .update_expression("SET locked_until = :until, #ttl = :ttl")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":until", AttributeValue::N(until.to_string()))
+            .expression_attribute_values(":ttl", AttributeValue::N(expires_at.to_string()))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    async fn clear(&self, key: &str) -> Result<(), String> {
+        self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(key.to_string()))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[async_trait]
+impl NonceStore for DynamoDbRateLimitStore {
+    async fn claim(&self, key: &str, expires_at: u64) -> Result<bool, String> {
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("id", AttributeValue::S(key.to_string()))
+            .item("ttl", AttributeValue::N(expires_at.to_string()))
+            .condition_expression("attribute_not_exists(id) OR #ttl <= :now")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":now", AttributeValue::N(now().to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => match err.into_service_error() {
+                PutItemError::ConditionalCheckFailedException(_) => Ok(false),
+                err => Err(err.to_string()),
+            },
+        }
+    }
+}
+
+/// Allows `limit` hits per key in each window of `window`.
+#[derive(Clone)]
+pub struct RateLimiter {
+    pub store: Arc<dyn RateLimitStore>,
+    pub limit: u64,
+    pub window: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>, limit: u64, window: Duration) -> Self {
+        Self {
+            store,
+            limit,
+            window,
+        }
+    }
+
+    /// Counts a hit against `key`. Returns how long until the key may try again when it is
+    /// over the limit, `None` otherwise.
+    pub async fn hit(&self, key: &str) -> Result<Option<Duration>, String> {
+        let now = now();
+        let window = self.window.as_secs().max(1);
+        let window_start = now - now % window;
+        let window_end = window_start + window;
+
+        let count = self.store.hit(key, window_start, window_end).await?;
+
+        Ok((count > self.limit).then(|| Duration::from_secs(window_end - now)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limits_each_key_separately() {
+        let limiter = RateLimiter::new(
+            Arc::new(InMemoryRateLimitStore::new()),
+            2,
+            Duration::from_secs(3600),
+        );
+
+        assert_eq!(limiter.hit("a").await.unwrap(), None);
+        assert_eq!(limiter.hit("a").await.unwrap(), None);
+        let retry_after = limiter.hit("a").await.unwrap().unwrap();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(3600));
+        assert_eq!(limiter.hit("b").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_nonces_are_claimed_once() {
+        let store = InMemoryRateLimitStore::new();
+        let expires_at = now() + 300;
+
+        assert!(store.claim("nonce#a", expires_at).await.unwrap());
+        assert!(!store.claim("nonce#a", expires_at).await.unwrap());
+        assert!(store.claim("nonce#b", expires_at).await.unwrap());
+    }
+}
@@ -0,0 +1,199 @@
+use crate::auth::api_key::{now, ApiKeyToken};
+use crate::auth::principal::AuthUser;
+use crate::db::OperationResult;
+use crate::models::api_key::{ApiKey, ApiKeyDynamoDbRepository};
+use crate::state::Repositories;
+use crate::tenant::TenantId;
+use axum::extract::State;
+use axum::http::header::CACHE_CONTROL;
+use axum::response::{IntoResponse, Response};
+use axum::{extract::Path, Json};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+
+/// The tenant's keys; `None` when `API_KEY` is not enabled and there is no key table.
+fn keys_for(
+    repositories: &Repositories,
+    tenant: &TenantId,
+) -> Option<Arc<dyn ApiKeyDynamoDbRepository>> {
+    repositories
+        .api_keys
+        .as_ref()
+        .map(|api_keys| api_keys.for_tenant(tenant))
+}
+
+pub async fn get(State(repositories): State<Repositories>, tenant: TenantId) -> Response {
+    let db = match keys_for(&repositories, &tenant) {
+        Some(db) => db,
+        None => return OperationResult::<()>::ItemNotFound.into_response(),
+    };
+
+    match db.scan().await {
+        OperationResult::Success(keys) => {
+            let keys: Vec<ApiKeyView> = keys
+                .unwrap_or_default()
+                .iter()
+                .map(|key| ApiKeyView::new(&tenant, key))
+                .collect();
+
+            (StatusCode::OK, Json(json!(keys))).into_response()
+        }
+        err => err.into_response(),
+    }
+}
+
+pub async fn create(
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
+    AuthUser(principal): AuthUser,
+    Json(body): Json<CreateApiKeyRequest>,
+) -> Response {
+    let db = match keys_for(&repositories, &tenant) {
+        Some(db) => db,
+        None => return OperationResult::<()>::ItemNotFound.into_response(),
+    };
+    let token = ApiKeyToken::generate(&tenant);
+    let created_at = now();
+
+    let key = ApiKey {
+        id: token.id.clone(),
+        name: body.name,
+        owner: body.owner.unwrap_or(principal.subject),
+        key_hash: token.hash(),
+        scopes: body.scopes,
+        created_at,
+        expires_at: body
+            .expires_in_seconds
+            .map(|expires_in| created_at + expires_in),
+        last_used_at: None,
+        deleted_at: None,
+        deleted_by: None,
+    };
+
+    match db.create(key.clone()).await {
+        OperationResult::Success(_) => (
+            StatusCode::CREATED,
+            [(CACHE_CONTROL, "no-store")],
+            Json(json!(IssuedApiKey::new(&token, &key))),
+        )
+            .into_response(),
+        err => err.into_response(),
+    }
+}
+
+/// Replaces the key's secret; the old one stops working immediately.
+pub async fn rotate(
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
+    Path(id): Path<String>,
+) -> Response {
+    let db = match keys_for(&repositories, &tenant) {
+        Some(db) => db,
+        None => return OperationResult::<()>::ItemNotFound.into_response(),
+    };
+
+    let key = match db.get_item(id.clone()).await {
+        OperationResult::Success(Some(key)) => key,
+        OperationResult::Success(None) => {
+            return OperationResult::<()>::ItemNotFound.into_response()
+        }
+        err => return err.into_response(),
+    };
+    let token = ApiKeyToken::rotated(&tenant, &id);
+
+    match db.replace_hash(id, token.hash()).await {
+        OperationResult::Success(_) => (
+            StatusCode::OK,
+            [(CACHE_CONTROL, "no-store")],
+            Json(json!(IssuedApiKey::new(&token, &key))),
+        )
+            .into_response(),
+        err => err.into_response(),
+    }
+}
+
+pub async fn revoke(
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
+    Path(id): Path<String>,
+    AuthUser(principal): AuthUser,
+) -> Response {
+    let db = match keys_for(&repositories, &tenant) {
+        Some(db) => db,
+        None => return OperationResult::<()>::ItemNotFound.into_response(),
+    };
+
+    match db.soft_delete(id, principal.subject).await {
+        OperationResult::Success(_) => (
+            StatusCode::NO_CONTENT,
+            Json(json!({"message": "API key was successfully revoked"})),
+        )
+            .into_response(),
+        err => err.into_response(),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    /// Defaults to the caller.
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub expires_in_seconds: Option<u64>,
+}
+
+/// A key as listed to admins, without its hash. `prefix` is the non-secret start of the key,
+/// so admins can tell which key a caller holds.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyView {
+    pub id: String,
+    pub prefix: String,
+    pub name: String,
+    pub owner: String,
+    pub scopes: Vec<String>,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub last_used_at: Option<u64>,
+}
+
+impl ApiKeyView {
+    fn new(tenant: &TenantId, key: &ApiKey) -> Self {
+        let token = ApiKeyToken {
+            tenant_id: tenant.clone(),
+            id: key.id.clone(),
+            secret: String::new(),
+        };
+
+        Self {
+            id: key.id.clone(),
+            prefix: token.to_string(),
+            name: key.name.clone(),
+            owner: key.owner.clone(),
+            scopes: key.scopes.clone(),
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+/// Returned when a key is created or rotated; the only time its secret is shown. It is sent
+/// with `Cache-Control: no-store`, so neither caches nor the idempotency layer keep it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IssuedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyView,
+}
+
+impl IssuedApiKey {
+    fn new(token: &ApiKeyToken, key: &ApiKey) -> Self {
+        Self {
+            key: token.to_string(),
+            api_key: ApiKeyView::new(&token.tenant_id, key),
+        }
+    }
+}
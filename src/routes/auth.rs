@@ -0,0 +1,1490 @@
+use crate::auth::api_key::verify_secret;
+use crate::auth::lockout::{account_key, ip_key, too_many_attempts, ClientIp};
+use crate::auth::password::{hash_password, verify_password};
+use crate::auth::sessions::{now, RefreshToken};
+use crate::auth::verification::{self, password_reset_email, verification_email};
+use crate::db::OperationResult;
+use crate::models::session::{Session, MAX_RETIRED_HASHES};
+use crate::models::user::User;
+use crate::models::verification::VerificationPurpose;
+use crate::state::Accounts;
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+/// Recorded as the revoker of a session ended because a rotated-out refresh token came back,
+/// which means it was copied.
+pub const REFRESH_TOKEN_REUSE: &str = "refresh-token-reuse";
+
+/// Recorded as the revoker of the sessions ended by a password reset.
+pub const PASSWORD_RESET: &str = "password-reset";
+
+pub const MIN_USERNAME_LENGTH: usize = 3;
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+/// Register, login, the session routes and email verification, which callers reach without an
+/// access token, so this router is merged outside the authentication layer.
+pub fn router(accounts: Accounts) -> Router {
+    Router::new()
+        .route("/auth/register", post(register))
+        .route("/auth/login", post(login))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/logout", post(logout))
+        .route("/user/verify-email", post(verify_email))
+        .route("/user/verify-email/resend", post(resend_verification))
+        .route("/auth/password-reset", post(request_password_reset))
+        .route("/auth/password-reset/confirm", post(confirm_password_reset))
+        .with_state(accounts)
+}
+
+/// Letters, digits, `.`, `_` and `-`, so usernames stay readable and never contain the `#`
+/// that separates the parts of their unique guard's key.
+fn valid_username(username: &str) -> bool {
+    (MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&username.chars().count())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+fn invalid_refresh_token() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({"error": "Invalid refresh token"})),
+    )
+        .into_response()
+}
+
+fn issue_tokens(accounts: &Accounts, user: &User, refresh_token: &RefreshToken) -> Response {
+    match accounts
+        .tokens
+        .issue(user, &accounts.tenant_id, &refresh_token.session_id)
+    {
+        Ok(access_token) => (
+            StatusCode::OK,
+            Json(json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "expires_in": accounts.tokens.access_token_ttl(),
+                "refresh_token": refresh_token.to_string(),
+            })),
+        )
+            .into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// Mails the user a link to verify their current email. Failures are logged rather than
+/// returned, since the user can ask for another link.
+async fn send_verification(accounts: &Accounts, user: &User) {
+    let verifications = accounts.verifications.for_tenant(&accounts.tenant_id);
+
+    let token = match verification::issue(
+        verifications.as_ref(),
+        user,
+        VerificationPurpose::EmailVerification,
+        accounts.email_verification_ttl,
+    )
+    .await
+    {
+        OperationResult::Success(Some(token)) => token,
+        _ => {
+            tracing::error!("Failed to store email verification for user {}", user.id);
+            return;
+        }
+    };
+
+    let email = verification_email(user, &token.link(&accounts.email_verification_url));
+    if let Err(err) = accounts.mailer.send(&email).await {
+        tracing::error!(
+            "Failed to send verification email to user {}: {}",
+            user.id,
+            err
+        );
+    }
+}
+
+/// Mails the user a password reset link, unless the account has already been sent as many as
+/// the limiter allows. Like [`send_verification`], it only logs failures.
+async fn send_password_reset(accounts: &Accounts, user: &User) {
+    let key = format!(
+        "{}#{}#{}",
+        PASSWORD_RESET,
+        accounts.tenant_id.as_str(),
+        user.id
+    );
+
+    match accounts.reset_limiter.hit(&key).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            tracing::warn!("Password reset rate limit reached for user {}", user.id);
+            return;
+        }
+        Err(err) => {
+            tracing::error!("Failed to check password reset rate limit: {}", err);
+            return;
+        }
+    }
+
+    let verifications = accounts.verifications.for_tenant(&accounts.tenant_id);
+    let token = match verification::issue(
+        verifications.as_ref(),
+        user,
+        VerificationPurpose::PasswordReset,
+        accounts.password_reset_ttl,
+    )
+    .await
+    {
+        OperationResult::Success(Some(token)) => token,
+        _ => {
+            tracing::error!("Failed to store password reset for user {}", user.id);
+            return;
+        }
+    };
+
+    let email = password_reset_email(user, &token.link(&accounts.password_reset_url));
+    if let Err(err) = accounts.mailer.send(&email).await {
+        tracing::error!(
+            "Failed to send password reset email to user {}: {}",
+            user.id,
+            err
+        );
+    }
+}
+
+async fn end_session(accounts: &Accounts, session_id: &str, ended_by: String) -> Response {
+    let sessions = accounts.sessions.for_tenant(&accounts.tenant_id);
+
+    if let Some(check) = accounts.tokens.sessions() {
+        check.forget(&accounts.tenant_id, session_id);
+    }
+
+    match sessions.soft_delete(session_id.to_string(), ended_by).await {
+        OperationResult::Success(_) | OperationResult::ItemNotFound => {
+            StatusCode::NO_CONTENT.into_response()
+        }
+        err => err.into_response(),
+    }
+}
+
+pub async fn register(
+    State(accounts): State<Accounts>,
+    Json(body): Json<RegisterRequest>,
+) -> Response {
+    let email = body.email.trim().to_lowercase();
+    if !email.contains('@') {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Invalid email"})),
+        )
+            .into_response();
+    }
+
+    let username = body.username.trim().to_string();
+    if !valid_username(&username) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Invalid username"})),
+        )
+            .into_response();
+    }
+
+    let violations = accounts.policy.violations(&body.password);
+    if !violations.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Password does not meet the policy",
+                "violations": violations,
+            })),
+        )
+            .into_response();
+    }
+
+    let password_hash = match hash_password(&body.password) {
+        Ok(hash) => hash,
+        Err(err) => return OperationResult::<User>::InternalError(err).into_response(),
+    };
+    let user = User {
+        id: Uuid::new_v4().to_string(),
+        email,
+        username,
+        created_at: now().to_string(),
+        email_verified: false,
+        password_hash: Some(password_hash),
+        admin: false,
+        deleted_at: None,
+        deleted_by: None,
+    };
+    let db = accounts.users.for_tenant(&accounts.tenant_id);
+
+    match db.create_user(user.clone()).await {
+        OperationResult::Success(_) => {
+            send_verification(&accounts, &user).await;
+
+            (
+                StatusCode::CREATED,
+                Json(json!({
+                    "id": user.id,
+                    "email": user.email,
+                    "username": user.username,
+                    "email_verified": false,
+                })),
+            )
+                .into_response()
+        }
+        err => err.into_response(),
+    }
+}
+
+/// Unknown emails, users without a password and wrong passwords all get the same answer, after
+/// the same amount of hashing work.
+/// Repeated failures lock out both the client IP and the account for a while; see
+/// [`crate::auth::lockout`].
+pub async fn login(
+    State(accounts): State<Accounts>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<LoginRequest>,
+) -> Response {
+    let db = accounts.users.for_tenant(&accounts.tenant_id);
+    let email = body.email.trim().to_lowercase();
+    let account_key = account_key(&accounts.tenant_id, &email);
+    let keys: Vec<String> = ip
+        .as_deref()
+        .map(ip_key)
+        .into_iter()
+        .chain([account_key.clone()])
+        .collect();
+
+    if let Some(retry_after) = accounts.lockout.retry_after(&keys).await {
+        return too_many_attempts(retry_after);
+    }
+
+    let user = match db.find_by_email(email).await {
+        OperationResult::Success(user) => user,
+        OperationResult::ItemNotFound => None,
+        err => return err.into_response(),
+    };
+
+    let password_hash = user.as_ref().and_then(|user| user.password_hash.as_deref());
+    let valid = verify_password(&body.password, password_hash);
+    let user = match user {
+        Some(user) if valid => user,
+        _ => {
+            tracing::info!(
+                target: "security",
+                event = "auth_failure",
+                key = %account_key,
+                "Failed login attempt"
+            );
+            for key in &keys {
+                accounts.lockout.record_failure(key).await;
+            }
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": "Invalid email or password"})),
+            )
+                .into_response();
+        }
+    };
+
+    accounts.lockout.clear(&account_key).await;
+
+    let refresh_token = RefreshToken::new_session();
+    let created_at = now();
+    let session = Session {
+        id: refresh_token.session_id.clone(),
+        user_id: user.id.clone(),
+        refresh_hash: refresh_token.hash(),
+        retired_hashes: Vec::new(),
+        created_at,
+        ttl: created_at + accounts.tokens.refresh_token_ttl(),
+        deleted_at: None,
+        deleted_by: None,
+    };
+
+    match accounts
+        .sessions
+        .for_tenant(&accounts.tenant_id)
+        .create(session)
+        .await
+    {
+        OperationResult::Success(_) => issue_tokens(&accounts, &user, &refresh_token),
+        err => err.into_response(),
+    }
+}
+
+/// Trades a refresh token for a new access token and a new refresh token. The old refresh
+/// token is retired; presenting it again revokes the whole session, since either the client
+/// or whoever copied it holds a newer one.
+pub async fn refresh(
+    State(accounts): State<Accounts>,
+    Json(body): Json<RefreshRequest>,
+) -> Response {
+    let token = match RefreshToken::parse(&body.refresh_token) {
+        Some(token) => token,
+        None => return invalid_refresh_token(),
+    };
+    let sessions = accounts.sessions.for_tenant(&accounts.tenant_id);
+
+    let session = match sessions.get_item(token.session_id.clone()).await {
+        OperationResult::Success(Some(session)) => session,
+        OperationResult::Success(None) | OperationResult::ItemNotFound => {
+            return invalid_refresh_token()
+        }
+        err => return err.into_response(),
+    };
+
+    if !verify_secret(&token.secret, &session.refresh_hash) {
+        if session
+            .retired_hashes
+            .iter()
+            .any(|retired| verify_secret(&token.secret, retired))
+        {
+            tracing::warn!(
+                "Refresh token reused on session {}, revoking it",
+                session.id
+            );
+            end_session(&accounts, &session.id, REFRESH_TOKEN_REUSE.to_string()).await;
+
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": "Refresh token reuse detected"})),
+            )
+                .into_response();
+        }
+
+        return invalid_refresh_token();
+    }
+
+    if session.is_expired(now()) {
+        return invalid_refresh_token();
+    }
+
+    let user = match accounts
+        .users
+        .for_tenant(&accounts.tenant_id)
+        .get_item(session.user_id.clone())
+        .await
+    {
+        OperationResult::Success(Some(user)) => user,
+        OperationResult::Success(None) | OperationResult::ItemNotFound => {
+            return invalid_refresh_token()
+        }
+        err => return err.into_response(),
+    };
+
+    let next = RefreshToken::for_session(&session.id);
+    let mut retired_hashes = session.retired_hashes;
+    retired_hashes.push(session.refresh_hash.clone());
+    if retired_hashes.len() > MAX_RETIRED_HASHES {
+        retired_hashes.drain(..retired_hashes.len() - MAX_RETIRED_HASHES);
+    }
+
+    match sessions
+        .rotate_refresh_hash(
+            session.id,
+            session.refresh_hash,
+            next.hash(),
+            retired_hashes,
+        )
+        .await
+    {
+        OperationResult::Success(_) => issue_tokens(&accounts, &user, &next),
+        // Another refresh with the same token, or a logout, got there first.
+        OperationResult::ItemNotFound => invalid_refresh_token(),
+        err => err.into_response(),
+    }
+}
+
+/// Revokes the session of the given refresh token, which also cuts off the access tokens
+/// issued for it. Logging out of a session that is already gone succeeds.
+pub async fn logout(
+    State(accounts): State<Accounts>,
+    Json(body): Json<RefreshRequest>,
+) -> Response {
+    let token = match RefreshToken::parse(&body.refresh_token) {
+        Some(token) => token,
+        None => return invalid_refresh_token(),
+    };
+
+    let session = match accounts
+        .sessions
+        .for_tenant(&accounts.tenant_id)
+        .get_item(token.session_id.clone())
+        .await
+    {
+        OperationResult::Success(Some(session)) => session,
+        OperationResult::Success(None) | OperationResult::ItemNotFound => {
+            return StatusCode::NO_CONTENT.into_response()
+        }
+        err => return err.into_response(),
+    };
+
+    let known = std::iter::once(&session.refresh_hash)
+        .chain(&session.retired_hashes)
+        .any(|hash| verify_secret(&token.secret, hash));
+    if !known {
+        return invalid_refresh_token();
+    }
+
+    end_session(&accounts, &session.id, session.user_id.clone()).await
+}
+
+/// Confirms the email a verification token was mailed to. Tokens are single use.
+pub async fn verify_email(
+    State(accounts): State<Accounts>,
+    Json(body): Json<VerifyEmailRequest>,
+) -> Response {
+    let invalid = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Invalid or expired verification token"})),
+        )
+            .into_response()
+    };
+    let verifications = accounts.verifications.for_tenant(&accounts.tenant_id);
+
+    let verification = match verification::redeem(
+        verifications.as_ref(),
+        &body.token,
+        VerificationPurpose::EmailVerification,
+    )
+    .await
+    {
+        OperationResult::Success(Some(verification)) => verification,
+        OperationResult::Success(None) | OperationResult::ItemNotFound => return invalid(),
+        err => return err.into_response(),
+    };
+
+    match accounts
+        .users
+        .for_tenant(&accounts.tenant_id)
+        .mark_email_verified(verification.user_id, verification.email)
+        .await
+    {
+        OperationResult::Success(_) => (
+            StatusCode::OK,
+            Json(json!({"message": "Email was successfully verified"})),
+        )
+            .into_response(),
+        // The user was deleted or changed their email since the token was sent.
+        OperationResult::ItemNotFound => invalid(),
+        err => err.into_response(),
+    }
+}
+
+/// Sends a new verification link to an unverified user. Answers the same whether or not the
+/// email belongs to anyone, so it cannot be used to find accounts.
+pub async fn resend_verification(
+    State(accounts): State<Accounts>,
+    Json(body): Json<ResendVerificationRequest>,
+) -> Response {
+    let db = accounts.users.for_tenant(&accounts.tenant_id);
+
+    match db.find_by_email(body.email.trim().to_lowercase()).await {
+        OperationResult::Success(Some(user)) if !user.email_verified => {
+            send_verification(&accounts, &user).await;
+        }
+        OperationResult::Success(_) | OperationResult::ItemNotFound => {}
+        err => return err.into_response(),
+    }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({"message": "If the account exists and is unverified, a verification email was sent"})),
+    )
+        .into_response()
+}
+
+/// Starts a password reset for an account with a password. Answers the same whether or not the
+/// email belongs to such an account, and whether or not it is rate limited.
+pub async fn request_password_reset(
+    State(accounts): State<Accounts>,
+    Json(body): Json<PasswordResetRequest>,
+) -> Response {
+    let db = accounts.users.for_tenant(&accounts.tenant_id);
+
+    match db.find_by_email(body.email.trim().to_lowercase()).await {
+        OperationResult::Success(Some(user)) if user.password_hash.is_some() => {
+            send_password_reset(&accounts, &user).await;
+        }
+        OperationResult::Success(_) | OperationResult::ItemNotFound => {}
+        err => return err.into_response(),
+    }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({"message": "If the account exists, a password reset email was sent"})),
+    )
+        .into_response()
+}
+
+/// Sets a new password with a reset token and ends every session of the account, so whoever
+/// knew the old password is signed out too.
+pub async fn confirm_password_reset(
+    State(accounts): State<Accounts>,
+    Json(body): Json<ConfirmPasswordResetRequest>,
+) -> Response {
+    let invalid = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Invalid or expired reset token"})),
+        )
+            .into_response()
+    };
+
+    // Checked first, so a rejected password does not use up the token.
+    let violations = accounts.policy.violations(&body.password);
+    if !violations.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Password does not meet the policy",
+                "violations": violations,
+            })),
+        )
+            .into_response();
+    }
+
+    let verifications = accounts.verifications.for_tenant(&accounts.tenant_id);
+    let verification = match verification::redeem(
+        verifications.as_ref(),
+        &body.token,
+        VerificationPurpose::PasswordReset,
+    )
+    .await
+    {
+        OperationResult::Success(Some(verification)) => verification,
+        OperationResult::Success(None) | OperationResult::ItemNotFound => return invalid(),
+        err => return err.into_response(),
+    };
+
+    let password_hash = match hash_password(&body.password) {
+        Ok(hash) => hash,
+        Err(err) => return OperationResult::<User>::InternalError(err).into_response(),
+    };
+
+    // A token mailed to an address the user has since replaced is void.
+    match accounts
+        .users
+        .for_tenant(&accounts.tenant_id)
+        .set_password_hash(
+            verification.user_id.clone(),
+            verification.email.clone(),
+            password_hash,
+        )
+        .await
+    {
+        OperationResult::Success(_) => {}
+        OperationResult::ItemNotFound => return invalid(),
+        err => return err.into_response(),
+    }
+
+    match accounts
+        .sessions
+        .for_tenant(&accounts.tenant_id)
+        .revoke_user_sessions(verification.user_id.clone(), PASSWORD_RESET.to_string())
+        .await
+    {
+        OperationResult::Success(revoked) => {
+            if let Some(check) = accounts.tokens.sessions() {
+                for session_id in revoked.unwrap_or_default() {
+                    check.forget(&accounts.tenant_id, &session_id);
+                }
+            }
+        }
+        OperationResult::ItemNotFound => {}
+        err => {
+            tracing::error!(
+                "Failed to end sessions of user {} after reset",
+                verification.user_id
+            );
+            return err.into_response();
+        }
+    }
+
+    (
+        StatusCode::OK,
+        Json(json!({"message": "Password was successfully reset"})),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegisterRequest {
+    pub email: String,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfirmPasswordResetRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginRequest {
+    pub email: String,
+    pub password: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::authorization::ITEMS_READ;
+    use crate::auth::lockout::{Lockout, LockoutPolicy};
+    use crate::auth::password::PasswordPolicy;
+    use crate::auth::tokens::TokenIssuer;
+    use crate::auth::verification::VerificationToken;
+    use crate::config::JwtConfig;
+    use crate::db::DynamoDbOperations;
+    use crate::models::session::SessionDynamoDbRepository;
+    use crate::models::user::UserDynamoDbRepository;
+    use crate::models::verification::Verification;
+    use crate::rate_limit::{InMemoryRateLimitStore, RateLimiter};
+    use crate::routes::test_support::*;
+    use crate::tenant::TenantId;
+    use axum::body::Body;
+    use axum::http::{header::RETRY_AFTER, Method, Request};
+    use mockall::predicate::*;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn accounts(users: MockUserRepository) -> Accounts {
+        accounts_with_sessions(users, MockSessionRepository::new())
+    }
+
+    fn accounts_with_sessions(
+        users: MockUserRepository,
+        sessions: MockSessionRepository,
+    ) -> Accounts {
+        let users: Arc<dyn UserDynamoDbRepository> = Arc::new(users);
+        let sessions: Arc<dyn SessionDynamoDbRepository> = Arc::new(sessions);
+
+        Accounts {
+            users: Arc::new(move |tenant: &TenantId| {
+                assert_eq!(tenant.as_str(), "acme");
+                users.clone()
+            }),
+            sessions: Arc::new(move |tenant: &TenantId| {
+                assert_eq!(tenant.as_str(), "acme");
+                sessions.clone()
+            }),
+            verifications: Arc::new(
+                |_: &TenantId| -> Arc<dyn DynamoDbOperations<Verification>> {
+                    panic!("no verifications expected")
+                },
+            ),
+            mailer: Arc::new(Outbox::default()),
+            tenant_id: TenantId::new("acme").unwrap(),
+            policy: PasswordPolicy::default(),
+            tokens: TokenIssuer::new(JwtConfig {
+                issuer: "https://api.example.com".to_string(),
+                audience: "template-api".to_string(),
+                signing_keys: vec![("k1".to_string(), "a".repeat(32))],
+                access_token_ttl_seconds: 900,
+                refresh_token_ttl_seconds: 86400,
+                session_cache_seconds: 30,
+                scopes: vec![ITEMS_READ.to_string()],
+            }),
+            email_verification_url: "https://app.example.com/verify-email".to_string(),
+            email_verification_ttl: 86400,
+            password_reset_url: "https://app.example.com/reset-password".to_string(),
+            password_reset_ttl: 3600,
+            lockout: Lockout::new(
+                Arc::new(InMemoryRateLimitStore::new()),
+                LockoutPolicy::default(),
+            ),
+            reset_limiter: RateLimiter::new(
+                Arc::new(InMemoryRateLimitStore::new()),
+                3,
+                Duration::from_secs(3600),
+            ),
+        }
+    }
+
+    /// `accounts` that store verifications in `verifications` and mail into the returned outbox.
+    fn accounts_with_mail(
+        users: MockUserRepository,
+        verifications: MockVerificationRepository,
+    ) -> (Accounts, Arc<Outbox>) {
+        let verifications: Arc<dyn DynamoDbOperations<Verification>> = Arc::new(verifications);
+        let outbox = Arc::new(Outbox::default());
+
+        let accounts = Accounts {
+            verifications: Arc::new(move |tenant: &TenantId| {
+                assert_eq!(tenant.as_str(), "acme");
+                verifications.clone()
+            }),
+            mailer: outbox.clone(),
+            ..accounts(users)
+        };
+
+        (accounts, outbox)
+    }
+
+    #[tokio::test]
+    async fn test_register_stores_argon2_hash() {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_create_user()
+            .withf(|user| {
+                user.email == "jane@example.com"
+                    && !user.admin
+                    && !user.email_verified
+                    && user
+                        .password_hash
+                        .as_deref()
+                        .is_some_and(|hash| hash.starts_with("$argon2id$"))
+            })
+            .times(1)
+            .returning(|_| OperationResult::Success(None));
+        let stored = Arc::new(Mutex::new(None));
+        let mut verifications = MockVerificationRepository::new();
+        verifications.expect_create().times(1).returning({
+            let stored = stored.clone();
+            move |verification| {
+                *stored.lock().unwrap() = Some(verification);
+                OperationResult::Success(None)
+            }
+        });
+        let (accounts, outbox) = accounts_with_mail(users, verifications);
+
+        let (status, body) = send(
+            router(accounts),
+            Method::POST,
+            "/auth/register",
+            Some(json!({
+                "email": " Jane@Example.com",
+                "username": "jane",
+                "password": "correct horse battery staple",
+            })),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["email"], json!("jane@example.com"));
+        assert!(body.get("password_hash").is_none());
+
+        let verification = stored.lock().unwrap().clone().unwrap();
+        assert_eq!(verification.purpose, VerificationPurpose::EmailVerification);
+        assert_eq!(verification.email, "jane@example.com");
+        let sent = outbox.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "jane@example.com");
+        let token = sent[0]
+            .body
+            .split("?token=")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(VerificationToken::parse)
+            .unwrap();
+        assert_eq!(token.id, verification.id);
+        assert_eq!(token.hash(), verification.token_hash);
+    }
+
+    #[tokio::test]
+    async fn test_register_enforces_password_policy_and_unique_email() {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_create_user()
+            .times(1)
+            .returning(|_| OperationResult::FieldAlreadyExists("email".to_string()));
+        let app = router(accounts(users));
+
+        let (status, body) = send(
+            app.clone(),
+            Method::POST,
+            "/auth/register",
+            Some(json!({"email": "jane@example.com", "username": "jane", "password": "short"})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["violations"],
+            json!(["Must be at least 12 characters long"])
+        );
+
+        let (status, body) = send(
+            app,
+            Method::POST,
+            "/auth/register",
+            Some(json!({
+                "email": "jane@example.com",
+                "username": "jane",
+                "password": "correct horse battery staple",
+            })),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["field"], json!("email"));
+    }
+
+    #[tokio::test]
+    async fn test_register_rejects_invalid_username() {
+        let mut users = MockUserRepository::new();
+        users.expect_create_user().never();
+        let app = router(accounts(users));
+
+        for username in [
+            "a",
+            "   ",
+            "bad name",
+            "x#y",
+            &"a".repeat(MAX_USERNAME_LENGTH + 1),
+        ] {
+            let (status, body) = send(
+                app.clone(),
+                Method::POST,
+                "/auth/register",
+                Some(json!({
+                    "email": "jane@example.com",
+                    "username": username,
+                    "password": "correct horse battery staple",
+                })),
+            )
+            .await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", username);
+            assert_eq!(body, json!({"error": "Invalid username"}));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_issues_verifiable_access_token() {
+        let hash = hash_password("correct horse battery staple").unwrap();
+        let mut users = MockUserRepository::new();
+        users
+            .expect_find_by_email()
+            .with(eq("jane@example.com".to_string()))
+            .returning(move |_| {
+                OperationResult::Success(Some(User {
+                    password_hash: Some(hash.clone()),
+                    ..user("user-1")
+                }))
+            });
+        let mut sessions = MockSessionRepository::new();
+        sessions
+            .expect_create()
+            .withf(|session| session.user_id == "user-1" && session.retired_hashes.is_empty())
+            .times(1)
+            .returning(|_| OperationResult::Success(None));
+        let accounts = accounts_with_sessions(users, sessions);
+
+        let (status, body) = send(
+            router(accounts.clone()),
+            Method::POST,
+            "/auth/login",
+            Some(json!({"email": "Jane@example.com", "password": "correct horse battery staple"})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["token_type"], json!("Bearer"));
+        let claims = accounts
+            .tokens
+            .verify(body["access_token"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.tenant_id, "acme");
+        assert_eq!(claims.scope, ITEMS_READ);
+        let refresh_token = RefreshToken::parse(body["refresh_token"].as_str().unwrap()).unwrap();
+        assert_eq!(claims.sid, refresh_token.session_id);
+    }
+
+    #[tokio::test]
+    async fn test_login_failures_are_indistinguishable() {
+        let hash = hash_password("correct horse battery staple").unwrap();
+        let mut users = MockUserRepository::new();
+        users
+            .expect_find_by_email()
+            .returning(move |email| match email.as_str() {
+                "jane@example.com" => OperationResult::Success(Some(User {
+                    password_hash: Some(hash.clone()),
+                    ..user("user-1")
+                })),
+                "cognito@example.com" => OperationResult::Success(Some(user("user-2"))),
+                _ => OperationResult::ItemNotFound,
+            });
+        let app = router(accounts(users));
+
+        for email in [
+            "jane@example.com",
+            "cognito@example.com",
+            "nobody@example.com",
+        ] {
+            let (status, body) = send(
+                app.clone(),
+                Method::POST,
+                "/auth/login",
+                Some(json!({"email": email, "password": "wrong password"})),
+            )
+            .await;
+
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(body, json!({"error": "Invalid email or password"}));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_locks_out_account_after_repeated_failures() {
+        let hash = hash_password("correct horse battery staple").unwrap();
+        let mut users = MockUserRepository::new();
+        users.expect_find_by_email().times(2).returning(move |_| {
+            OperationResult::Success(Some(User {
+                password_hash: Some(hash.clone()),
+                ..user("user-1")
+            }))
+        });
+        let app = router(Accounts {
+            lockout: Lockout::new(
+                Arc::new(InMemoryRateLimitStore::new()),
+                LockoutPolicy {
+                    threshold: 2,
+                    ..LockoutPolicy::default()
+                },
+            ),
+            ..accounts(users)
+        });
+
+        for _ in 0..2 {
+            let (status, _) = send(
+                app.clone(),
+                Method::POST,
+                "/auth/login",
+                Some(json!({"email": "jane@example.com", "password": "wrong password"})),
+            )
+            .await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/auth/login")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        json!({"email": "Jane@example.com", "password": "correct horse battery staple"})
+                            .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "60");
+    }
+
+    fn session(token: &RefreshToken, retired: &[&RefreshToken]) -> Session {
+        Session {
+            id: token.session_id.clone(),
+            user_id: "user-1".to_string(),
+            refresh_hash: token.hash(),
+            retired_hashes: retired.iter().map(|token| token.hash()).collect(),
+            created_at: 1700000000,
+            ttl: u64::MAX,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    fn users_with(id: &'static str) -> MockUserRepository {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_get_item()
+            .with(eq(id.to_string()))
+            .returning(|id| OperationResult::Success(Some(user(&id))));
+        users
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rotates_refresh_token() {
+        let retired = RefreshToken::new_session();
+        let current = RefreshToken::for_session(&retired.session_id);
+        let stored = session(&current, &[&retired]);
+        let (current_hash, retired_hash) = (current.hash(), retired.hash());
+        let mut sessions = MockSessionRepository::new();
+        sessions
+            .expect_get_item()
+            .returning(move |_| OperationResult::Success(Some(stored.clone())));
+        sessions
+            .expect_rotate_refresh_hash()
+            .withf(move |_, old, new, retired_hashes| {
+                *old == current_hash
+                    && *new != current_hash
+                    && *retired_hashes == vec![retired_hash.clone(), current_hash.clone()]
+            })
+            .times(1)
+            .returning(|_, _, _, _| OperationResult::Success(None));
+        let accounts = accounts_with_sessions(users_with("user-1"), sessions);
+
+        let (status, body) = send(
+            router(accounts.clone()),
+            Method::POST,
+            "/auth/refresh",
+            Some(json!({"refresh_token": current.to_string()})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let next = RefreshToken::parse(body["refresh_token"].as_str().unwrap()).unwrap();
+        assert_eq!(next.session_id, current.session_id);
+        assert_ne!(next, current);
+        let claims = accounts
+            .tokens
+            .verify(body["access_token"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(claims.sid, current.session_id);
+    }
+
+    #[tokio::test]
+    async fn test_reused_refresh_token_revokes_session() {
+        let retired = RefreshToken::new_session();
+        let current = RefreshToken::for_session(&retired.session_id);
+        let stored = session(&current, &[&retired]);
+        let mut sessions = MockSessionRepository::new();
+        sessions
+            .expect_get_item()
+            .returning(move |_| OperationResult::Success(Some(stored.clone())));
+        sessions.expect_rotate_refresh_hash().never();
+        sessions
+            .expect_soft_delete()
+            .with(
+                eq(current.session_id.clone()),
+                eq(REFRESH_TOKEN_REUSE.to_string()),
+            )
+            .times(1)
+            .returning(|_, _| OperationResult::Success(None));
+
+        let (status, body) = send(
+            router(accounts_with_sessions(MockUserRepository::new(), sessions)),
+            Method::POST,
+            "/auth/refresh",
+            Some(json!({"refresh_token": retired.to_string()})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, json!({"error": "Refresh token reuse detected"}));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_refresh_token_is_rejected_without_revoking() {
+        let current = RefreshToken::new_session();
+        let guessed = RefreshToken::for_session(&current.session_id);
+        let stored = session(&current, &[]);
+        let mut sessions = MockSessionRepository::new();
+        sessions
+            .expect_get_item()
+            .returning(move |_| OperationResult::Success(Some(stored.clone())));
+        sessions.expect_soft_delete().never();
+        let app = router(accounts_with_sessions(MockUserRepository::new(), sessions));
+
+        for refresh_token in [guessed.to_string(), "garbage".to_string()] {
+            for uri in ["/auth/refresh", "/auth/logout"] {
+                let (status, body) = send(
+                    app.clone(),
+                    Method::POST,
+                    uri,
+                    Some(json!({"refresh_token": refresh_token})),
+                )
+                .await;
+
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                assert_eq!(body, json!({"error": "Invalid refresh token"}));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_logout_revokes_session() {
+        let current = RefreshToken::new_session();
+        let stored = session(&current, &[]);
+        let mut sessions = MockSessionRepository::new();
+        sessions
+            .expect_get_item()
+            .returning(move |_| OperationResult::Success(Some(stored.clone())));
+        sessions
+            .expect_soft_delete()
+            .with(eq(current.session_id.clone()), eq("user-1".to_string()))
+            .times(1)
+            .returning(|_, _| OperationResult::Success(None));
+
+        let (status, _) = send(
+            router(accounts_with_sessions(MockUserRepository::new(), sessions)),
+            Method::POST,
+            "/auth/logout",
+            Some(json!({"refresh_token": current.to_string()})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    fn verification(token: &VerificationToken) -> Verification {
+        Verification {
+            id: token.id.clone(),
+            user_id: "user-1".to_string(),
+            purpose: VerificationPurpose::EmailVerification,
+            email: "jane@example.com".to_string(),
+            token_hash: token.hash(),
+            created_at: 1700000000,
+            ttl: u64::MAX,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_email_redeems_token_once() {
+        let token = VerificationToken::generate();
+        let stored = verification(&token);
+        let mut verifications = MockVerificationRepository::new();
+        verifications
+            .expect_get_item()
+            .returning(move |_| OperationResult::Success(Some(stored.clone())));
+        let mut redeemed = false;
+        verifications
+            .expect_soft_delete()
+            .with(eq(token.id.clone()), eq("user-1".to_string()))
+            .times(2)
+            .returning(move |_, _| {
+                let first = !redeemed;
+                redeemed = true;
+                if first {
+                    OperationResult::Success(None)
+                } else {
+                    OperationResult::ItemNotFound
+                }
+            });
+        let mut users = MockUserRepository::new();
+        users
+            .expect_mark_email_verified()
+            .with(eq("user-1".to_string()), eq("jane@example.com".to_string()))
+            .times(1)
+            .returning(|_, _| OperationResult::Success(None));
+        let (accounts, _) = accounts_with_mail(users, verifications);
+        let app = router(accounts);
+
+        let (status, _) = send(
+            app.clone(),
+            Method::POST,
+            "/user/verify-email",
+            Some(json!({"token": token.to_string()})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(
+            app,
+            Method::POST,
+            "/user/verify-email",
+            Some(json!({"token": token.to_string()})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({"error": "Invalid or expired verification token"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_email_rejects_tokens_for_a_replaced_email() {
+        let token = VerificationToken::generate();
+        let stored = verification(&token);
+        let mut verifications = MockVerificationRepository::new();
+        verifications
+            .expect_get_item()
+            .returning(move |_| OperationResult::Success(Some(stored.clone())));
+        verifications
+            .expect_soft_delete()
+            .returning(|_, _| OperationResult::Success(None));
+        let mut users = MockUserRepository::new();
+        users
+            .expect_mark_email_verified()
+            .returning(|_, _| OperationResult::ItemNotFound);
+        let (accounts, _) = accounts_with_mail(users, verifications);
+
+        let (status, _) = send(
+            router(accounts),
+            Method::POST,
+            "/user/verify-email",
+            Some(json!({"token": token.to_string()})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_resend_verification_does_not_reveal_accounts() {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_find_by_email()
+            .returning(|email| match email.as_str() {
+                "jane@example.com" => OperationResult::Success(Some(User {
+                    email,
+                    email_verified: false,
+                    ..user("user-1")
+                })),
+                "verified@example.com" => OperationResult::Success(Some(User {
+                    email,
+                    ..user("user-2")
+                })),
+                _ => OperationResult::ItemNotFound,
+            });
+        let mut verifications = MockVerificationRepository::new();
+        verifications
+            .expect_create()
+            .withf(|verification| verification.user_id == "user-1")
+            .times(1)
+            .returning(|_| OperationResult::Success(None));
+        let (accounts, outbox) = accounts_with_mail(users, verifications);
+        let app = router(accounts);
+
+        let mut bodies = Vec::new();
+        for email in [
+            "jane@example.com",
+            "verified@example.com",
+            "nobody@example.com",
+        ] {
+            let (status, body) = send(
+                app.clone(),
+                Method::POST,
+                "/user/verify-email/resend",
+                Some(json!({"email": email})),
+            )
+            .await;
+
+            assert_eq!(status, StatusCode::ACCEPTED);
+            bodies.push(body);
+        }
+
+        assert!(bodies.windows(2).all(|pair| pair[0] == pair[1]));
+        let sent = outbox.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "jane@example.com");
+    }
+
+    fn password_user(email: &str) -> OperationResult<User> {
+        let (id, password_hash) = match email {
+            "jane@example.com" => ("user-1", Some("$argon2id$stored".to_string())),
+            "sso@example.com" => ("user-2", None),
+            _ => return OperationResult::ItemNotFound,
+        };
+
+        OperationResult::Success(Some(User {
+            email: email.to_string(),
+            password_hash,
+            ..user(id)
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_password_reset_request_does_not_reveal_accounts() {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_find_by_email()
+            .returning(|email| password_user(&email));
+        let mut verifications = MockVerificationRepository::new();
+        verifications
+            .expect_create()
+            .withf(|verification| {
+                verification.user_id == "user-1"
+                    && verification.purpose == VerificationPurpose::PasswordReset
+                    && verification.ttl == verification.created_at + 3600
+            })
+            .times(1)
+            .returning(|_| OperationResult::Success(None));
+        let (accounts, outbox) = accounts_with_mail(users, verifications);
+        let app = router(accounts);
+
+        let mut bodies = Vec::new();
+        for email in ["jane@example.com", "sso@example.com", "nobody@example.com"] {
+            let (status, body) = send(
+                app.clone(),
+                Method::POST,
+                "/auth/password-reset",
+                Some(json!({"email": email})),
+            )
+            .await;
+
+            assert_eq!(status, StatusCode::ACCEPTED);
+            bodies.push(body);
+        }
+
+        assert!(bodies.windows(2).all(|pair| pair[0] == pair[1]));
+        let sent = outbox.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "jane@example.com");
+        assert!(sent[0]
+            .body
+            .contains("https://app.example.com/reset-password?token=vt."));
+    }
+
+    #[tokio::test]
+    async fn test_password_reset_requests_are_rate_limited_per_account() {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_find_by_email()
+            .returning(|email| password_user(&email));
+        let mut verifications = MockVerificationRepository::new();
+        verifications
+            .expect_create()
+            .times(1)
+            .returning(|_| OperationResult::Success(None));
+        let (accounts, outbox) = accounts_with_mail(users, verifications);
+        let app = router(Accounts {
+            reset_limiter: RateLimiter::new(
+                Arc::new(InMemoryRateLimitStore::new()),
+                1,
+                Duration::from_secs(3600),
+            ),
+            ..accounts
+        });
+
+        for _ in 0..3 {
+            let (status, _) = send(
+                app.clone(),
+                Method::POST,
+                "/auth/password-reset",
+                Some(json!({"email": "jane@example.com"})),
+            )
+            .await;
+
+            assert_eq!(status, StatusCode::ACCEPTED);
+        }
+
+        assert_eq!(outbox.sent.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_password_reset_sets_hash_and_ends_sessions() {
+        let token = VerificationToken::generate();
+        let stored = Verification {
+            purpose: VerificationPurpose::PasswordReset,
+            ..verification(&token)
+        };
+        let mut verifications = MockVerificationRepository::new();
+        verifications
+            .expect_get_item()
+            .returning(move |_| OperationResult::Success(Some(stored.clone())));
+        verifications
+            .expect_soft_delete()
+            .times(1)
+            .returning(|_, _| OperationResult::Success(None));
+        let mut users = MockUserRepository::new();
+        users
+            .expect_set_password_hash()
+            .withf(|id, email, hash| {
+                id == "user-1"
+                    && email == "jane@example.com"
+                    && crate::auth::password::verify_password("a brand new passphrase", Some(hash))
+            })
+            .times(1)
+            .returning(|_, _, _| OperationResult::Success(None));
+        let mut sessions = MockSessionRepository::new();
+        sessions
+            .expect_revoke_user_sessions()
+            .with(eq("user-1".to_string()), eq(PASSWORD_RESET.to_string()))
+            .times(1)
+            .returning(|_, _| OperationResult::Success(Some(vec!["s1".to_string()])));
+        let (accounts, _) = accounts_with_mail(users, verifications);
+        let sessions: Arc<dyn SessionDynamoDbRepository> = Arc::new(sessions);
+        let accounts = Accounts {
+            sessions: Arc::new(move |_: &TenantId| sessions.clone()),
+            ..accounts
+        };
+
+        let (status, _) = send(
+            router(accounts),
+            Method::POST,
+            "/auth/password-reset/confirm",
+            Some(json!({"token": token.to_string(), "password": "a brand new passphrase"})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_password_reset_rejects_weak_password_before_using_token() {
+        let mut verifications = MockVerificationRepository::new();
+        verifications.expect_get_item().never();
+        verifications.expect_soft_delete().never();
+        let (accounts, _) = accounts_with_mail(MockUserRepository::new(), verifications);
+
+        let (status, body) = send(
+            router(accounts),
+            Method::POST,
+            "/auth/password-reset/confirm",
+            Some(json!({
+                "token": VerificationToken::generate().to_string(),
+                "password": "short",
+            })),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["violations"],
+            json!(["Must be at least 12 characters long"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_email_verification_token_cannot_reset_password() {
+        let token = VerificationToken::generate();
+        let stored = verification(&token);
+        let mut verifications = MockVerificationRepository::new();
+        verifications
+            .expect_get_item()
+            .returning(move |_| OperationResult::Success(Some(stored.clone())));
+        verifications.expect_soft_delete().never();
+        let (accounts, _) = accounts_with_mail(MockUserRepository::new(), verifications);
+
+        let (status, body) = send(
+            router(accounts),
+            Method::POST,
+            "/auth/password-reset/confirm",
+            Some(json!({"token": token.to_string(), "password": "a brand new passphrase"})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, json!({"error": "Invalid or expired reset token"}));
+    }
+}
@@ -1,25 +1,44 @@
-use crate::db::{DynamoDbOperations, DynamoDbRepository, OperationResult};
+use crate::auth::principal::AuthUser;
+use crate::db::{OperationResult, ScanFilter};
 use crate::models::item::{CreateItem, Item};
+use crate::state::Repositories;
+use crate::tenant::TenantId;
+use axum::extract::State;
 use axum::response::{IntoResponse, Response};
-use axum::Extension;
 use axum::{extract::Path, Json};
 use reqwest::StatusCode;
 use serde_json::json;
+use tracing::warn;
 use uuid::Uuid;
 
-pub async fn get(Extension(db): Extension<DynamoDbRepository<Item>>) -> Response {
-    match db.scan().await {
-        OperationResult::Success(data) => {
-            (StatusCode::OK, Json(json!({"items": data}))).into_response()
+pub async fn get(State(repositories): State<Repositories>, tenant: TenantId) -> Response {
+    let db = repositories.items.for_tenant(&tenant);
+
+    match db.scan_report(ScanFilter::Active).await {
+        OperationResult::Success(report) => {
+            let report = report.unwrap_or_default();
+            let mut body = json!({"items": report.items});
+
+            if !report.skipped.is_empty() {
+                body["meta"] = json!({
+                    "skipped_count": report.skipped_count,
+                    "skipped": report.skipped,
+                });
+            }
+
+            (StatusCode::OK, Json(body)).into_response()
         }
         err => err.into_response(),
     }
 }
 
 pub async fn get_by_id(
-    Extension(db): Extension<DynamoDbRepository<Item>>,
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
     Path(id): Path<String>,
 ) -> Response {
+    let db = repositories.items.for_tenant(&tenant);
+
     match db.get_item(id).await {
         OperationResult::Success(item) => {
             (StatusCode::OK, Json(json!({"item": item}))).into_response()
@@ -28,14 +47,51 @@ pub async fn get_by_id(
     }
 }
 
+/// Gives back a slot counted for `owner` whose item was not created. Failures leave the count one too high, which only ever errs towards the quota.
+async fn release_item_count(repositories: &Repositories, tenant: &TenantId, owner: String) {
+    let users = repositories.users.for_tenant(tenant);
+
+    match users.adjust_item_count(owner.clone(), -1, None).await {
+        OperationResult::Success(_) => {}
+        OperationResult::InternalError(err) => {
+            warn!(owner = %owner, error = %err, "Failed to decrement item count")
+        }
+        // Items created before counting began leave nothing to decrement.
+        _ => warn!(owner = %owner, "Item count was already zero"),
+    }
+}
+
 pub async fn create(
-    Extension(db): Extension<DynamoDbRepository<Item>>,
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
+    AuthUser(principal): AuthUser,
     Json(create_item): Json<CreateItem>,
 ) -> Response {
+    let db = repositories.items.for_tenant(&tenant);
+    let users = repositories.users.for_tenant(&tenant);
+    let owner = principal.subject;
+
+    // Counted before the write, so concurrent creates cannot overshoot the quota together.
+    match users
+        .adjust_item_count(owner.clone(), 1, repositories.item_quota)
+        .await
+    {
+        OperationResult::Success(_) => {}
+        OperationResult::CounterOutOfBounds => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(json!({ "error": "Item quota exceeded" })),
+            )
+                .into_response()
+        }
+        err => return err.into_response(),
+    }
+
     let item = Item {
         id: Uuid::new_v4().to_string(),
         name: create_item.name,
         age: create_item.age,
+        created_by: Some(owner.clone()),
         deleted_at: None,
         deleted_by: None,
     };
@@ -50,15 +106,21 @@ pub async fn create(
             })),
         )
             .into_response(),
-        err => err.into_response(),
+        err => {
+            release_item_count(&repositories, &tenant, owner).await;
+            err.into_response()
+        }
     }
 }
 
 pub async fn update(
-    Extension(db): Extension<DynamoDbRepository<Item>>,
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
     Path(id): Path<String>,
-    Json(item): Json<Item>,
+    Json(mut item): Json<Item>,
 ) -> Response {
+    let db = repositories.items.for_tenant(&tenant);
+
     if id != item.id {
         return (
             StatusCode::BAD_REQUEST,
@@ -67,6 +129,13 @@ pub async fn update(
             .into_response();
     }
 
+    // The creator is kept from the stored item rather than taken from the request body.
+    item.created_by = match db.get_item(id).await {
+        OperationResult::Success(Some(existing)) => existing.created_by,
+        OperationResult::Success(None) => None,
+        err => return err.into_response(),
+    };
+
     match db.update(item).await {
         OperationResult::Success(_) => (
             StatusCode::OK,
@@ -80,10 +149,26 @@ pub async fn update(
 }
 
 pub async fn delete(
-    Extension(db): Extension<DynamoDbRepository<Item>>,
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
     Path(id): Path<String>,
+    AuthUser(principal): AuthUser,
 ) -> Response {
-    match db.soft_delete(id, "todo!".to_string()).await {
+    let db = repositories.items.for_tenant(&tenant);
+
+    let owner = match db.get_item(id.clone()).await {
+        OperationResult::Success(existing) => existing.and_then(|item| item.created_by),
+        err => return err.into_response(),
+    };
+
+    let deleted = match owner {
+        Some(owner) => {
+            soft_delete_counted(&repositories, &tenant, id, principal.subject, owner).await
+        }
+        None => db.soft_delete(id, principal.subject).await,
+    };
+
+    match deleted {
         OperationResult::Success(_) => (
             StatusCode::NO_CONTENT,
             Json(json!({"message": "Item was successfully removed"})),
@@ -92,3 +177,33 @@ pub async fn delete(
         err => err.into_response(),
     }
 }
+
+/// Soft deletes the item and takes it off `owner`'s item count in one transaction, so the
+/// count cannot drift from the items it counts.
+async fn soft_delete_counted(
+    repositories: &Repositories,
+    tenant: &TenantId,
+    id: String,
+    deleted_by: String,
+    owner: String,
+) -> OperationResult<Item> {
+    let db = repositories.items.for_tenant(tenant);
+    let users = repositories.users.for_tenant(tenant);
+
+    let count_update = match users.item_count_step(owner.clone(), -1) {
+        Ok(step) => step,
+        Err(err) => return OperationResult::InternalError(err),
+    };
+
+    match db
+        .soft_delete_counted(id.clone(), deleted_by.clone(), count_update)
+        .await
+    {
+        // Items created before counting began leave nothing to decrement.
+        OperationResult::CounterOutOfBounds => {
+            warn!(owner = %owner, "Item count was already zero");
+            db.soft_delete(id, deleted_by).await
+        }
+        result => result,
+    }
+}
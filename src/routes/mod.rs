@@ -1,3 +1,1076 @@
+pub mod api_key;
+pub mod auth;
 pub mod foo;
 pub mod parameters;
+#[cfg(test)]
+pub(crate) mod test_support;
 pub mod user;
+
+use axum::{
+    extract::FromRef,
+    middleware::from_fn_with_state,
+    routing::{delete, get, patch, post, MethodRouter},
+    Router,
+};
+
+use crate::auth::authorization::{
+    require_admin, require_scope, require_verified_email, RequiredScope, ITEMS_READ, ITEMS_WRITE,
+};
+use crate::state::Repositories;
+
+/// Builds the API routes over any state the repositories can be taken from. Authentication is
+/// left to the caller, which layers its middleware on top of the returned router; authorization
+/// guards are applied here, per route.
+pub fn router<S>(state: S) -> Router
+where
+    S: Clone + Send + Sync + 'static,
+    Repositories: FromRef<S>,
+{
+    let read_items = from_fn_with_state(RequiredScope(ITEMS_READ), require_scope);
+    let write_scope = from_fn_with_state(RequiredScope(ITEMS_WRITE), require_scope);
+    let repositories = Repositories::from_ref(&state);
+    let api_keys = repositories.api_keys.is_some();
+    let verified = repositories
+        .require_verified_email
+        .then(|| from_fn_with_state(repositories.clone(), require_verified_email));
+    let admin = from_fn_with_state(repositories, require_admin);
+
+    // The scope is checked first, so callers without it never cost a user lookup.
+    let write_items = |writes: MethodRouter<S>| {
+        let writes = match verified.clone() {
+            Some(verified) => writes.route_layer(verified),
+            None => writes,
+        };
+        writes.route_layer(write_scope.clone())
+    };
+
+    let router = Router::new()
+        .route("/parameters", get(parameters::handler))
+        .route(
+            "/foo",
+            get(foo::get)
+                .route_layer(read_items.clone())
+                .merge(write_items(post(foo::create))),
+        )
+        .route(
+            "/foo/:id",
+            get(foo::get_by_id)
+                .route_layer(read_items)
+                .merge(write_items(post(foo::update).delete(foo::delete))),
+        )
+        .route("/user", get(user::get).route_layer(admin.clone()))
+        .route("/user/:id", delete(user::delete).route_layer(admin.clone()))
+        .route(
+            "/user/:id/admin-status",
+            patch(user::patch_admin_status).route_layer(admin.clone()),
+        );
+
+    let router = if api_keys {
+        router
+            .route(
+                "/api-keys",
+                get(api_key::get)
+                    .post(api_key::create)
+                    .route_layer(admin.clone()),
+            )
+            .route(
+                "/api-keys/:id",
+                delete(api_key::revoke).route_layer(admin.clone()),
+            )
+            .route(
+                "/api-keys/:id/rotate",
+                post(api_key::rotate).route_layer(admin),
+            )
+    } else {
+        router
+    };
+
+    router.with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::{
+        send, user, MockApiKeyRepository, MockItemRepository, MockUserRepository,
+    };
+    use super::*;
+    use crate::auth::api_key::ApiKeyToken;
+    use crate::auth::chain::Authenticator;
+    use crate::auth::principal::Principal;
+    use crate::auth::roles::ADMIN_ROLE;
+    use crate::auth::secret_auth::{SecretAuth, SECRET_ACTOR};
+    use crate::config::AuthMethod;
+    use crate::db::{OperationResult, ScanFilter, ScanReport, SkippedRecord};
+    use crate::models::api_key::{ApiKey, ApiKeyDynamoDbRepository};
+    use crate::models::item::{Item, ItemDynamoDbRepository};
+    use crate::models::user::{User, UserDynamoDbRepository};
+    use crate::tenant::TenantId;
+    use aws_sdk_dynamodb::types::TransactWriteItem;
+    use axum::body::Body;
+    use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+    use axum::http::{Method, Request, StatusCode};
+    use axum::Extension;
+    use mockall::predicate::*;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    fn item(id: &str) -> Item {
+        Item {
+            id: id.to_string(),
+            name: "name".to_string(),
+            age: 30,
+            created_by: Some("user123".to_string()),
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    fn admin(id: &str) -> User {
+        User {
+            admin: true,
+            ..user(id)
+        }
+    }
+
+    fn caller() -> Principal {
+        Principal {
+            scopes: vec![ITEMS_READ.to_string(), ITEMS_WRITE.to_string()],
+            tenant_id: TenantId::new("acme"),
+            ..Principal::new("user123", AuthMethod::Cognito)
+        }
+    }
+
+    /// Users repository in which the caller is an admin.
+    fn admin_users() -> MockUserRepository {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_get_item()
+            .with(eq("user123".to_string()))
+            .returning(|id| OperationResult::Success(Some(admin(&id))));
+        users
+    }
+
+    fn app(items: MockItemRepository, users: MockUserRepository) -> Router {
+        app_as(caller(), items, users)
+    }
+
+    /// The router as the auth middleware would leave it for `principal`.
+    fn app_as(
+        principal: Principal,
+        items: MockItemRepository,
+        users: MockUserRepository,
+    ) -> Router {
+        app_with_keys(principal, items, users, MockApiKeyRepository::new())
+    }
+
+    fn app_with_keys(
+        principal: Principal,
+        items: MockItemRepository,
+        users: MockUserRepository,
+        keys: MockApiKeyRepository,
+    ) -> Router {
+        app_with_quota(principal, items, users, keys, None)
+    }
+
+    fn app_with_quota(
+        principal: Principal,
+        items: MockItemRepository,
+        users: MockUserRepository,
+        keys: MockApiKeyRepository,
+        item_quota: Option<i64>,
+    ) -> Router {
+        let items: Arc<dyn ItemDynamoDbRepository> = Arc::new(items);
+        let users: Arc<dyn UserDynamoDbRepository> = Arc::new(users);
+        let keys: Arc<dyn ApiKeyDynamoDbRepository> = Arc::new(keys);
+
+        let app = router(Repositories {
+            items: Arc::new(move |_: &TenantId| items.clone()),
+            users: Arc::new(move |_: &TenantId| users.clone()),
+            api_keys: Some(Arc::new(move |_: &TenantId| keys.clone())),
+            item_quota,
+            require_verified_email: false,
+        });
+        let app = match principal.tenant_id.clone() {
+            Some(tenant_id) => app.layer(Extension(tenant_id)),
+            None => app,
+        };
+
+        app.layer(Extension(principal))
+    }
+
+    #[tokio::test]
+    async fn test_list_items_reports_skipped_records() {
+        let mut items = MockItemRepository::new();
+        items
+            .expect_scan_report()
+            .with(eq(ScanFilter::Active))
+            .returning(|_| {
+                OperationResult::Success(Some(ScanReport {
+                    items: vec![item("1")],
+                    skipped_count: 1,
+                    skipped: vec![SkippedRecord {
+                        key: "2".to_string(),
+                        error: "invalid".to_string(),
+                    }],
+                }))
+            });
+
+        let (status, body) = send(
+            app(items, MockUserRepository::new()),
+            Method::GET,
+            "/foo",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["items"][0]["id"], "1");
+        assert_eq!(body["meta"]["skipped_count"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_missing_item_is_not_found() {
+        let mut items = MockItemRepository::new();
+        items
+            .expect_get_item()
+            .with(eq("missing".to_string()))
+            .returning(|_| OperationResult::ItemNotFound);
+
+        let (status, body) = send(
+            app(items, MockUserRepository::new()),
+            Method::GET,
+            "/foo/missing",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, json!({"error": "Item not found"}));
+    }
+
+    /// Users repository keeping the caller's item count in `count`. Increments must be bounded
+    /// by `quota`, decrements are unbounded.
+    fn counting_users(count: Arc<Mutex<i64>>, quota: Option<i64>) -> MockUserRepository {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_adjust_item_count()
+            .withf(move |id, delta, bound| {
+                id == "user123" && *bound == if *delta > 0 { quota } else { None }
+            })
+            .returning(move |_, delta, bound| {
+                let mut count = count.lock().unwrap();
+                let next = *count + delta;
+                if next < 0 || bound.is_some_and(|quota| delta > 0 && next > quota) {
+                    return OperationResult::CounterOutOfBounds;
+                }
+                *count = next;
+                OperationResult::Success(Some(next))
+            });
+        users
+    }
+
+    #[tokio::test]
+    async fn test_create_item_returns_generated_id() {
+        let mut items = MockItemRepository::new();
+        items
+            .expect_create()
+            .withf(|item| {
+                item.name == "new" && item.age == 5 && item.created_by.as_deref() == Some("user123")
+            })
+            .times(1)
+            .returning(|item| OperationResult::Success(Some(item)));
+        let count = Arc::new(Mutex::new(0));
+
+        let (status, body) = send(
+            app(items, counting_users(count.clone(), None)),
+            Method::POST,
+            "/foo",
+            Some(json!({"name": "new", "age": 5})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(body["item_id"].as_str().is_some_and(|id| !id.is_empty()));
+        assert_eq!(*count.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_conflict_is_reported() {
+        let mut items = MockItemRepository::new();
+        items
+            .expect_create()
+            .returning(|_| OperationResult::ItemAlreadyExists);
+        let count = Arc::new(Mutex::new(0));
+
+        let (status, _) = send(
+            app(items, counting_users(count.clone(), None)),
+            Method::POST,
+            "/foo",
+            Some(json!({"name": "new", "age": 5})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(*count.lock().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_create_beyond_item_quota_is_refused() {
+        let mut items = MockItemRepository::new();
+        items
+            .expect_create()
+            .times(2)
+            .returning(|item| OperationResult::Success(Some(item)));
+        let count = Arc::new(Mutex::new(0));
+        let app = app_with_quota(
+            caller(),
+            items,
+            counting_users(count.clone(), Some(2)),
+            MockApiKeyRepository::new(),
+            Some(2),
+        );
+
+        let mut statuses = Vec::new();
+        for _ in 0..3 {
+            let (status, _) = send(
+                app.clone(),
+                Method::POST,
+                "/foo",
+                Some(json!({"name": "new", "age": 5})),
+            )
+            .await;
+            statuses.push(status);
+        }
+
+        assert_eq!(
+            statuses,
+            vec![
+                StatusCode::CREATED,
+                StatusCode::CREATED,
+                StatusCode::FORBIDDEN
+            ]
+        );
+        assert_eq!(*count.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_update_with_mismatched_id_is_rejected() {
+        let mut items = MockItemRepository::new();
+        items.expect_update().never();
+
+        let (status, _) = send(
+            app(items, MockUserRepository::new()),
+            Method::POST,
+            "/foo/1",
+            Some(json!(item("2"))),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// Users repository handing out a decrement of `user123`'s item count for a transaction.
+    fn item_count_users() -> MockUserRepository {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_item_count_step()
+            .with(eq("user123".to_string()), eq(-1))
+            .returning(|_, _| Ok(TransactWriteItem::builder().build()));
+        users.expect_adjust_item_count().never();
+        users
+    }
+
+    #[tokio::test]
+    async fn test_delete_item_soft_deletes_with_its_count() {
+        let mut items = MockItemRepository::new();
+        items
+            .expect_get_item()
+            .returning(|id| OperationResult::Success(Some(item(&id))));
+        items
+            .expect_soft_delete_counted()
+            .withf(|id, deleted_by, _| id == "1" && deleted_by == "user123")
+            .times(1)
+            .returning(|_, _, _| OperationResult::Success(None));
+        items.expect_soft_delete().never();
+
+        let (status, _) = send(
+            app(items, item_count_users()),
+            Method::DELETE,
+            "/foo/1",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_delete_uncounted_item_still_soft_deletes() {
+        let mut items = MockItemRepository::new();
+        items
+            .expect_get_item()
+            .returning(|id| OperationResult::Success(Some(item(&id))));
+        items
+            .expect_soft_delete_counted()
+            .times(1)
+            .returning(|_, _, _| OperationResult::CounterOutOfBounds);
+        items
+            .expect_soft_delete()
+            .withf(|id, deleted_by| id == "1" && deleted_by == "user123")
+            .times(1)
+            .returning(|_, _| OperationResult::Success(None));
+
+        let (status, _) = send(
+            app(items, item_count_users()),
+            Method::DELETE,
+            "/foo/1",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_update_keeps_stored_creator() {
+        let mut items = MockItemRepository::new();
+        items
+            .expect_get_item()
+            .returning(|id| OperationResult::Success(Some(item(&id))));
+        items
+            .expect_update()
+            .withf(|item| item.created_by.as_deref() == Some("user123"))
+            .times(1)
+            .returning(|item| OperationResult::Success(Some(item)));
+        let body = json!({"id": "1", "name": "renamed", "age": 31, "created_by": "someone-else"});
+
+        let (status, _) = send(
+            app(items, MockUserRepository::new()),
+            Method::POST,
+            "/foo/1",
+            Some(body),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_item_writes_require_write_scope() {
+        let mut items = MockItemRepository::new();
+        items.expect_create().never();
+        let principal = Principal {
+            scopes: vec![ITEMS_READ.to_string()],
+            ..caller()
+        };
+
+        let (status, body) = send(
+            app_as(principal, items, MockUserRepository::new()),
+            Method::POST,
+            "/foo",
+            Some(json!({"name": "new", "age": 5})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, json!({"error": "Missing scope items:write"}));
+    }
+
+    #[tokio::test]
+    async fn test_item_reads_require_read_scope() {
+        let principal = Principal {
+            scopes: Vec::new(),
+            ..caller()
+        };
+
+        let (status, _) = send(
+            app_as(
+                principal,
+                MockItemRepository::new(),
+                MockUserRepository::new(),
+            ),
+            Method::GET,
+            "/foo/1",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_leaves_out_password_hashes() {
+        let mut users = admin_users();
+        users.expect_scan().returning(|| {
+            OperationResult::Success(Some(vec![User {
+                password_hash: Some("$argon2id$hash".to_string()),
+                ..user("1")
+            }]))
+        });
+
+        let (status, body) = send(
+            app(MockItemRepository::new(), users),
+            Method::GET,
+            "/user",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["id"], "1");
+        assert_eq!(body[0]["email"], "user@example.com");
+        assert!(body[0].get("password_hash").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_user_routes_require_admin() {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_get_item()
+            .returning(|id| OperationResult::Success(Some(user(&id))));
+        users.expect_scan().never();
+
+        let (status, body) = send(
+            app(MockItemRepository::new(), users),
+            Method::GET,
+            "/user",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, json!({"error": "Admin role required"}));
+    }
+
+    #[tokio::test]
+    async fn test_caller_without_user_record_is_not_admin() {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_get_item()
+            .returning(|_| OperationResult::ItemNotFound);
+        users.expect_update_admin_status().never();
+
+        let (status, _) = send(
+            app(MockItemRepository::new(), users),
+            Method::PATCH,
+            "/user/user123/admin-status",
+            Some(json!({"admin": true})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_mapped_admin_role_skips_user_record_lookup() {
+        let mut users = MockUserRepository::new();
+        users.expect_get_item().never();
+        users
+            .expect_scan()
+            .returning(|| OperationResult::Success(Some(vec![user("user456")])));
+        let principal = Principal {
+            roles: vec![ADMIN_ROLE.to_string()],
+            ..caller()
+        };
+
+        let (status, _) = send(
+            app_as(principal, MockItemRepository::new(), users),
+            Method::GET,
+            "/user",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_records_acting_user() {
+        let mut users = admin_users();
+        users
+            .expect_soft_delete_user()
+            .with(eq("2".to_string()), eq("user123".to_string()))
+            .times(1)
+            .returning(|id, _| OperationResult::Success(Some(user(&id))));
+
+        let (status, _) = send(
+            app(MockItemRepository::new(), users),
+            Method::DELETE,
+            "/user/2",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_deleting_last_admin_is_refused() {
+        let mut users = admin_users();
+        users
+            .expect_soft_delete_user()
+            .with(eq("user123".to_string()), eq("user123".to_string()))
+            .times(1)
+            .returning(|_, _| OperationResult::CounterOutOfBounds);
+
+        let (status, body) = send(
+            app(MockItemRepository::new(), users),
+            Method::DELETE,
+            "/user/user123",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body, json!({"error": "Cannot remove the last admin"}));
+    }
+
+    #[tokio::test]
+    async fn test_patch_admin_status() {
+        let mut users = admin_users();
+        users
+            .expect_update_admin_status()
+            .with(eq("1".to_string()), eq(true))
+            .times(1)
+            .returning(|id, admin| OperationResult::Success(Some(User { admin, ..user(&id) })));
+
+        let (status, _) = send(
+            app(MockItemRepository::new(), users),
+            Method::PATCH,
+            "/user/1/admin-status",
+            Some(json!({"admin": true})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_demoting_last_admin_is_refused() {
+        let mut users = admin_users();
+        users
+            .expect_update_admin_status()
+            .with(eq("user123".to_string()), eq(false))
+            .times(1)
+            .returning(|_, _| OperationResult::CounterOutOfBounds);
+
+        let (status, body) = send(
+            app(MockItemRepository::new(), users),
+            Method::PATCH,
+            "/user/user123/admin-status",
+            Some(json!({"admin": false})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body, json!({"error": "Cannot remove the last admin"}));
+    }
+
+    #[tokio::test]
+    async fn test_demoting_admin_while_others_remain() {
+        let mut users = admin_users();
+        users
+            .expect_update_admin_status()
+            .with(eq("1".to_string()), eq(false))
+            .times(1)
+            .returning(|id, _| OperationResult::Success(Some(user(&id))));
+
+        let (status, _) = send(
+            app(MockItemRepository::new(), users),
+            Method::PATCH,
+            "/user/1/admin-status",
+            Some(json!({"admin": false})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_repositories_are_scoped_to_request_tenant() {
+        let mut items = MockItemRepository::new();
+        items
+            .expect_get_item()
+            .returning(|id| OperationResult::Success(Some(item(&id))));
+        let items: Arc<dyn ItemDynamoDbRepository> = Arc::new(items);
+        let users: Arc<dyn UserDynamoDbRepository> = Arc::new(MockUserRepository::new());
+        let keys: Arc<dyn ApiKeyDynamoDbRepository> = Arc::new(MockApiKeyRepository::new());
+        let principal = Principal {
+            tenant_id: TenantId::new("globex"),
+            ..caller()
+        };
+
+        let app = router(Repositories {
+            items: Arc::new(move |tenant: &TenantId| {
+                assert_eq!(tenant.as_str(), "globex");
+                items.clone()
+            }),
+            users: Arc::new(move |_: &TenantId| users.clone()),
+            api_keys: Some(Arc::new(move |_: &TenantId| keys.clone())),
+            item_quota: None,
+            require_verified_email: false,
+        })
+        .layer(Extension(TenantId::new("globex").unwrap()))
+        .layer(Extension(principal));
+
+        let (status, _) = send(app, Method::GET, "/foo/1", None).await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_request_without_tenant_is_forbidden() {
+        let app = router(Repositories {
+            items: Arc::new(|_: &TenantId| -> Arc<dyn ItemDynamoDbRepository> {
+                panic!("repository must not be resolved without a tenant")
+            }),
+            users: Arc::new(|_: &TenantId| -> Arc<dyn UserDynamoDbRepository> {
+                panic!("repository must not be resolved without a tenant")
+            }),
+            api_keys: None,
+            item_quota: None,
+            require_verified_email: false,
+        })
+        .layer(Extension(Principal {
+            tenant_id: None,
+            ..caller()
+        }));
+
+        let (status, _) = send(app, Method::GET, "/foo", None).await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_request_is_rejected() {
+        let (status, _) = send(
+            router(Repositories {
+                items: Arc::new(|_: &TenantId| -> Arc<dyn ItemDynamoDbRepository> {
+                    unreachable!()
+                }),
+                users: Arc::new(|_: &TenantId| -> Arc<dyn UserDynamoDbRepository> {
+                    unreachable!()
+                }),
+                api_keys: None,
+                item_quota: None,
+                require_verified_email: false,
+            }),
+            Method::GET,
+            "/foo",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_routes_need_api_key_table() {
+        let app = router(Repositories {
+            items: Arc::new(|_: &TenantId| -> Arc<dyn ItemDynamoDbRepository> { unreachable!() }),
+            users: Arc::new(|_: &TenantId| -> Arc<dyn UserDynamoDbRepository> { unreachable!() }),
+            api_keys: None,
+            item_quota: None,
+            require_verified_email: false,
+        })
+        .layer(Extension(TenantId::new("acme").unwrap()))
+        .layer(Extension(Principal {
+            roles: vec![ADMIN_ROLE.to_string()],
+            ..caller()
+        }));
+
+        let (status, _) = send(app, Method::GET, "/api-keys", None).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    fn api_key(id: &str) -> ApiKey {
+        ApiKey {
+            id: id.to_string(),
+            name: "ci".to_string(),
+            owner: "user123".to_string(),
+            key_hash: "hash".to_string(),
+            scopes: vec![ITEMS_READ.to_string()],
+            created_at: 1700000000,
+            expires_at: None,
+            last_used_at: None,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_api_key_shows_secret_once_and_stores_its_hash() {
+        let mut keys = MockApiKeyRepository::new();
+        keys.expect_create()
+            .withf(|key| key.owner == "user123" && key.expires_at == Some(key.created_at + 3600))
+            .times(1)
+            .returning(|_| OperationResult::Success(None));
+        keys.expect_scan()
+            .returning(|| OperationResult::Success(Some(vec![api_key("k1")])));
+        let app = app_with_keys(caller(), MockItemRepository::new(), admin_users(), keys);
+
+        let (status, body) = send(
+            app.clone(),
+            Method::POST,
+            "/api-keys",
+            Some(json!({"name": "ci", "scopes": [ITEMS_READ], "expires_in_seconds": 3600})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        let token = ApiKeyToken::parse(body["key"].as_str().unwrap()).unwrap();
+        assert_eq!(token.tenant_id.as_str(), "acme");
+        assert_eq!(body["id"], json!(token.id));
+        assert_eq!(body["scopes"], json!([ITEMS_READ]));
+        assert!(body.get("key_hash").is_none());
+
+        let (status, body) = send(app, Method::GET, "/api-keys", None).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["id"], json!("k1"));
+        assert!(body[0].get("key").is_none());
+        assert!(body[0].get("key_hash").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_issued_api_keys_are_marked_no_store() {
+        let mut keys = MockApiKeyRepository::new();
+        keys.expect_create()
+            .returning(|_| OperationResult::Success(None));
+        keys.expect_get_item()
+            .returning(|id| OperationResult::Success(Some(api_key(&id))));
+        keys.expect_replace_hash()
+            .returning(|_, _| OperationResult::Success(None));
+        let app = app_with_keys(caller(), MockItemRepository::new(), admin_users(), keys);
+
+        for (uri, body) in [
+            ("/api-keys", json!({"name": "ci"}).to_string()),
+            ("/api-keys/k1/rotate", String::new()),
+        ] {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+
+            let response = app.clone().oneshot(request).await.unwrap();
+
+            assert!(response.status().is_success());
+            assert_eq!(response.headers()[CACHE_CONTROL], "no-store");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotate_api_key_replaces_hash() {
+        let mut keys = MockApiKeyRepository::new();
+        keys.expect_get_item()
+            .with(eq("k1".to_string()))
+            .returning(|id| OperationResult::Success(Some(api_key(&id))));
+        keys.expect_replace_hash()
+            .withf(|id, hash| id == "k1" && hash != "hash")
+            .times(1)
+            .returning(|_, _| OperationResult::Success(None));
+
+        let (status, body) = send(
+            app_with_keys(caller(), MockItemRepository::new(), admin_users(), keys),
+            Method::POST,
+            "/api-keys/k1/rotate",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let token = ApiKeyToken::parse(body["key"].as_str().unwrap()).unwrap();
+        assert_eq!(token.id, "k1");
+    }
+
+    #[tokio::test]
+    async fn test_revoke_api_key_records_revoker() {
+        let mut keys = MockApiKeyRepository::new();
+        keys.expect_soft_delete()
+            .with(eq("k1".to_string()), eq("user123".to_string()))
+            .times(1)
+            .returning(|_, _| OperationResult::Success(None));
+
+        let (status, _) = send(
+            app_with_keys(caller(), MockItemRepository::new(), admin_users(), keys),
+            Method::DELETE,
+            "/api-keys/k1",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_routes_require_admin() {
+        let mut users = MockUserRepository::new();
+        users
+            .expect_get_item()
+            .returning(|id| OperationResult::Success(Some(user(&id))));
+        let mut keys = MockApiKeyRepository::new();
+        keys.expect_create().never();
+
+        let (status, _) = send(
+            app_with_keys(caller(), MockItemRepository::new(), users, keys),
+            Method::POST,
+            "/api-keys",
+            Some(json!({"name": "ci"})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_secret_with_admin_role_can_issue_the_first_api_key() {
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("Authorization", "Bearer s3cret".parse().unwrap());
+        let principal = SecretAuth::new("s3cret".to_string(), TenantId::new("acme").unwrap())
+            .with_roles(vec![ADMIN_ROLE.to_string()])
+            .authenticate(&headers)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut users = MockUserRepository::new();
+        users.expect_get_item().never();
+        let mut keys = MockApiKeyRepository::new();
+        keys.expect_create()
+            .withf(|key| key.owner == SECRET_ACTOR)
+            .times(1)
+            .returning(|_| OperationResult::Success(None));
+
+        let (status, _) = send(
+            app_with_keys(principal, MockItemRepository::new(), users, keys),
+            Method::POST,
+            "/api-keys",
+            Some(json!({"name": "bootstrap"})),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    fn verified_only(principal: Principal, users: MockUserRepository) -> Router {
+        let users: Arc<dyn UserDynamoDbRepository> = Arc::new(users);
+        let repositories = Repositories {
+            items: Arc::new(|_: &TenantId| -> Arc<dyn ItemDynamoDbRepository> { unreachable!() }),
+            users: Arc::new(move |_: &TenantId| users.clone()),
+            api_keys: None,
+            item_quota: None,
+            require_verified_email: false,
+        };
+
+        Router::new()
+            .route("/verified", get(|| async { StatusCode::NO_CONTENT }))
+            .route_layer(from_fn_with_state(repositories, require_verified_email))
+            .layer(Extension(TenantId::new("acme").unwrap()))
+            .layer(Extension(principal))
+    }
+
+    /// Users repository in which the caller, `user123`, is verified and everyone else is not.
+    fn verification_users() -> MockUserRepository {
+        let mut users = MockUserRepository::new();
+        users.expect_get_item().returning(|id| match id.as_str() {
+            "user123" => OperationResult::Success(Some(user(&id))),
+            _ => OperationResult::Success(Some(User {
+                email_verified: false,
+                ..user(&id)
+            })),
+        });
+        users
+    }
+
+    #[tokio::test]
+    async fn test_require_verified_email() {
+        let (status, _) = send(
+            verified_only(caller(), verification_users()),
+            Method::GET,
+            "/verified",
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, body) = send(
+            verified_only(
+                Principal::new("user-2", AuthMethod::Password),
+                verification_users(),
+            ),
+            Method::GET,
+            "/verified",
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, json!({"error": "Verified email required"}));
+    }
+
+    #[tokio::test]
+    async fn test_verified_email_claim_skips_user_lookup() {
+        let mut users = MockUserRepository::new();
+        users.expect_get_item().never();
+        let principal = Principal {
+            claims: serde_json::from_value(json!({
+                "sub": "user-3",
+                "exp": 1,
+                "iss": "issuer",
+                "iat": 1,
+                "token_use": "id",
+                "email_verified": true,
+            }))
+            .ok(),
+            ..Principal::new("user-3", AuthMethod::Cognito)
+        };
+
+        let (status, _) = send(
+            verified_only(principal, users),
+            Method::GET,
+            "/verified",
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_item_writes_can_require_verified_email() {
+        let mut items = MockItemRepository::new();
+        items
+            .expect_scan_report()
+            .returning(|_| OperationResult::Success(Some(ScanReport::default())));
+        items.expect_create().never();
+        let items: Arc<dyn ItemDynamoDbRepository> = Arc::new(items);
+        let users: Arc<dyn UserDynamoDbRepository> = Arc::new(verification_users());
+        let app = router(Repositories {
+            items: Arc::new(move |_: &TenantId| items.clone()),
+            users: Arc::new(move |_: &TenantId| users.clone()),
+            api_keys: None,
+            item_quota: None,
+            require_verified_email: true,
+        })
+        .layer(Extension(TenantId::new("acme").unwrap()))
+        .layer(Extension(Principal {
+            subject: "user-2".to_string(),
+            ..caller()
+        }));
+
+        let (status, _) = send(app.clone(), Method::GET, "/foo", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(
+            app,
+            Method::POST,
+            "/foo",
+            Some(json!({"name": "new", "age": 5})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, json!({"error": "Verified email required"}));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::db::{DynamoDbOperations, OperationResult, ScanFilter, ScanReport};
+use crate::mailer::{Email, Mailer};
+use crate::models::api_key::{ApiKey, ApiKeyDynamoDbRepository};
+use crate::models::item::{Item, ItemDynamoDbRepository};
+use crate::models::session::{Session, SessionDynamoDbRepository};
+use crate::models::user::{User, UserDynamoDbRepository};
+use crate::models::verification::Verification;
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::TransactWriteItem;
+use axum::body::{to_bytes, Body};
+use axum::http::{Method, Request, StatusCode};
+use axum::Router;
+use mockall::mock;
+use serde_json::Value;
+use std::sync::Mutex;
+use tower::ServiceExt;
+
+mock! {
+    pub ItemRepository {}
+
+    #[async_trait]
+    impl DynamoDbOperations<Item> for ItemRepository {
+        async fn get_item(&self, id: String) -> OperationResult<Item>;
+        async fn create(&self, item: Item) -> OperationResult<Item>;
+        async fn update(&self, item: Item) -> OperationResult<Item>;
+        async fn delete(&self, id: String) -> OperationResult<Item>;
+        async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<Item>;
+        async fn scan(&self) -> OperationResult<Vec<Item>>;
+        async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<Item>>;
+        async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<Item>>;
+        async fn get_deleted_items(&self) -> OperationResult<Vec<Item>>;
+    }
+
+    #[async_trait]
+    impl ItemDynamoDbRepository for ItemRepository {
+        async fn soft_delete_counted(&self, id: String, deleted_by: String, count_update: TransactWriteItem) -> OperationResult<Item>;
+    }
+}
+
+mock! {
+    pub UserRepository {}
+
+    #[async_trait]
+    impl DynamoDbOperations<User> for UserRepository {
+        async fn get_item(&self, id: String) -> OperationResult<User>;
+        async fn create(&self, item: User) -> OperationResult<User>;
+        async fn update(&self, item: User) -> OperationResult<User>;
+        async fn delete(&self, id: String) -> OperationResult<User>;
+        async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<User>;
+        async fn scan(&self) -> OperationResult<Vec<User>>;
+        async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<User>>;
+        async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<User>>;
+        async fn get_deleted_items(&self) -> OperationResult<Vec<User>>;
+    }
+
+    #[async_trait]
+    impl UserDynamoDbRepository for UserRepository {
+        async fn create_user(&self, user: User) -> OperationResult<User>;
+        async fn update_admin_status(&self, id: String, admin: bool) -> OperationResult<User>;
+        async fn soft_delete_user(&self, id: String, deleted_by: String) -> OperationResult<User>;
+        async fn adjust_item_count(&self, id: String, delta: i64, quota: Option<i64>) -> OperationResult<i64>;
+        async fn get_item_count(&self, id: String) -> OperationResult<i64>;
+        fn item_count_step(&self, id: String, delta: i64) -> Result<TransactWriteItem, String>;
+        async fn find_by_email(&self, email: String) -> OperationResult<User>;
+        async fn mark_email_verified(&self, id: String, email: String) -> OperationResult<User>;
+        async fn set_password_hash(&self, id: String, email: String, password_hash: String) -> OperationResult<User>;
+    }
+}
+
+mock! {
+    pub ApiKeyRepository {}
+
+    #[async_trait]
+    impl DynamoDbOperations<ApiKey> for ApiKeyRepository {
+        async fn get_item(&self, id: String) -> OperationResult<ApiKey>;
+        async fn create(&self, item: ApiKey) -> OperationResult<ApiKey>;
+        async fn update(&self, item: ApiKey) -> OperationResult<ApiKey>;
+        async fn delete(&self, id: String) -> OperationResult<ApiKey>;
+        async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<ApiKey>;
+        async fn scan(&self) -> OperationResult<Vec<ApiKey>>;
+        async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<ApiKey>>;
+        async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<ApiKey>>;
+        async fn get_deleted_items(&self) -> OperationResult<Vec<ApiKey>>;
+    }
+
+    #[async_trait]
+    impl ApiKeyDynamoDbRepository for ApiKeyRepository {
+        async fn replace_hash(&self, id: String, key_hash: String) -> OperationResult<ApiKey>;
+        async fn record_use(&self, id: String, used_at: u64) -> OperationResult<ApiKey>;
+    }
+}
+
+mock! {
+    pub SessionRepository {}
+
+    #[async_trait]
+    impl DynamoDbOperations<Session> for SessionRepository {
+        async fn get_item(&self, id: String) -> OperationResult<Session>;
+        async fn create(&self, item: Session) -> OperationResult<Session>;
+        async fn update(&self, item: Session) -> OperationResult<Session>;
+        async fn delete(&self, id: String) -> OperationResult<Session>;
+        async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<Session>;
+        async fn scan(&self) -> OperationResult<Vec<Session>>;
+        async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<Session>>;
+        async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<Session>>;
+        async fn get_deleted_items(&self) -> OperationResult<Vec<Session>>;
+    }
+
+    #[async_trait]
+    impl SessionDynamoDbRepository for SessionRepository {
+        async fn rotate_refresh_hash(
+            &self,
+            id: String,
+            current_hash: String,
+            new_hash: String,
+            retired_hashes: Vec<String>,
+        ) -> OperationResult<Session>;
+        async fn revoke_user_sessions(
+            &self,
+            user_id: String,
+            revoked_by: String,
+        ) -> OperationResult<Vec<String>>;
+    }
+}
+
+mock! {
+    pub VerificationRepository {}
+
+    #[async_trait]
+    impl DynamoDbOperations<Verification> for VerificationRepository {
+        async fn get_item(&self, id: String) -> OperationResult<Verification>;
+        async fn create(&self, item: Verification) -> OperationResult<Verification>;
+        async fn update(&self, item: Verification) -> OperationResult<Verification>;
+        async fn delete(&self, id: String) -> OperationResult<Verification>;
+        async fn soft_delete(&self, id: String, user_id: String) -> OperationResult<Verification>;
+        async fn scan(&self) -> OperationResult<Vec<Verification>>;
+        async fn scan_report(&self, filter: ScanFilter) -> OperationResult<ScanReport<Verification>>;
+        async fn get_deleted_items_by_user(&self, user_id: String) -> OperationResult<Vec<Verification>>;
+        async fn get_deleted_items(&self) -> OperationResult<Vec<Verification>>;
+    }
+}
+
+/// Keeps what it is asked to send, for assertions.
+#[derive(Default)]
+pub(crate) struct Outbox {
+    pub(crate) sent: Mutex<Vec<Email>>,
+}
+
+#[async_trait]
+impl Mailer for Outbox {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+pub(crate) fn user(id: &str) -> User {
+    User {
+        id: id.to_string(),
+        email: "user@example.com".to_string(),
+        username: "user".to_string(),
+        created_at: "2024-01-01T00:00:00Z".to_string(),
+        email_verified: true,
+        password_hash: None,
+        admin: false,
+        deleted_at: None,
+        deleted_by: None,
+    }
+}
+
+pub(crate) async fn send(
+    app: Router,
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let request = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => request
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes).unwrap()
+    };
+
+    (status, body)
+}
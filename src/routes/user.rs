@@ -1,48 +1,103 @@
-use crate::db::{DynamoDbOperations, DynamoDbRepository, OperationResult};
-use crate::models::user::{User, UserDynamoDbRepository};
+use crate::auth::principal::AuthUser;
+use crate::db::OperationResult;
+use crate::models::user::User;
+use crate::state::Repositories;
+use crate::tenant::TenantId;
+use axum::extract::State;
 use axum::response::{IntoResponse, Response};
-use axum::Extension;
 use axum::{extract::Path, Json};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-pub async fn get(Extension(db): Extension<DynamoDbRepository<User>>) -> Response {
+pub async fn get(State(repositories): State<Repositories>, tenant: TenantId) -> Response {
+    let db = repositories.users.for_tenant(&tenant);
+
     match db.scan().await {
-        OperationResult::Success(data) => (StatusCode::OK, Json(json!(data))).into_response(),
+        OperationResult::Success(users) => {
+            let users: Vec<UserView> = users
+                .unwrap_or_default()
+                .iter()
+                .map(UserView::new)
+                .collect();
+
+            (StatusCode::OK, Json(json!(users))).into_response()
+        }
         err => err.into_response(),
     }
 }
 
 pub async fn delete(
-    Extension(db): Extension<DynamoDbRepository<User>>,
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
     Path(id): Path<String>,
+    AuthUser(principal): AuthUser,
 ) -> Response {
-    match db.soft_delete(id, "admin".to_string()).await {
+    let db = repositories.users.for_tenant(&tenant);
+
+    match db.soft_delete_user(id, principal.subject).await {
         OperationResult::Success(_) => (
             StatusCode::NO_CONTENT,
             Json(json!({"message": "Item was successfully removed"})),
         )
             .into_response(),
+        OperationResult::CounterOutOfBounds => last_admin(),
         err => err.into_response(),
     }
 }
 pub async fn patch_admin_status(
-    Extension(db): Extension<DynamoDbRepository<User>>,
+    State(repositories): State<Repositories>,
+    tenant: TenantId,
     Path(id): Path<String>,
     Json(body): Json<UpdateAdminStatusRequest>,
 ) -> Response {
-    match UserDynamoDbRepository::update_admin_status(db, id, body.admin).await {
+    let db = repositories.users.for_tenant(&tenant);
+
+    match db.update_admin_status(id, body.admin).await {
         OperationResult::Success(_) => (
             StatusCode::OK,
             Json(json!({"message": "Admin status was successfully updated"})),
         )
             .into_response(),
+        OperationResult::CounterOutOfBounds => last_admin(),
         err => err.into_response(),
     }
 }
 
+/// The tenant's admin count refused to drop below one.
+fn last_admin() -> Response {
+    (
+        StatusCode::CONFLICT,
+        Json(json!({"error": "Cannot remove the last admin"})),
+    )
+        .into_response()
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateAdminStatusRequest {
     pub admin: bool,
 }
+
+/// A user as listed to admins, without the password hash.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserView {
+    pub id: String,
+    pub email: String,
+    pub username: String,
+    pub created_at: String,
+    pub email_verified: bool,
+    pub admin: bool,
+}
+
+impl UserView {
+    fn new(user: &User) -> Self {
+        Self {
+            id: user.id.clone(),
+            email: user.email.clone(),
+            username: user.username.clone(),
+            created_at: user.created_at.clone(),
+            email_verified: user.email_verified,
+            admin: user.admin,
+        }
+    }
+}
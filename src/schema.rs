@@ -0,0 +1,484 @@
+use aws_sdk_dynamodb::operation::describe_table::DescribeTableError;
+use aws_sdk_dynamodb::types::{
+    AttributeDefinition, BillingMode, GlobalSecondaryIndex, KeySchemaElement, KeyType, Projection,
+    ProjectionType, ScalarAttributeType, TableDescription, TableStatus, TimeToLiveDescription,
+    TimeToLiveSpecification, TimeToLiveStatus,
+};
+use aws_sdk_dynamodb::Client;
+use std::fmt;
+use std::time::Duration;
+
+const ACTIVE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const ACTIVE_POLL_ATTEMPTS: u32 = 120;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttributeType {
+    String,
+    Number,
+    Binary,
+}
+
+impl AttributeType {
+    fn scalar(self) -> ScalarAttributeType {
+        match self {
+            AttributeType::String => ScalarAttributeType::S,
+            AttributeType::Number => ScalarAttributeType::N,
+            AttributeType::Binary => ScalarAttributeType::B,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyAttribute {
+    pub name: &'static str,
+    pub attribute_type: AttributeType,
+}
+
+impl KeyAttribute {
+    pub const fn string(name: &'static str) -> Self {
+        Self {
+            name,
+            attribute_type: AttributeType::String,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexSchema {
+    pub name: &'static str,
+    pub partition_key: KeyAttribute,
+    pub sort_key: Option<KeyAttribute>,
+}
+
+/// Key schema, global secondary indexes (always projecting all attributes), TTL attribute and
+/// billing mode of a table. The table name is deployment configuration and not part of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableSchema {
+    pub partition_key: KeyAttribute,
+    pub sort_key: Option<KeyAttribute>,
+    pub indexes: &'static [IndexSchema],
+    pub ttl_attribute: Option<&'static str>,
+    pub billing_mode: BillingMode,
+}
+
+impl TableSchema {
+    pub const fn keyed_by_id() -> Self {
+        Self {
+            partition_key: KeyAttribute::string("id"),
+            sort_key: None,
+            indexes: &[],
+            ttl_attribute: None,
+            billing_mode: BillingMode::PayPerRequest,
+        }
+    }
+
+    pub const fn with_indexes(mut self, indexes: &'static [IndexSchema]) -> Self {
+        self.indexes = indexes;
+        self
+    }
+
+    pub const fn with_ttl(mut self, attribute: &'static str) -> Self {
+        self.ttl_attribute = Some(attribute);
+        self
+    }
+}
+
+/// Table a model is stored in.
+pub trait TableDefinition {
+    const TABLE_SCHEMA: TableSchema;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BootstrapOutcome {
+    Created,
+    Verified,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SchemaError {
+    Missing,
+    Mismatch(Vec<String>),
+    Service(String),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::Missing => write!(f, "Table does not exist"),
+            SchemaError::Mismatch(differences) => {
+                write!(f, "Table does not match schema: {}", differences.join("; "))
+            }
+            SchemaError::Service(err) => write!(f, "DynamoDB error: {}", err),
+        }
+    }
+}
+
+fn key_schema(
+    partition_key: &KeyAttribute,
+    sort_key: &Option<KeyAttribute>,
+) -> Vec<KeySchemaElement> {
+    let mut keys = vec![(partition_key, KeyType::Hash)];
+    if let Some(sort_key) = sort_key {
+        keys.push((sort_key, KeyType::Range));
+    }
+
+    keys.into_iter()
+        .map(|(key, key_type)| {
+            KeySchemaElement::builder()
+                .attribute_name(key.name)
+                .key_type(key_type)
+                .build()
+                .expect("Key schema element has all required fields")
+        })
+        .collect()
+}
+
+fn attribute_definitions(schema: &TableSchema) -> Vec<AttributeDefinition> {
+    let mut keys = vec![&schema.partition_key];
+    keys.extend(&schema.sort_key);
+    for index in schema.indexes {
+        keys.push(&index.partition_key);
+        keys.extend(&index.sort_key);
+    }
+
+    let mut definitions: Vec<AttributeDefinition> = Vec::new();
+    for key in keys {
+        if definitions
+            .iter()
+            .all(|definition| definition.attribute_name() != key.name)
+        {
+            definitions.push(
+                AttributeDefinition::builder()
+                    .attribute_name(key.name)
+                    .attribute_type(key.attribute_type.scalar())
+                    .build()
+                    .expect("Attribute definition has all required fields"),
+            );
+        }
+    }
+
+    definitions
+}
+
+fn key_differences(
+    target: &str,
+    expected: &[KeySchemaElement],
+    actual: &[KeySchemaElement],
+) -> Option<String> {
+    let describe = |keys: &[KeySchemaElement]| {
+        keys.iter()
+            .map(|key| format!("{} {}", key.attribute_name(), key.key_type().as_str()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    if expected == actual {
+        None
+    } else {
+        Some(format!(
+            "{} keys are [{}], expected [{}]",
+            target,
+            describe(actual),
+            describe(expected)
+        ))
+    }
+}
+
+/// Lists every way `table` deviates from `schema`. Billing mode is only compared when the
+/// endpoint reports it, as DynamoDB Local does not always do so.
+pub fn schema_differences(
+    schema: &TableSchema,
+    table: &TableDescription,
+    ttl: Option<&TimeToLiveDescription>,
+) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    differences.extend(key_differences(
+        "Table",
+        &key_schema(&schema.partition_key, &schema.sort_key),
+        table.key_schema(),
+    ));
+
+    for definition in attribute_definitions(schema) {
+        let actual = table
+            .attribute_definitions()
+            .iter()
+            .find(|actual| actual.attribute_name() == definition.attribute_name());
+
+        match actual {
+            Some(actual) if actual.attribute_type() == definition.attribute_type() => {}
+            Some(actual) => differences.push(format!(
+                "Attribute {} is {}, expected {}",
+                definition.attribute_name(),
+                actual.attribute_type().as_str(),
+                definition.attribute_type().as_str()
+            )),
+            None => differences.push(format!(
+                "Attribute {} is not defined",
+                definition.attribute_name()
+            )),
+        }
+    }
+
+    for index in schema.indexes {
+        match table
+            .global_secondary_indexes()
+            .iter()
+            .find(|actual| actual.index_name() == Some(index.name))
+        {
+            Some(actual) => differences.extend(key_differences(
+                &format!("Index {}", index.name),
+                &key_schema(&index.partition_key, &index.sort_key),
+                actual.key_schema(),
+            )),
+            None => differences.push(format!("Index {} is missing", index.name)),
+        }
+    }
+
+    if let Some(attribute) = schema.ttl_attribute {
+        let enabled = ttl.is_some_and(|ttl| {
+            ttl.attribute_name() == Some(attribute)
+                && matches!(
+                    ttl.time_to_live_status(),
+                    Some(TimeToLiveStatus::Enabled | TimeToLiveStatus::Enabling)
+                )
+        });
+
+        if !enabled {
+            differences.push(format!("TTL is not enabled on {}", attribute));
+        }
+    }
+
+    if let Some(actual) = table
+        .billing_mode_summary()
+        .and_then(|summary| summary.billing_mode())
+    {
+        if *actual != schema.billing_mode {
+            differences.push(format!(
+                "Billing mode is {}, expected {}",
+                actual.as_str(),
+                schema.billing_mode.as_str()
+            ));
+        }
+    }
+
+    differences
+}
+
+async fn describe_table(
+    client: &Client,
+    table_name: &str,
+) -> Result<Option<TableDescription>, SchemaError> {
+    match client.describe_table().table_name(table_name).send().await {
+        Ok(output) => Ok(output.table),
+        Err(err) => match err.into_service_error() {
+            DescribeTableError::ResourceNotFoundException(_) => Ok(None),
+            err => Err(SchemaError::Service(err.to_string())),
+        },
+    }
+}
+
+async fn create_table(
+    client: &Client,
+    table_name: &str,
+    schema: &TableSchema,
+) -> Result<(), SchemaError> {
+    let indexes = schema
+        .indexes
+        .iter()
+        .map(|index| {
+            GlobalSecondaryIndex::builder()
+                .index_name(index.name)
+                .set_key_schema(Some(key_schema(&index.partition_key, &index.sort_key)))
+                .projection(
+                    Projection::builder()
+                        .projection_type(ProjectionType::All)
+                        .build(),
+                )
+                .build()
+                .map_err(|err| SchemaError::Service(err.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    client
+        .create_table()
+        .table_name(table_name)
+        .set_attribute_definitions(Some(attribute_definitions(schema)))
+        .set_key_schema(Some(key_schema(&schema.partition_key, &schema.sort_key)))
+        .set_global_secondary_indexes((!indexes.is_empty()).then_some(indexes))
+        .billing_mode(schema.billing_mode.clone())
+        .send()
+        .await
+        .map_err(|err| SchemaError::Service(err.into_service_error().to_string()))?;
+
+    for _ in 0..ACTIVE_POLL_ATTEMPTS {
+        let status = describe_table(client, table_name)
+            .await?
+            .and_then(|table| table.table_status().cloned());
+
+        if status == Some(TableStatus::Active) {
+            break;
+        }
+
+        tokio::time::sleep(ACTIVE_POLL_INTERVAL).await;
+    }
+
+    if let Some(attribute) = schema.ttl_attribute {
+        let specification = TimeToLiveSpecification::builder()
+            .attribute_name(attribute)
+            .enabled(true)
+            .build()
+            .map_err(|err| SchemaError::Service(err.to_string()))?;
+
+        client
+            .update_time_to_live()
+            .table_name(table_name)
+            .time_to_live_specification(specification)
+            .send()
+            .await
+            .map_err(|err| SchemaError::Service(err.into_service_error().to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Verifies that `table_name` matches `schema`, creating the table first if it does not exist
+/// and `create` is set.
+pub async fn ensure_table(
+    client: &Client,
+    table_name: &str,
+    schema: &TableSchema,
+    create: bool,
+) -> Result<BootstrapOutcome, SchemaError> {
+    let table = match describe_table(client, table_name).await? {
+        Some(table) => table,
+        None if create => {
+            create_table(client, table_name, schema).await?;
+            return Ok(BootstrapOutcome::Created);
+        }
+        None => return Err(SchemaError::Missing),
+    };
+
+    let ttl = match schema.ttl_attribute {
+        Some(_) => {
+            client
+                .describe_time_to_live()
+                .table_name(table_name)
+                .send()
+                .await
+                .map_err(|err| SchemaError::Service(err.into_service_error().to_string()))?
+                .time_to_live_description
+        }
+        None => None,
+    };
+
+    match schema_differences(schema, &table, ttl.as_ref()) {
+        differences if differences.is_empty() => Ok(BootstrapOutcome::Verified),
+        differences => Err(SchemaError::Mismatch(differences)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::types::{BillingModeSummary, GlobalSecondaryIndexDescription};
+
+    const INDEXED: TableSchema = TableSchema::keyed_by_id()
+        .with_indexes(&[IndexSchema {
+            name: "email-index",
+            partition_key: KeyAttribute::string("email"),
+            sort_key: None,
+        }])
+        .with_ttl("ttl");
+
+    fn table(schema: &TableSchema) -> TableDescription {
+        let indexes = schema
+            .indexes
+            .iter()
+            .map(|index| {
+                GlobalSecondaryIndexDescription::builder()
+                    .index_name(index.name)
+                    .set_key_schema(Some(key_schema(&index.partition_key, &index.sort_key)))
+                    .build()
+            })
+            .collect();
+
+        TableDescription::builder()
+            .set_attribute_definitions(Some(attribute_definitions(schema)))
+            .set_key_schema(Some(key_schema(&schema.partition_key, &schema.sort_key)))
+            .set_global_secondary_indexes(Some(indexes))
+            .billing_mode_summary(
+                BillingModeSummary::builder()
+                    .billing_mode(schema.billing_mode.clone())
+                    .build(),
+            )
+            .build()
+    }
+
+    fn ttl(attribute: &str) -> TimeToLiveDescription {
+        TimeToLiveDescription::builder()
+            .attribute_name(attribute)
+            .time_to_live_status(TimeToLiveStatus::Enabled)
+            .build()
+    }
+
+    #[test]
+    fn test_attribute_definitions_include_index_keys_once() {
+        const SCHEMA: TableSchema = TableSchema::keyed_by_id().with_indexes(&[
+            IndexSchema {
+                name: "by-email",
+                partition_key: KeyAttribute::string("email"),
+                sort_key: Some(KeyAttribute::string("id")),
+            },
+            IndexSchema {
+                name: "by-email-only",
+                partition_key: KeyAttribute::string("email"),
+                sort_key: None,
+            },
+        ]);
+
+        let names: Vec<_> = attribute_definitions(&SCHEMA)
+            .iter()
+            .map(|definition| definition.attribute_name().to_string())
+            .collect();
+
+        assert_eq!(names, vec!["id", "email"]);
+    }
+
+    #[test]
+    fn test_matching_table_has_no_differences() {
+        assert!(schema_differences(&INDEXED, &table(&INDEXED), Some(&ttl("ttl"))).is_empty());
+    }
+
+    #[test]
+    fn test_missing_index_and_ttl_are_reported() {
+        let differences = schema_differences(&INDEXED, &table(&TableSchema::keyed_by_id()), None);
+
+        assert_eq!(
+            differences,
+            vec![
+                "Attribute email is not defined",
+                "Index email-index is missing",
+                "TTL is not enabled on ttl",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_key_and_billing_mismatches_are_reported() {
+        let mut actual = TableSchema::keyed_by_id();
+        actual.partition_key = KeyAttribute::string("user_id");
+        actual.billing_mode = BillingMode::Provisioned;
+
+        let differences = schema_differences(&TableSchema::keyed_by_id(), &table(&actual), None);
+
+        assert_eq!(
+            differences,
+            vec![
+                "Table keys are [user_id HASH], expected [id HASH]",
+                "Attribute id is not defined",
+                "Billing mode is PROVISIONED, expected PAY_PER_REQUEST",
+            ]
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::Client;
+use axum::extract::FromRef;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::auth::api_key::ApiKeyAuth;
+use crate::auth::chain::{AuthChain, Authenticator};
+use crate::auth::cognito_auth::Auth;
+use crate::auth::lockout::Lockout;
+use crate::auth::oidc::OidcVerifier;
+use crate::auth::password::PasswordPolicy;
+use crate::auth::secret_auth::SecretAuth;
+use crate::auth::sessions::SessionCheck;
+use crate::auth::signing::HmacAuth;
+use crate::auth::tokens::TokenIssuer;
+use crate::config::{AuthMethod, Config};
+use crate::db::{dynamodb_client, DynamoDbOperations, DynamoDbRepository, TenantRepository};
+use crate::encryption::FieldEncryptor;
+use crate::idempotency::{
+    DynamoDbIdempotencyStore, Idempotency, IdempotencyStore, InMemoryIdempotencyStore,
+};
+use crate::mailer::{mailer_from_config, Mailer};
+use crate::models::api_key::{ApiKey, ApiKeyDynamoDbRepository};
+use crate::models::item::{Item, ItemDynamoDbRepository};
+use crate::models::session::{Session, SessionDynamoDbRepository};
+use crate::models::user::{User, UserDynamoDbRepository};
+use crate::models::verification::Verification;
+use crate::rate_limit::{
+    DynamoDbRateLimitStore, InMemoryRateLimitStore, LockoutStore, NonceStore, RateLimitStore,
+    RateLimiter,
+};
+use crate::tenant::TenantId;
+
+/// AWS configuration from the default credential and region chains, loaded once at start-up
+/// and shared by every client.
+pub async fn load_sdk_config() -> SdkConfig {
+    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+
+    aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .region(region_provider)
+        .load()
+        .await
+}
+
+/// Backends of every entity the routes work with. Handlers extract this part of the state, so
+/// the router runs against any implementation, including mocks.
+#[derive(Clone)]
+pub struct Repositories {
+    pub items: Arc<dyn TenantRepository<dyn ItemDynamoDbRepository>>,
+    pub users: Arc<dyn TenantRepository<dyn UserDynamoDbRepository>>,
+    /// Only set when `API_KEY` is enabled; the key routes are not mounted otherwise.
+    pub api_keys: Option<Arc<dyn TenantRepository<dyn ApiKeyDynamoDbRepository>>>,
+    /// Most active items one caller may have created; unlimited when `None`.
+    pub item_quota: Option<i64>,
+    /// Whether creating, updating and deleting items requires a verified email.
+    pub require_verified_email: bool,
+}
+
+/// What the public register and login routes need. Self-registered users all land in one
+/// configured tenant, since nothing identifies a tenant before they sign in.
+#[derive(Clone)]
+pub struct Accounts {
+    pub users: Arc<dyn TenantRepository<dyn UserDynamoDbRepository>>,
+    pub sessions: Arc<dyn TenantRepository<dyn SessionDynamoDbRepository>>,
+    pub verifications: Arc<dyn TenantRepository<dyn DynamoDbOperations<Verification>>>,
+    pub mailer: Arc<dyn Mailer>,
+    pub tenant_id: TenantId,
+    pub policy: PasswordPolicy,
+    pub tokens: TokenIssuer,
+    /// Page the verification link points at; it receives the token as `?token=`.
+    pub email_verification_url: String,
+    pub email_verification_ttl: u64,
+    /// Page the password reset link points at; it receives the token as `?token=`.
+    pub password_reset_url: String,
+    pub password_reset_ttl: u64,
+    /// Failed logins per client IP and per account.
+    pub lockout: Lockout,
+    /// Bounds how many reset emails one account can be sent.
+    pub reset_limiter: RateLimiter,
+}
+
+/// Everything handlers and middleware share, built once per cold start.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub sdk_config: SdkConfig,
+    pub dynamodb: Client,
+    pub repositories: Repositories,
+    pub auth: AuthChain,
+    /// Set when `HMAC` is among the auth methods; layered in front of `auth`.
+    pub hmac: Option<HmacAuth>,
+    pub accounts: Option<Accounts>,
+    pub idempotency: Idempotency,
+}
+
+impl FromRef<AppState> for Repositories {
+    fn from_ref(state: &AppState) -> Self {
+        state.repositories.clone()
+    }
+}
+
+impl AppState {
+    pub async fn from_config(config: Config) -> Self {
+        let sdk_config = load_sdk_config().await;
+        let dynamodb = dynamodb_client(&sdk_config, config.dynamodb_endpoint.as_deref());
+
+        let encryptor = config.encryption.as_ref().map(|encryption| {
+            Arc::new(
+                FieldEncryptor::from_config(encryption, &sdk_config)
+                    .expect("Failed to load encryption key provider"),
+            )
+        });
+
+        let items = DynamoDbRepository::<Item>::from_client(
+            dynamodb.clone(),
+            config.dynamodb_table_name.clone(),
+        )
+        .with_scan_mode(config.scan_mode);
+
+        let users = DynamoDbRepository::<User>::from_client(
+            dynamodb.clone(),
+            config
+                .dynamodb_user_table_name
+                .clone()
+                .expect("USER_TABLE_NAME must be set"),
+        )
+        .with_scan_mode(config.scan_mode);
+
+        let users: Arc<dyn TenantRepository<dyn UserDynamoDbRepository>> = match encryptor {
+            Some(encryptor) => Arc::new(users.with_encryption(encryptor)),
+            None => Arc::new(users),
+        };
+
+        let api_keys = config
+            .dynamodb_api_key_table_name
+            .clone()
+            .map(|table_name| {
+                Arc::new(DynamoDbRepository::<ApiKey>::from_client(
+                    dynamodb.clone(),
+                    table_name,
+                )) as Arc<dyn TenantRepository<dyn ApiKeyDynamoDbRepository>>
+            });
+
+        let idempotency_store: Arc<dyn IdempotencyStore> = match &config.idempotency_table_name {
+            Some(table_name) => Arc::new(DynamoDbIdempotencyStore::from_client(
+                dynamodb.clone(),
+                table_name.clone(),
+            )),
+            None => Arc::new(InMemoryIdempotencyStore::new()),
+        };
+        let idempotency = Idempotency::new(
+            idempotency_store,
+            Duration::from_secs(config.idempotency_ttl_seconds),
+        );
+
+        // One table backs the rate limits, lockouts and nonces.
+        let (rate_limit_store, lockout_store, nonce_store) = match &config.rate_limit_table_name {
+            Some(table_name) => {
+                let store = Arc::new(DynamoDbRateLimitStore::from_client(
+                    dynamodb.clone(),
+                    table_name.clone(),
+                ));
+                (
+                    store.clone() as Arc<dyn RateLimitStore>,
+                    store.clone() as Arc<dyn LockoutStore>,
+                    store as Arc<dyn NonceStore>,
+                )
+            }
+            // Lockouts and nonces kept in memory reset with every cold start and are not shared
+            // between instances, so outside development they need the table.
+            None if !config.development && (config.jwt.is_some() || config.hmac.is_some()) => {
+                panic!("RATE_LIMIT_TABLE_NAME must be set for PASSWORD or HMAC outside development")
+            }
+            None => {
+                let store = Arc::new(InMemoryRateLimitStore::new());
+                (
+                    store.clone() as Arc<dyn RateLimitStore>,
+                    store.clone() as Arc<dyn LockoutStore>,
+                    store as Arc<dyn NonceStore>,
+                )
+            }
+        };
+        let lockout = Lockout::new(lockout_store, config.lockout_policy);
+
+        let accounts = config.jwt.clone().map(|jwt| {
+            let sessions: Arc<dyn TenantRepository<dyn SessionDynamoDbRepository>> =
+                Arc::new(DynamoDbRepository::<Session>::from_client(
+                    dynamodb.clone(),
+                    config.dynamodb_session_table_name.clone().unwrap(),
+                ));
+            let verifications: Arc<dyn TenantRepository<dyn DynamoDbOperations<Verification>>> =
+                Arc::new(DynamoDbRepository::<Verification>::from_client(
+                    dynamodb.clone(),
+                    config.dynamodb_verification_table_name.clone().unwrap(),
+                ));
+            let session_cache = Duration::from_secs(jwt.session_cache_seconds);
+
+            Accounts {
+                users: users.clone(),
+                sessions: sessions.clone(),
+                verifications,
+                mailer: mailer_from_config(&config.mailer, &sdk_config),
+                tenant_id: TenantId::new(config.password_tenant_id.clone().unwrap())
+                    .expect("PASSWORD_TENANT_ID must not be empty or contain '#'"),
+                policy: config.password_policy.clone(),
+                tokens: TokenIssuer::new(jwt)
+                    .with_sessions(SessionCheck::new(sessions, session_cache)),
+                email_verification_url: config.email_verification_url.clone(),
+                email_verification_ttl: config.email_verification_ttl_seconds,
+                password_reset_url: config.password_reset_url.clone(),
+                password_reset_ttl: config.password_reset_ttl_seconds,
+                lockout: lockout.clone(),
+                reset_limiter: RateLimiter::new(
+                    rate_limit_store.clone(),
+                    config.password_reset_limit,
+                    Duration::from_secs(config.password_reset_window_seconds),
+                ),
+            }
+        });
+
+        let auth = config.auth_methods.iter().fold(
+            AuthChain::new().with_lockout(lockout.clone()),
+            |chain, method| {
+                let authenticator: Arc<dyn Authenticator> = match method {
+                    AuthMethod::Cognito => Arc::new(
+                        Auth::new(
+                            config.cognito_region.as_deref().unwrap(),
+                            config.cognito_user_pool_id.as_deref().unwrap(),
+                            config.cognito_client_id.as_deref().unwrap(),
+                        )
+                        .expect("Failed to initialize Cognito key set")
+                        .with_role_mappings(config.role_mappings.clone()),
+                    ),
+                    AuthMethod::Oidc => Arc::new(
+                        OidcVerifier::new(config.oidc.clone().unwrap())
+                            .with_role_mappings(config.role_mappings.clone()),
+                    ),
+                    AuthMethod::ApiKey => Arc::new(ApiKeyAuth::new(
+                        api_keys.clone().expect("API_KEY_TABLE_NAME must be set"),
+                    )),
+                    AuthMethod::Password => Arc::new(accounts.clone().unwrap().tokens),
+                    AuthMethod::Secret => {
+                        let tenant_id = TenantId::new(config.secret_tenant_id.clone().unwrap())
+                            .expect("SECRET_TENANT_ID must not be empty or contain '#'");
+                        Arc::new(
+                            SecretAuth::new(config.secret.clone().unwrap(), tenant_id)
+                                .with_scopes(config.secret_scopes.clone())
+                                .with_roles(config.secret_roles.clone()),
+                        )
+                    }
+                    // Verified by its own layer, `hmac` below.
+                    AuthMethod::Hmac => return chain,
+                    // Taken from the request context by `authenticate_authorizer`.
+                    AuthMethod::Authorizer => return chain,
+                };
+                chain.with(authenticator)
+            },
+        );
+        let hmac = config
+            .hmac
+            .clone()
+            .map(|hmac| HmacAuth::new(hmac, nonce_store).with_lockout(lockout));
+
+        let item_quota = config.item_quota;
+        let require_verified_email = config.require_verified_email;
+
+        Self {
+            config: Arc::new(config),
+            sdk_config,
+            dynamodb,
+            repositories: Repositories {
+                items: Arc::new(items),
+                users,
+                api_keys,
+                item_quota,
+                require_verified_email,
+            },
+            auth,
+            hmac,
+            accounts,
+            idempotency,
+        }
+    }
+}
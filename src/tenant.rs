@@ -0,0 +1,213 @@
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use reqwest::StatusCode;
+use serde_json::json;
+use std::collections::HashMap;
+
+pub const TENANT_PREFIX: &str = "TENANT#";
+pub const TENANT_ATTRIBUTE: &str = "tenant_id";
+
+/// Tenant of the authenticated principal. Only auth middleware inserts it into the request
+/// extensions, so handlers never take it from headers or bodies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TenantId(String);
+
+impl TenantId {
+    /// Rejects empty ids and ids containing `#`, which would make scoped keys ambiguous.
+    pub fn new(tenant_id: impl Into<String>) -> Option<Self> {
+        let tenant_id = tenant_id.into();
+
+        if tenant_id.is_empty() || tenant_id.contains('#') {
+            None
+        } else {
+            Some(Self(tenant_id))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for TenantId
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<TenantId>().cloned().ok_or_else(|| {
+            (
+                StatusCode::FORBIDDEN,
+                Json(json!({ "error": "No tenant for principal" })),
+            )
+                .into_response()
+        })
+    }
+}
+
+pub fn scoped_id(tenant_id: &str, id: &str) -> String {
+    format!("{}{}#{}", TENANT_PREFIX, tenant_id, id)
+}
+
+/// Moves a serialized record into the tenant's key space and stamps the tenant attribute,
+/// replacing whatever the record itself carried.
+pub fn scope_item(tenant_id: &str, item: &mut HashMap<String, AttributeValue>) {
+    if let Some(AttributeValue::S(id)) = item.get("id") {
+        let id = scoped_id(tenant_id, id);
+        item.insert("id".to_string(), AttributeValue::S(id));
+    }
+
+    item.insert(
+        TENANT_ATTRIBUTE.to_string(),
+        AttributeValue::S(tenant_id.to_string()),
+    );
+}
+
+pub fn item_tenant(item: &HashMap<String, AttributeValue>) -> Option<String> {
+    match item.get(TENANT_ATTRIBUTE) {
+        Some(AttributeValue::S(tenant_id)) => Some(tenant_id.clone()),
+        _ => None,
+    }
+}
+
+pub fn owns_item(tenant_id: &str, item: &HashMap<String, AttributeValue>) -> bool {
+    let prefix = scoped_id(tenant_id, "");
+
+    matches!(item.get(TENANT_ATTRIBUTE), Some(AttributeValue::S(owner)) if owner == tenant_id)
+        && matches!(item.get("id"), Some(AttributeValue::S(id)) if id.starts_with(&prefix))
+}
+
+/// Reverses [`scope_item`] for a record already checked with [`owns_item`].
+pub fn unscope_item(tenant_id: &str, item: &mut HashMap<String, AttributeValue>) {
+    let prefix = scoped_id(tenant_id, "");
+
+    if let Some(AttributeValue::S(id)) = item.get("id") {
+        if let Some(id) = id.strip_prefix(&prefix) {
+            item.insert("id".to_string(), AttributeValue::S(id.to_string()));
+        }
+    }
+
+    item.remove(TENANT_ATTRIBUTE);
+}
+
+pub fn scoped_condition(condition: &str) -> String {
+    format!("({}) AND {} = :tenant_id", condition, TENANT_ATTRIBUTE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    fn record(id: &str) -> HashMap<String, AttributeValue> {
+        HashMap::from([
+            ("id".to_string(), AttributeValue::S(id.to_string())),
+            ("name".to_string(), AttributeValue::S("name".to_string())),
+        ])
+    }
+
+    #[test]
+    fn test_tenant_id_validation() {
+        assert!(TenantId::new("acme").is_some());
+        assert!(TenantId::new("").is_none());
+        assert!(TenantId::new("acme#other").is_none());
+    }
+
+    #[test]
+    fn test_same_id_maps_to_different_keys_per_tenant() {
+        assert_eq!(scoped_id("acme", "1"), "TENANT#acme#1");
+        assert_ne!(scoped_id("acme", "1"), scoped_id("globex", "1"));
+    }
+
+    #[test]
+    fn test_scope_item_overrides_tenant_from_body() {
+        let mut item = record("1");
+        item.insert(
+            TENANT_ATTRIBUTE.to_string(),
+            AttributeValue::S("globex".to_string()),
+        );
+
+        scope_item("acme", &mut item);
+
+        assert_eq!(item["id"], AttributeValue::S("TENANT#acme#1".to_string()));
+        assert_eq!(
+            item[TENANT_ATTRIBUTE],
+            AttributeValue::S("acme".to_string())
+        );
+    }
+
+    #[test]
+    fn test_id_from_body_cannot_escape_tenant() {
+        let mut item = record("TENANT#globex#1");
+
+        scope_item("acme", &mut item);
+
+        assert!(owns_item("acme", &item));
+        assert!(!owns_item("globex", &item));
+    }
+
+    #[test]
+    fn test_other_tenants_records_are_not_owned() {
+        let mut item = record("1");
+        scope_item("acme", &mut item);
+
+        assert!(owns_item("acme", &item));
+        assert!(!owns_item("globex", &item));
+        assert!(!owns_item("acme", &record("1")));
+    }
+
+    #[test]
+    fn test_unscope_item_restores_record() {
+        let mut item = record("1");
+        scope_item("acme", &mut item);
+
+        unscope_item("acme", &mut item);
+
+        assert_eq!(item, record("1"));
+    }
+
+    #[test]
+    fn test_scoped_condition() {
+        assert_eq!(
+            scoped_condition("attribute_exists(id)"),
+            "(attribute_exists(id)) AND tenant_id = :tenant_id"
+        );
+    }
+
+    async fn handler(tenant: TenantId) -> String {
+        tenant.as_str().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_extractor_requires_tenant_from_auth() {
+        let app = Router::new().route("/", get(handler));
+
+        let response = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_extractor_reads_tenant_from_extensions() {
+        let app = Router::new()
+            .route("/", get(handler))
+            .layer(Extension(TenantId::new("acme").unwrap()));
+
+        let response = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}